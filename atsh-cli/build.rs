@@ -1,4 +1,3 @@
-use std::path::{Path, PathBuf};
 use std::process::Command;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -24,48 +23,24 @@ fn get_version() -> String {
     format!("{} ({} {})", VERSION, commit_hash, commit_date)
 }
 
-fn patch_file(file: &Path, key: &str, value: &str) {
-    let content = std::fs::read_to_string(file).unwrap();
-    let mut lines: Vec<&str> = content.lines().map(|s| s).collect();
-    for i in 0..lines.len() {
-        if lines[i].contains(key) {
-            lines[i] = value;
-            break;
-        }
-    }
-    std::fs::write(file, lines.join("\n") + "\n").unwrap();
-    println!("success patch consts {key} to {value}");
-}
-
 fn main() {
     println!(
         "{:#?}",
         std::env::vars().collect::<std::collections::HashMap<_, _>>()
     );
 
-    // in cargo publish, do noting
-    if std::env::var("CARGO_PKG_PUBLISHED").is_ok() {
+    // the version shown by `--version`, the source files are never patched
+    let version = if std::env::var("CARGO_PKG_PUBLISHED").is_ok() {
+        // in cargo publish, do noting
         println!("warning: do nothing in cargo publish process");
-        return;
-    }
-
-    // if cross compiling, unsupport get version because of no git
-    if std::env::var("CROSS_RUNNER").is_ok() {
+        VERSION.to_string()
+    } else if std::env::var("CROSS_RUNNER").is_ok() {
+        // if cross compiling, unsupport get version because of no git
         println!("warning: cross compiling, unsupport get version");
-        return;
-    }
-
-    let version = get_version();
+        VERSION.to_string()
+    } else {
+        get_version()
+    };
     println!("build version: {version}");
-
-    let build_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
-    let cli_path = build_dir.join("src").join("main.rs");
-    assert!(cli_path.is_file());
-
-    let version_code = format!("    version = \"{}\",", version);
-    patch_file(&cli_path, "version", &version_code);
-
-    let package_toml = build_dir.join("Cargo.toml");
-    assert!(package_toml.is_file());
-    patch_file(&package_toml, "name", "name = \"atsh\"");
+    println!("cargo:rustc-env=ATSH_BUILD_VERSION={version}");
 }
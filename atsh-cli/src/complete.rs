@@ -0,0 +1,294 @@
+use clap::{Command, Parser, ValueEnum};
+use clap_complete::Shell;
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+
+use atsh_lib::atsh::{get_all, list_dir, Remote};
+
+/// the name of the hidden helper command.
+pub const HELPER: &str = "__complete";
+
+/// the dynamic value to complete.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    /// the record index.
+    Index,
+    /// the index, tag or `all`.
+    Target,
+    /// the path on the remote server, args are the index and the prefix.
+    RemotePath,
+}
+
+/// The hidden helper of the completion scripts, like `atsh __complete index`,
+/// print the candidates of the dynamic value.
+#[derive(Parser, Debug)]
+#[command(name = "atsh __complete")]
+pub struct Helper {
+    /// the profile given on the command line being completed.
+    #[arg(long, env = "ATSH_PROFILE")]
+    pub profile: Option<String>,
+    kind: Kind,
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    args: Vec<String>,
+}
+
+impl Helper {
+    pub fn run(&self) -> Result<()> {
+        for item in candidates(self.kind, &self.args)? {
+            println!("{item}");
+        }
+        Ok(())
+    }
+}
+
+// The positions of the dynamic values are the same in every shell:
+// the value after `-i/--index` is an index, the first positional of
+// `exec/copy-id/revoke-id` (and `keys audit`) is a target, the remote path is
+// the second value after `-p` of `upload` and the first one of `download`.
+// The `--profile` on the command line is passed to the helper.
+
+const BASH: &str = r#"
+_atsh_dynamic() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    local sub="${COMP_WORDS[1]}" index="" at=0 kind="" i profile=()
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "${COMP_WORDS[i]}" in
+            -i|--index) index="${COMP_WORDS[i+1]}" ;;
+            -p|--path) at=$i ;;
+            --profile) profile=(--profile "${COMP_WORDS[i+1]}") ;;
+        esac
+    done
+    local n=$((COMP_CWORD - at))
+    if [[ "$prev" == "-i" || "$prev" == "--index" ]]; then
+        kind="index"
+    elif [[ "$sub" =~ ^(exec|copy-id|revoke-id)$ && $COMP_CWORD -eq 2 ]] || [[ "$sub" == "keys" && $COMP_CWORD -eq 3 ]]; then
+        kind="target"
+    elif [[ $at -gt 0 && -n "$index" ]] && [[ ("$sub" =~ ^(upload|up)$ && $n -eq 2) || ("$sub" =~ ^(download|down|dload)$ && $n -eq 1) ]]; then
+        kind="remote-path"
+    fi
+    if [[ -z "$kind" ]]; then
+        _atsh "$@"
+        return
+    fi
+    local IFS=$'\n'
+    COMPREPLY=($(compgen -W "$(atsh __complete "${profile[@]}" "$kind" "$index" "$cur" 2>/dev/null | cut -f1)" -- "$cur"))
+    if [[ "$kind" == "remote-path" ]]; then
+        compopt -o nospace 2>/dev/null
+    fi
+}
+"#;
+
+const ZSH: &str = r#"
+_atsh_dynamic() {
+    local sub=${words[2]} prev=${words[CURRENT-1]} cur=${words[CURRENT]}
+    local index="" at=0 kind="" i
+    local -a profile
+    for ((i = 2; i < CURRENT; i++)); do
+        case ${words[i]} in
+            (-i|--index) index=${words[i+1]} ;;
+            (-p|--path) at=$i ;;
+            (--profile) profile=(--profile ${words[i+1]}) ;;
+        esac
+    done
+    local n=$((CURRENT - at))
+    if [[ $prev == (-i|--index) ]]; then
+        kind=index
+    elif [[ $sub == (exec|copy-id|revoke-id) && $CURRENT -eq 3 ]] || [[ $sub == keys && $CURRENT -eq 4 ]]; then
+        kind=target
+    elif (( at > 0 )) && [[ -n $index ]] && [[ ( $sub == (upload|up) && $n -eq 2 ) || ( $sub == (download|down|dload) && $n -eq 1 ) ]]; then
+        kind=remote-path
+    fi
+    if [[ -z $kind ]]; then
+        _atsh "$@"
+        return
+    fi
+    local -a items
+    items=(${(f)"$(atsh __complete $profile $kind "$index" "$cur" 2>/dev/null)"})
+    if [[ $kind == remote-path ]]; then
+        compadd -S '' -- $items
+    else
+        items=("${(@)items/$'\t'/:}")
+        _describe $kind items
+    fi
+}
+"#;
+
+const FISH: &str = r#"
+function __atsh_profile
+    # print the `--profile` and its value on the command line
+    set -l cmd (commandline -opc)
+    for i in (seq 2 (count $cmd))
+        if test "$cmd[$i]" = --profile
+            echo --profile
+            echo $cmd[(math $i + 1)]
+        end
+    end
+end
+
+function __atsh_dynamic_kind
+    # print the kind (and index) of the dynamic value, fail if not dynamic
+    set -l cmd (commandline -opc)
+    set -l sub $cmd[2]
+    set -l index
+    set -l at 0
+    for i in (seq 3 (count $cmd))
+        switch $cmd[$i]
+            case -i --index
+                set index $cmd[(math $i + 1)]
+            case -p --path
+                set at $i
+        end
+    end
+    set -l n (math (count $cmd) + 1 - $at)
+    if begin; contains -- "$sub" exec copy-id revoke-id; and test (count $cmd) -eq 2; end
+        or begin; test "$sub" = keys; and test (count $cmd) -eq 3; end
+        echo target
+    else if test $at -gt 0 -a -n "$index"; and begin
+            begin; contains -- "$sub" upload up; and test $n -eq 2; end
+            or begin; contains -- "$sub" download down dload; and test $n -eq 1; end
+        end
+        echo remote-path
+        echo $index
+    else
+        return 1
+    end
+end
+
+complete -c atsh -n "__fish_atsh_using_subcommand edit remove login upload download" -s i -l index -x -a "(atsh __complete (__atsh_profile) index 2>/dev/null)"
+complete -c atsh -n "__atsh_dynamic_kind >/dev/null" -f -a "(atsh __complete (__atsh_profile) (__atsh_dynamic_kind) (commandline -ct) 2>/dev/null)"
+complete -c atsh -n "__atsh_dynamic_kind >/dev/null" -s p -l path -f -a "(atsh __complete (__atsh_profile) (__atsh_dynamic_kind) (commandline -ct) 2>/dev/null)"
+"#;
+
+/// the completion script of the shell, bash/zsh/fish complete the dynamic values.
+pub fn script(shell: Shell, cmd: &mut Command) -> String {
+    let mut buf = vec![];
+    clap_complete::generate(shell, cmd, "atsh", &mut buf);
+    let script = String::from_utf8_lossy(&buf).to_string();
+    // register the dynamic one instead of the generated `_atsh`
+    match shell {
+        Shell::Bash => {
+            script.replace("complete -F _atsh ", "complete -F _atsh_dynamic ") + BASH.trim_start()
+        }
+        Shell::Zsh => {
+            // the `_atsh` file in `$fpath` is autoloaded, so define it before calling
+            let (head, tail) = script
+                .rsplit_once("if [ \"$funcstack[1]\" = \"_atsh\" ]")
+                .unwrap_or((&script, ""));
+            format!(
+                "{head}{}\nif [ \"$funcstack[1]\" = \"_atsh\" ]{}",
+                ZSH.trim(),
+                tail.replace("_atsh \"$@\"", "_atsh_dynamic \"$@\"")
+                    .replace("compdef _atsh ", "compdef _atsh_dynamic ")
+            )
+        }
+        Shell::Fish => script + FISH,
+        _ => script,
+    }
+}
+
+/// the short description of the remote server.
+fn describe(remote: &Remote) -> String {
+    match &remote.name {
+        Some(name) => format!("{name} {remote}"),
+        None => remote.to_string(),
+    }
+}
+
+/// split the path prefix to the directory and the name prefix.
+fn split_path(prefix: &str) -> (&str, &str) {
+    match prefix.rfind('/') {
+        Some(i) => prefix.split_at(i + 1),
+        None => ("", prefix),
+    }
+}
+
+/// the candidates of the dynamic value, one per line like `value\tdescription`.
+fn candidates(kind: Kind, args: &[String]) -> Result<Vec<String>> {
+    match kind {
+        Kind::Index => Ok(get_all()?
+            .iter()
+            .map(|r| format!("{}\t{}", r.index, describe(r)))
+            .collect()),
+        Kind::Target => {
+            let remotes = get_all()?;
+            let mut tags = BTreeMap::new();
+            for tag in remotes.iter().flat_map(|r| r.tags.iter()) {
+                *tags.entry(tag).or_insert(0) += 1;
+            }
+            let mut items = vec![format!("all\tall {} servers", remotes.len())];
+            items.extend(
+                remotes
+                    .iter()
+                    .map(|r| format!("{}\t{}", r.index, describe(r))),
+            );
+            items.extend(
+                tags.iter()
+                    .map(|(tag, n)| format!("{tag}\ttag of {n} servers")),
+            );
+            Ok(items)
+        }
+        Kind::RemotePath => {
+            let index = args
+                .first()
+                .and_then(|i| i.parse().ok())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "the index is required"))?;
+            let (dir, name) = split_path(args.get(1).map(String::as_str).unwrap_or_default());
+            // SFTP starts at the home directory and never expands `~`
+            let path = match dir.strip_prefix("~/").unwrap_or(dir) {
+                "" => ".",
+                path => path,
+            };
+            Ok(list_dir(index, path)?
+                .into_iter()
+                // the hidden entries only if asked
+                .filter(|(n, _)| {
+                    n.starts_with(name) && (name.starts_with('.') || !n.starts_with('.'))
+                })
+                .map(|(n, is_dir)| format!("{dir}{n}{}", if is_dir { "/" } else { "" }))
+                .collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_split_path() {
+        assert_eq!(split_path("/etc/ss"), ("/etc/", "ss"));
+        assert_eq!(split_path("/etc/"), ("/etc/", ""));
+        assert_eq!(split_path("docs"), ("", "docs"));
+        assert_eq!(split_path(""), ("", ""));
+    }
+
+    #[test]
+    fn test_script() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let script = script(shell, &mut crate::Cli::command());
+            assert!(script.contains("atsh __complete"), "{shell}");
+            // the helper is never offered
+            assert_eq!(
+                script.matches(HELPER).count(),
+                script.matches("atsh __complete").count(),
+                "{shell}"
+            );
+        }
+        let bash = script(Shell::Bash, &mut crate::Cli::command());
+        assert!(bash.contains("complete -F _atsh_dynamic "));
+        assert!(!bash.contains("complete -F _atsh "));
+        let zsh = script(Shell::Zsh, &mut crate::Cli::command());
+        assert!(zsh.contains("compdef _atsh_dynamic atsh"));
+        assert!(zsh.find("_atsh_dynamic() {") < zsh.find("_atsh_dynamic \"$@\""));
+    }
+
+    #[test]
+    fn test_helper() {
+        let helper = Helper::parse_from([HELPER, "--profile", "work", "target"]);
+        assert_eq!(helper.profile.as_deref(), Some("work"));
+        assert_eq!(helper.kind, Kind::Target);
+        let helper = Helper::parse_from([HELPER, "remote-path", "1", "--profile"]);
+        assert_eq!(helper.args, vec!["1", "--profile"]);
+    }
+}
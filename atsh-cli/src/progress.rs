@@ -0,0 +1,112 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use atsh_lib::atsh::{Event, Observer};
+
+/// Render the events of the remote operations on stderr,
+/// a spinner until the session is logged in and a bar of the bytes transferred.
+/// The output of the command is written to stdout.
+#[derive(Default)]
+pub struct Progress {
+    /// render the spinner and bars, only on terminal.
+    bars: bool,
+    /// the output of every server follows a header like `==> user@ip:port <==`.
+    headers: bool,
+    /// the bar and the file it belongs to, `None` for the spinner.
+    bar: Mutex<Option<(ProgressBar, Option<PathBuf>)>>,
+    /// the server of the last output.
+    output: Mutex<Option<String>>,
+}
+
+impl Progress {
+    pub fn new(bars: bool, headers: bool) -> Self {
+        Progress {
+            bars,
+            headers,
+            ..Default::default()
+        }
+    }
+
+    fn write_output(&self, remote: &str, data: &str) {
+        let mut last = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        let mut stdout = std::io::stdout().lock();
+        if self.headers && last.as_deref() != Some(remote) {
+            let _ = writeln!(stdout, "==> {remote} <==");
+        }
+        *last = Some(remote.to_string());
+        let _ = stdout.write_all(data.as_bytes());
+        let _ = stdout.flush();
+    }
+
+    fn spinner(message: String) -> ProgressBar {
+        let bar = ProgressBar::new_spinner().with_message(message);
+        bar.enable_steady_tick(Duration::from_millis(100));
+        bar
+    }
+
+    fn bytes(path: &Path, total: u64) -> ProgressBar {
+        let style = ProgressStyle::with_template(
+            "{msg} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} {eta}",
+        )
+        .unwrap_or_else(|_| ProgressStyle::default_bar())
+        .progress_chars("=> ");
+        ProgressBar::new(total)
+            .with_style(style)
+            .with_message(path.display().to_string())
+    }
+}
+
+impl Observer for Progress {
+    fn on_event(&self, event: &Event) {
+        if let Event::Output { remote, data } = event {
+            return self.write_output(remote, data);
+        }
+        if !self.bars {
+            return;
+        }
+        let mut current = self.bar.lock().unwrap_or_else(|e| e.into_inner());
+        match event {
+            Event::Connecting { remote } => {
+                if let Some((bar, _)) = current.take() {
+                    bar.finish_and_clear();
+                }
+                *current = Some((Self::spinner(format!("connecting {remote}")), None));
+            }
+            Event::Handshake { remote } => {
+                if let Some((bar, _)) = current.as_ref() {
+                    bar.set_message(format!("handshake {remote}"));
+                }
+            }
+            Event::Transferred {
+                path, bytes, total, ..
+            } => {
+                if !matches!(current.as_ref(), Some((_, Some(p))) if p == path) {
+                    if let Some((bar, _)) = current.take() {
+                        bar.finish_and_clear();
+                    }
+                    *current = Some((Self::bytes(path, *total), Some(path.clone())));
+                }
+                if let Some((bar, _)) = current.as_ref() {
+                    bar.set_position(*bytes);
+                    if *bytes >= *total {
+                        bar.finish();
+                    }
+                }
+            }
+            // the shell or command output follows, clear the spinner
+            Event::Authenticated { .. } | Event::Finished { .. } | Event::Failed { .. } => {
+                if let Some((bar, path)) = current.take() {
+                    match path {
+                        Some(_) if !bar.is_finished() => bar.abandon(),
+                        Some(_) => {}
+                        None => bar.finish_and_clear(),
+                    }
+                }
+            }
+            Event::KeyInstalled { .. } | Event::KeyRevoked { .. } | Event::Output { .. } => {}
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use clap::Args;
+use std::io::{Error, ErrorKind, IsTerminal, Result};
+
+use atsh_lib::atsh::{read_fd, CONFIG};
+
+/// The sources of the password, the prompt is used if none is given.
+#[derive(Args, Debug, Default)]
+#[group(multiple = false)]
+pub struct PasswordArgs {
+    /// the password, it is visible in the shell history and `ps`, prefer the prompt or other sources.
+    #[arg(short, long)]
+    password: Option<String>,
+    /// read the password from the first line of stdin.
+    #[arg(long, default_value = "false")]
+    password_stdin: bool,
+    /// read the password from the file descriptor, like `--password-fd 3 3<secret.txt`.
+    #[arg(long)]
+    password_fd: Option<u32>,
+    /// read the password from the environment variable, like `--password-env SSH_PASS`.
+    #[arg(long)]
+    password_env: Option<String>,
+}
+
+impl PasswordArgs {
+    /// the password from the given source, `None` if no source is given.
+    pub fn read(&self) -> Result<Option<String>> {
+        if let Some(password) = &self.password {
+            return Ok(Some(password.clone()));
+        }
+        read_source(
+            self.password_stdin,
+            self.password_fd,
+            self.password_env.as_deref(),
+        )
+    }
+
+    /// the password from the given source or the prompt with confirmation.
+    pub fn read_or_prompt(&self, prompt: &str) -> Result<String> {
+        let password = match self.read()? {
+            Some(password) => password,
+            None => prompt_secret(prompt, true)?,
+        };
+        if password.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "the password is empty"));
+        }
+        Ok(password)
+    }
+}
+
+/// read the secret from the first line of stdin, the file descriptor or the environment variable.
+fn read_source(stdin: bool, fd: Option<u32>, env: Option<&str>) -> Result<Option<String>> {
+    if stdin {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        return Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()));
+    }
+    if let Some(fd) = fd {
+        return Ok(Some(read_fd(&fd.to_string())?));
+    }
+    if let Some(name) = env {
+        return std::env::var(name).map(Some).map_err(|_| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("environment variable `{name}` not found"),
+            )
+        });
+    }
+    Ok(None)
+}
+
+/// The sources of the secret option like `--totp`, it is never given as the value
+/// which is visible in the shell history and `ps`.
+macro_rules! secret_args {
+    ($name:ident, $long:literal, $what:literal) => {
+        secret_args!(
+            $name,
+            $long,
+            $what,
+            concat!("prompt the ", $what, ", empty to clear it on `edit`")
+        );
+    };
+    ($name:ident, $long:literal, $what:literal, $prompt:expr) => {
+        #[derive(Args, Debug, Default)]
+        #[group(multiple = false)]
+        pub struct $name {
+            #[arg(
+                                long = $long,
+                                id = $long,
+                                help = $prompt
+                            )]
+            prompt: bool,
+            #[arg(
+                                long = concat!($long, "-stdin"),
+                                id = concat!($long, "-stdin"),
+                                help = concat!("read the ", $what, " from the first line of stdin")
+                            )]
+            stdin: bool,
+            #[arg(
+                                long = concat!($long, "-fd"),
+                                id = concat!($long, "-fd"),
+                                value_name = "FD",
+                                help = concat!("read the ", $what, " from the file descriptor")
+                            )]
+            fd: Option<u32>,
+            #[arg(
+                                long = concat!($long, "-env"),
+                                id = concat!($long, "-env"),
+                                value_name = "VAR",
+                                help = concat!("read the ", $what, " from the environment variable")
+                            )]
+            env: Option<String>,
+        }
+
+        impl $name {
+            /// the secret from the given source, `None` if no source is given.
+            pub fn read(&self) -> Result<Option<String>> {
+                if self.prompt {
+                    return prompt_secret($what, true).map(Some);
+                }
+                read_source(self.stdin, self.fd, self.env.as_deref())
+            }
+        }
+    };
+}
+
+secret_args!(
+    LoginPasswordArgs,
+    "password",
+    "login password",
+    "prompt the login password"
+);
+secret_args!(TotpArgs, "totp", "base32 TOTP seed");
+secret_args!(SudoPasswordArgs, "sudo-password", "sudo password");
+
+/// prompt the secret without echo, type it twice if `confirm`.
+pub fn prompt_secret(prompt: &str, confirm: bool) -> Result<String> {
+    if !std::io::stdin().is_terminal() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("no terminal to prompt `{prompt}`, use the stdin, fd or env source"),
+        ));
+    }
+    let secret = rpassword::prompt_password(format!("{prompt}: "))?;
+    if confirm && rpassword::prompt_password(format!("confirm {prompt}: "))? != secret {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("the {prompt} does not match"),
+        ));
+    }
+    Ok(secret)
+}
+
+/// prompt the `ATSH_KEY` if it is not given by the environment.
+pub fn ensure_key(confirm: bool) -> Result<()> {
+    if CONFIG.get_enc_key().is_ok() || !std::io::stdin().is_terminal() {
+        return Ok(());
+    }
+    Ok(CONFIG.set_enc_key(Some(prompt_secret("ATSH_KEY", confirm)?))?)
+}
@@ -0,0 +1,383 @@
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{self, EnterAlternateScreen};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Arc;
+
+use atsh_lib::atsh::{
+    download, edit, exec, get_all, login, remove, upload, Atsh, Remote, RemoteEdit, Target,
+};
+
+use crate::progress::Progress;
+
+/// the action which needs the input line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Exec,
+    Upload,
+    Download,
+    Edit,
+}
+
+impl Action {
+    fn prompt(&self) -> &'static str {
+        match self {
+            Action::Exec => "exec> ",
+            Action::Upload => "upload (local remote)> ",
+            Action::Download => "download (remote local)> ",
+            Action::Edit => "edit (name|note|tags|user|ip|port|mode|principals=value)> ",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Mode {
+    Normal,
+    Search,
+    Input(Action, String),
+    Delete,
+}
+
+struct App {
+    remotes: Vec<Remote>,
+    query: String,
+    /// the position of `remotes` matched the query, best first.
+    matched: Vec<usize>,
+    state: ListState,
+    mode: Mode,
+    message: String,
+}
+
+/// the text to search of the remote server.
+fn haystack(remote: &Remote) -> String {
+    format!(
+        "{} {} {} {} {}",
+        remote.name.as_deref().unwrap_or_default(),
+        remote.user,
+        remote.ip,
+        remote.tags.join(" "),
+        remote.note.as_deref().unwrap_or_default(),
+    )
+}
+
+/// split the paths of upload/download, like `from to`.
+fn paths(input: &str) -> Result<Vec<String>> {
+    let paths = input
+        .split_whitespace()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+    if paths.len() != 2 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "two paths are required, like `from to`",
+        ));
+    }
+    Ok(paths)
+}
+
+impl App {
+    fn new() -> Result<App> {
+        let mut app = App {
+            remotes: vec![],
+            query: String::new(),
+            matched: vec![],
+            state: ListState::default(),
+            mode: Mode::Normal,
+            message:
+                "/ search  enter login  x exec  u upload  d download  e edit  D delete  q quit"
+                    .to_string(),
+        };
+        app.reload()?;
+        Ok(app)
+    }
+
+    fn reload(&mut self) -> Result<()> {
+        self.remotes = get_all()?;
+        self.search();
+        Ok(())
+    }
+
+    /// fuzzy search the remote servers by the query.
+    fn search(&mut self) {
+        let matcher = SkimMatcherV2::default();
+        let mut scored = self
+            .remotes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, r)| {
+                if self.query.is_empty() {
+                    return Some((i, 0));
+                }
+                matcher
+                    .fuzzy_match(&haystack(r), &self.query)
+                    .map(|score| (i, score))
+            })
+            .collect::<Vec<_>>();
+        // the best first, and keep the index order for the same score
+        scored.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        self.matched = scored.into_iter().map(|(i, _)| i).collect();
+        let selected = match self.state.selected() {
+            _ if self.matched.is_empty() => None,
+            Some(i) => Some(i.min(self.matched.len() - 1)),
+            None => Some(0),
+        };
+        self.state.select(selected);
+    }
+
+    fn selected(&self) -> Option<&Remote> {
+        self.state
+            .selected()
+            .and_then(|i| self.matched.get(i))
+            .map(|&i| &self.remotes[i])
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [top, bottom] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(3)]).areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)]).areas(top);
+
+        let items = self
+            .matched
+            .iter()
+            .map(|&i| {
+                let r = &self.remotes[i];
+                ListItem::new(Line::from(vec![
+                    Span::styled(format!("{:>4} ", r.index), Style::new().fg(Color::DarkGray)),
+                    Span::raw(format!("{:<16} ", r.name.as_deref().unwrap_or_default())),
+                    Span::styled(r.to_string(), Style::new().fg(Color::Cyan)),
+                ]))
+            })
+            .collect::<Vec<_>>();
+        let title = format!(" servers {}/{} ", self.matched.len(), self.remotes.len());
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, left, &mut self.state);
+
+        let details = match self.selected() {
+            Some(r) => {
+                let field = |k: &str, v: String| {
+                    Line::from(vec![
+                        Span::styled(format!("{k:>11}: "), Style::new().fg(Color::Green)),
+                        Span::raw(v),
+                    ])
+                };
+                vec![
+                    field("index", r.index.to_string()),
+                    field("name", r.name.clone().unwrap_or_default()),
+                    field("user", r.user.clone()),
+                    field("ip", r.ip.clone()),
+                    field("port", r.port.to_string()),
+                    field("mode", r.mode.to_string()),
+                    field("authorized", r.authorized.to_string()),
+                    field("tags", r.tags.join(",")),
+                    field("principals", r.principals.clone().unwrap_or_default()),
+                    field("note", r.note.clone().unwrap_or_default()),
+                ]
+            }
+            None => vec![Line::raw("no server matched")],
+        };
+        frame.render_widget(
+            Paragraph::new(details)
+                .block(Block::bordered().title(" details "))
+                .wrap(Wrap { trim: false }),
+            right,
+        );
+
+        let (title, line) = match &self.mode {
+            Mode::Normal => (" help ", self.message.clone()),
+            Mode::Search => (" search ", format!("/{}", self.query)),
+            Mode::Input(action, input) => (" input ", format!("{}{}", action.prompt(), input)),
+            Mode::Delete => (" confirm ", "delete the selected server? (y/n)".to_string()),
+        };
+        frame.render_widget(
+            Paragraph::new(line).block(Block::bordered().title(title)),
+            bottom,
+        );
+    }
+
+    fn move_by(&mut self, step: isize) {
+        if self.matched.is_empty() {
+            return;
+        }
+        let i = self.state.selected().unwrap_or(0) as isize + step;
+        self.state
+            .select(Some(i.clamp(0, self.matched.len() as isize - 1) as usize));
+    }
+
+    /// handle the key, return false to quit.
+    fn on_key(&mut self, terminal: &mut DefaultTerminal, key: KeyEvent) -> Result<bool> {
+        match self.mode.clone() {
+            Mode::Normal => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(false)
+                }
+                KeyCode::Char('/') => self.mode = Mode::Search,
+                KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+                KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+                KeyCode::PageDown => self.move_by(10),
+                KeyCode::PageUp => self.move_by(-10),
+                KeyCode::Enter => {
+                    if let Some(index) = self.selected().map(|r| r.index) {
+                        self.suspend(terminal, || Ok(login(index, false)?))?;
+                    }
+                }
+                KeyCode::Char('x') => self.mode = Mode::Input(Action::Exec, String::new()),
+                KeyCode::Char('u') => self.mode = Mode::Input(Action::Upload, String::new()),
+                KeyCode::Char('d') => self.mode = Mode::Input(Action::Download, String::new()),
+                KeyCode::Char('e') => self.mode = Mode::Input(Action::Edit, String::new()),
+                KeyCode::Char('D') | KeyCode::Delete if self.selected().is_some() => {
+                    self.mode = Mode::Delete
+                }
+                _ => {}
+            },
+            Mode::Search => match key.code {
+                KeyCode::Esc => {
+                    self.query.clear();
+                    self.search();
+                    self.mode = Mode::Normal;
+                }
+                KeyCode::Enter => self.mode = Mode::Normal,
+                KeyCode::Down => self.move_by(1),
+                KeyCode::Up => self.move_by(-1),
+                KeyCode::Backspace => {
+                    self.query.pop();
+                    self.search();
+                }
+                KeyCode::Char(c) => {
+                    self.query.push(c);
+                    self.search();
+                }
+                _ => {}
+            },
+            Mode::Input(action, mut input) => match key.code {
+                KeyCode::Esc => self.mode = Mode::Normal,
+                KeyCode::Enter => {
+                    self.mode = Mode::Normal;
+                    self.run(terminal, action, &input)?;
+                }
+                KeyCode::Backspace => {
+                    input.pop();
+                    self.mode = Mode::Input(action, input);
+                }
+                KeyCode::Char(c) => {
+                    input.push(c);
+                    self.mode = Mode::Input(action, input);
+                }
+                _ => {}
+            },
+            Mode::Delete => {
+                self.mode = Mode::Normal;
+                if key.code == KeyCode::Char('y') {
+                    if let Some(index) = self.selected().map(|r| r.index) {
+                        self.report(
+                            terminal,
+                            remove(&[index])
+                                .map(|_| "deleted".to_string())
+                                .map_err(Error::from),
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// run the action of input on the selected server.
+    fn run(&mut self, terminal: &mut DefaultTerminal, action: Action, input: &str) -> Result<()> {
+        let remote = match self.selected() {
+            Some(r) => r.clone(),
+            None => return Ok(()),
+        };
+        let index = remote.index;
+        match action {
+            Action::Exec => self.suspend(terminal, || {
+                // no observer over the picker, only print the output while suspended
+                Atsh::global().set_observer(Some(Arc::new(Progress::new(false, false))));
+                let outcomes = exec(&Target::Index(index), input, false);
+                Atsh::global().set_observer(None);
+                for o in outcomes? {
+                    match (o.value, o.error) {
+                        (Some(v), _) => println!("exit {}", v.code),
+                        (None, e) => println!("{}", e.unwrap_or_default()),
+                    }
+                }
+                Ok(())
+            }),
+            Action::Upload => match paths(input) {
+                Ok(p) => self.suspend(terminal, || Ok(upload(index, &p)?)),
+                Err(e) => self.report(terminal, Err(e)),
+            },
+            Action::Download => match paths(input) {
+                Ok(p) => self.suspend(terminal, || Ok(download(index, &p)?)),
+                Err(e) => self.report(terminal, Err(e)),
+            },
+            Action::Edit => {
+                let result = input
+                    .parse::<RemoteEdit>()
+                    .and_then(|changes| edit(remote.index, &changes))
+                    .map(|_| "updated".to_string())
+                    .map_err(Error::from);
+                self.report(terminal, result)
+            }
+        }
+    }
+
+    /// show the result in the status line, the logs may break the screen so redraw all.
+    fn report(&mut self, terminal: &mut DefaultTerminal, result: Result<String>) -> Result<()> {
+        self.message = match result {
+            Ok(msg) => msg,
+            Err(e) => format!("error: {e}"),
+        };
+        self.reload()?;
+        terminal.clear()
+    }
+
+    /// leave the screen to run the interactive operation, like login.
+    fn suspend(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        f: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        ratatui::restore();
+        if let Err(e) = f() {
+            eprintln!("error: {e}");
+        }
+        println!("\npress enter to return...");
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        terminal::enable_raw_mode()?;
+        execute!(std::io::stdout(), EnterAlternateScreen)?;
+        self.message = "done".to_string();
+        self.reload()?;
+        terminal.clear()
+    }
+}
+
+/// the full-screen terminal UI to pick the server and operate on it.
+pub fn run() -> Result<()> {
+    let mut app = App::new()?;
+    let mut terminal = ratatui::init();
+    let result = (|| -> Result<()> {
+        loop {
+            terminal.draw(|frame| app.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !app.on_key(&mut terminal, key)? {
+                    return Ok(());
+                }
+            }
+        }
+    })();
+    ratatui::restore();
+    result
+}
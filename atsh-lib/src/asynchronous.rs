@@ -0,0 +1,312 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::debug;
+
+use crate::connection::{ExecOutput, Outcome, Remote, Target};
+use crate::context::Atsh;
+use crate::error::Error;
+
+type Result<T> = std::result::Result<T, Error>;
+
+thread_local! {
+    // the limit of the blocking call on the current thread, see `SSHSession::new`
+    static LIMIT: RefCell<Option<Limit>> = const { RefCell::new(None) };
+}
+
+/// the deadline and cancellation of one blocking call.
+#[derive(Clone)]
+struct Limit {
+    deadline: Option<Instant>,
+    cancel: Cancel,
+}
+
+/// the time left to connect the remote server, `None` means no limit.
+/// it is an error if the call is cancelled or timed out already.
+pub(crate) fn remaining() -> Result<Option<Duration>> {
+    let Some(limit) = LIMIT.with(|l| l.borrow().clone()) else {
+        return Ok(None);
+    };
+    if limit.cancel.is_cancelled() {
+        return Err(Error::Transport("the operation is cancelled".to_string()));
+    }
+    match limit.deadline {
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(left) if !left.is_zero() => Ok(Some(left)),
+            _ => Err(Error::Transport("the operation timed out".to_string())),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The token to cancel the operations of `AsyncAtsh`, clone it to cancel from another task.
+///
+/// The future returns at once when cancelled, the blocking thread stops at the next step:
+/// before the next server, or between the reads of the transfer and the command output,
+/// and the session is closed. The steps done are kept, like the servers removed already.
+#[derive(Clone, Default)]
+pub struct Cancel(Arc<(AtomicBool, Notify)>);
+
+impl Cancel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0 .0.store(true, Ordering::SeqCst);
+        self.0 .1.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0 .0.load(Ordering::SeqCst)
+    }
+
+    /// wait until cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.0 .1.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// The async api of `Atsh` for the GUI and service, the sync api is still used by the cli.
+///
+/// Every call runs on the blocking thread pool of tokio, so a runtime is required.
+/// The calls time out after `AsyncAtsh::DEFAULT_TIMEOUT`, set a longer one for large transfers.
+#[derive(Debug, Clone)]
+pub struct AsyncAtsh {
+    atsh: Atsh,
+    timeout: Option<Duration>,
+}
+
+impl AsyncAtsh {
+    /// the timeout of every call if not set by `timeout`.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(600);
+
+    pub fn new(atsh: Atsh) -> Self {
+        AsyncAtsh {
+            atsh,
+            timeout: Some(Self::DEFAULT_TIMEOUT),
+        }
+    }
+
+    /// the timeout of every call, include connecting, authentication and transfers.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn atsh(&self) -> &Atsh {
+        &self.atsh
+    }
+
+    /// run `f` on the blocking thread in the scope of the `Atsh`.
+    async fn run<T, F>(&self, cancel: &Cancel, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Atsh) -> Result<T> + Send + 'static,
+    {
+        let atsh = self.atsh.clone();
+        let limit = Limit {
+            deadline: self.timeout.map(|t| Instant::now() + t),
+            cancel: cancel.clone(),
+        };
+        let task = tokio::task::spawn_blocking(move || {
+            LIMIT.with(|l| *l.borrow_mut() = Some(limit));
+            let result = remaining().and_then(|_| f(&atsh));
+            LIMIT.with(|l| *l.borrow_mut() = None);
+            result
+        });
+        let task = async {
+            task.await
+                .map_err(|e| Error::other(format!("the blocking task failed: {e}")))?
+        };
+        tokio::select! {
+            result = with_timeout(self.timeout, task) => result,
+            _ = cancel.cancelled() => {
+                debug!("the operation is cancelled");
+                Err(Error::Transport("the operation is cancelled".to_string()))
+            }
+        }
+    }
+
+    /// connect the remote server and check the login, nothing is changed.
+    pub async fn connect(&self, remote: &Remote, cancel: &Cancel) -> Result<()> {
+        let remote = remote.clone();
+        self.run(cancel, move |atsh| atsh.verify_remote(&remote))
+            .await
+    }
+
+    /// install the atsh key (or trust the CA) to the remote server by its index.
+    pub async fn authenticate(&self, index: usize, cancel: &Cancel) -> Result<()> {
+        self.run(cancel, move |atsh| atsh.authenticate(index)).await
+    }
+
+    /// revoke the atsh key and delete the records.
+    pub async fn remove(&self, index: &[usize], cancel: &Cancel) -> Result<usize> {
+        let index = index.to_vec();
+        self.run(cancel, move |atsh| atsh.remove(&index)).await
+    }
+
+    /// grant the public key (file or string) to login the remote servers.
+    pub async fn copy_id(
+        &self,
+        target: &Target,
+        key: &str,
+        cancel: &Cancel,
+    ) -> Result<Vec<Outcome<bool>>> {
+        let (target, key) = (target.clone(), key.to_string());
+        self.run(cancel, move |atsh| atsh.copy_id(&target, &key))
+            .await
+    }
+
+    /// revoke the public key (file or string) granted by `copy_id`.
+    pub async fn revoke_id(
+        &self,
+        target: &Target,
+        key: &str,
+        cancel: &Cancel,
+    ) -> Result<Vec<Outcome<bool>>> {
+        let (target, key) = (target.clone(), key.to_string());
+        self.run(cancel, move |atsh| atsh.revoke_id(&target, &key))
+            .await
+    }
+
+    /// execute the command on the remote servers, see `Atsh::exec`.
+    pub async fn exec(
+        &self,
+        target: &Target,
+        command: &str,
+        sudo: bool,
+        cancel: &Cancel,
+    ) -> Result<Vec<Outcome<ExecOutput>>> {
+        let (target, command) = (target.clone(), command.to_string());
+        self.run(cancel, move |atsh| atsh.exec(&target, &command, sudo))
+            .await
+    }
+
+    pub async fn upload(
+        &self,
+        index: usize,
+        local: impl Into<PathBuf>,
+        remote: &str,
+        cancel: &Cancel,
+    ) -> Result<()> {
+        let path = [local.into().display().to_string(), remote.to_string()];
+        self.run(cancel, move |atsh| atsh.upload(index, &path))
+            .await
+    }
+
+    pub async fn download(
+        &self,
+        index: usize,
+        remote: &str,
+        local: impl Into<PathBuf>,
+        cancel: &Cancel,
+    ) -> Result<()> {
+        let path = [remote.to_string(), local.into().display().to_string()];
+        self.run(cancel, move |atsh| atsh.download(index, &path))
+            .await
+    }
+
+    /// list the directory on the remote server, the entry name and whether it is a directory.
+    pub async fn list_dir(
+        &self,
+        index: usize,
+        dir: &str,
+        cancel: &Cancel,
+    ) -> Result<Vec<(String, bool)>> {
+        let dir = dir.to_string();
+        self.run(cancel, move |atsh| atsh.list_dir(index, &dir))
+            .await
+    }
+}
+
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    f: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(t) => tokio::time::timeout(t, f)
+            .await
+            .map_err(|_| Error::Transport(format!("the operation timed out after {t:?}")))?,
+        None => f.await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Remotes;
+
+    fn block_on<T>(f: impl Future<Output = T>) -> T {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    #[test]
+    fn test_async() {
+        let dir = std::env::temp_dir().join(format!("atsh-async-{}", std::process::id()));
+        let atsh = AsyncAtsh::new(Atsh::open(&dir).unwrap()).timeout(Duration::from_secs(5));
+        let remote = Remote {
+            user: "root".to_string(),
+            ip: "127.0.0.1".to_string(),
+            port: 1,
+            ..Default::default()
+        };
+
+        // nothing is listening on the port
+        let e = block_on(atsh.connect(&remote, &Cancel::new())).unwrap_err();
+        assert_eq!(e.code(), 1003, "{e}");
+
+        let cancel = Cancel::new();
+        cancel.cancel();
+        let e = block_on(atsh.connect(&remote, &cancel)).unwrap_err();
+        assert_eq!(e.to_string(), "the operation is cancelled");
+
+        let e = block_on(atsh.authenticate(42, &Cancel::new())).unwrap_err();
+        assert_eq!(e.code(), 1005);
+        std::fs::remove_dir_all(dir).unwrap();
+        assert_eq!(
+            AsyncAtsh::new(Atsh::global().clone()).timeout,
+            Some(AsyncAtsh::DEFAULT_TIMEOUT)
+        );
+    }
+
+    #[test]
+    fn test_cancel_steps() {
+        let cancel = Cancel::new();
+        LIMIT.with(|l| {
+            *l.borrow_mut() = Some(Limit {
+                deadline: None,
+                cancel: cancel.clone(),
+            })
+        });
+        let remotes = Remotes(vec![Remote::default(), Remote::default()]);
+        let calls = std::cell::Cell::new(0);
+        let outcomes = remotes.each(|_| {
+            calls.set(calls.get() + 1);
+            // cancelled while the first server is running
+            cancel.cancel();
+            Ok(())
+        });
+        LIMIT.with(|l| *l.borrow_mut() = None);
+        assert_eq!(calls.get(), 1);
+        assert!(outcomes[0].error.is_none());
+        assert_eq!(
+            outcomes[1].error.as_deref(),
+            Some("the operation is cancelled")
+        );
+    }
+}
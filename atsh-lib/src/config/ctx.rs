@@ -1,12 +1,15 @@
+use crate::error::Error;
 use std::env::{current_exe, home_dir};
-use std::io::{Error, ErrorKind};
 use std::path::{Path, PathBuf};
 use std::sync::{LazyLock, OnceLock};
 use tracing::debug;
 
+use crate::context::current;
+
 static WORK_DIR: OnceLock<PathBuf> = OnceLock::new();
+// the file in the work directory of the `Atsh` in scope, the default one if none
 pub static WORK_DIR_FILE: LazyLock<fn(&str) -> PathBuf> =
-    LazyLock::new(|| |n| get_work_dir().join(n));
+    LazyLock::new(|| |n| current().work_dir().join(n));
 
 /// if you want to change the work directory,
 /// call before call the `get_work_dir`,
@@ -20,7 +23,7 @@ pub fn set_work_dir(w: impl AsRef<Path>) -> Result<(), Error> {
     // we set WORK_DIR immediately
     WORK_DIR
         .set(wd)
-        .map_err(|_| Error::new(ErrorKind::AlreadyExists, "WORK_DIR already initialized"))?;
+        .map_err(|_| Error::InvalidInput("WORK_DIR already initialized".to_string()))?;
     Ok(())
 }
 
@@ -28,23 +31,7 @@ pub fn set_work_dir(w: impl AsRef<Path>) -> Result<(), Error> {
 /// if not set, will use `$HOME/.atsh`
 pub fn get_work_dir() -> &'static PathBuf {
     WORK_DIR.get_or_init(|| {
-        let wd = if cfg!(test) {
-            PathBuf::from("test.atsh.d")
-        } else if let Some(w) = std::env::var_os("ATSH_WORK_DIR") {
-            let wd = PathBuf::from(w);
-            debug!(work_dir=?wd, "The work directory by environment `ATSH_WORK_DIR`");
-            wd
-        } else {
-            let wd = home_dir()
-                // the system home directory
-                .map(|h| h.join(".atsh.d"))
-                // current executable directory
-                .or_else(|| current_exe().ok().map(|e| e.with_file_name(".atsh.d")))
-                // Error
-                // .ok_or_else(|| Error::new(ErrorKind::NotFound, "WORK_DIR not found"))?;
-                .expect("WORK_DIR not found");
-            wd
-        };
+        let wd = base_work_dir();
         if !wd.exists() {
             std::fs::create_dir_all(&wd).expect("Failed to create work directory");
         }
@@ -53,6 +40,26 @@ pub fn get_work_dir() -> &'static PathBuf {
     })
 }
 
+/// the work directory of the `default` profile, the others are in its `profiles`.
+pub(crate) fn base_work_dir() -> PathBuf {
+    if cfg!(test) {
+        PathBuf::from("test.atsh.d")
+    } else if let Some(w) = std::env::var_os("ATSH_WORK_DIR") {
+        let wd = PathBuf::from(w);
+        debug!(work_dir=?wd, "The work directory by environment `ATSH_WORK_DIR`");
+        wd
+    } else {
+        home_dir()
+            // the system home directory
+            .map(|h| h.join(".atsh.d"))
+            // current executable directory
+            .or_else(|| current_exe().ok().map(|e| e.with_file_name(".atsh.d")))
+            // Error
+            // .ok_or_else(|| Error::new(ErrorKind::NotFound, "WORK_DIR not found"))?;
+            .expect("WORK_DIR not found")
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -1,20 +1,36 @@
+use crate::error::Error;
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
-use std::io::{Error, ErrorKind};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::LazyLock;
 use tracing::{debug, info, warn};
 
 use super::ctx::WORK_DIR_FILE;
+use super::profile::{current_profile, profile_key_var, DEFAULT_PROFILE};
 
 // `ATSH_KEY` start
 //  The key used to encrypt the data like password.
+//  Each profile has its own key, like `ATSH_KEY_WORK` for the profile `work`.
 static ATSH_KEY: LazyLock<Mutex<Option<String>>> = LazyLock::new(|| {
-    if let Ok(key) = std::env::var("ATSH_KEY") {
-        debug!("`ATSH_KEY` found in environment variable");
+    let var = profile_key_var(current_profile());
+    if let Ok(key) = std::env::var(&var) {
+        debug!("`{var}` found in environment variable");
         return Mutex::new(Some(key));
     }
+    // keep the key out of the environment, like `ATSH_KEY_FD=3 atsh ... 3<key.txt`
+    if let Ok(fd) = std::env::var("ATSH_KEY_FD") {
+        match read_fd(&fd) {
+            Ok(key) => {
+                debug!("`ATSH_KEY` found in file descriptor {fd}");
+                return Mutex::new(Some(key));
+            }
+            Err(e) => warn!(error = ?e, "💥 Failed to read `ATSH_KEY` from `ATSH_KEY_FD`"),
+        }
+    }
+    if current_profile() != DEFAULT_PROFILE {
+        return Mutex::new(None);
+    }
     if let Ok(key) = std::env::var("ASKEY") {
         warn!("💡 Deprecated `ASKEY` in next version and use `ATSH_KEY` instead");
         return Mutex::new(Some(key));
@@ -23,6 +39,15 @@ static ATSH_KEY: LazyLock<Mutex<Option<String>>> = LazyLock::new(|| {
     Mutex::new(None)
 });
 
+/// read the first line from the file descriptor, like `3`.
+pub fn read_fd(fd: &str) -> Result<String, Error> {
+    let fd: u32 = fd
+        .parse()
+        .map_err(|_| Error::InvalidInput(format!("invalid file descriptor `{fd}`")))?;
+    let data = std::fs::read_to_string(format!("/dev/fd/{fd}"))?;
+    Ok(data.lines().next().unwrap_or_default().to_string())
+}
+
 pub fn get_atshkey() -> Result<String, Error> {
     let key = {
         let k = ATSH_KEY.lock();
@@ -31,11 +56,21 @@ pub fn get_atshkey() -> Result<String, Error> {
     if let Some(k) = key {
         Ok(k)
     } else {
-        Err(Error::new(
-            ErrorKind::NotFound,
-            "💥 Export `ATSH_KEY` to protect password",
-        ))
+        Err(Error::Crypto(format!(
+            "💥 Export `{}` (or `ATSH_KEY_FD`) to protect password",
+            profile_key_var(current_profile())
+        )))
+    }
+}
+
+/// the key must be long enough to protect the password.
+pub(crate) fn check_atshkey(key: &str) -> Result<(), Error> {
+    if key.len() < 5 {
+        return Err(Error::InvalidInput(
+            "💥 ATSH_KEY must be at least 5 characters".to_string(),
+        ));
     }
+    Ok(())
 }
 
 pub fn set_atshkey(key: Option<impl AsRef<str>>) -> Result<(), Error> {
@@ -46,12 +81,7 @@ pub fn set_atshkey(key: Option<impl AsRef<str>>) -> Result<(), Error> {
     }
     let key = key.unwrap();
     let set = key.as_ref();
-    if set.len() < 5 {
-        return Err(Error::new(
-            ErrorKind::InvalidInput,
-            "💥 ATSH_KEY must be at least 5 characters",
-        ));
-    }
+    check_atshkey(set)?;
     info!("🔑 Set ATSH_KEY to {}...", &set[..2]);
     *ATSH_KEY.lock() = Some(set.to_string());
     Ok(())
@@ -98,12 +128,130 @@ impl SSHKey {
     pub fn read_public(&self) -> Result<String, Error> {
         let key = self.get_public();
         if !key.is_file() {
-            return Err(Error::new(
-                ErrorKind::NotFound,
-                "public key not found, you can generate it by `ssh-keygen` and set it to config",
+            return Err(Error::InvalidInput(
+                "public key not found, you can generate it by `ssh-keygen` and set it to config"
+                    .to_string(),
             ));
         }
-        std::fs::read_to_string(key)
+        Ok(std::fs::read_to_string(key)?)
+    }
+}
+
+// The short-lived key pair generated for every session of an ephemeral record.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EphemeralKey {
+    /// the minutes the key is accepted by the remote host (`expiry-time=`), 0 means no expiry.
+    pub ttl: u32,
+    /// restrict the key to the client address seen by the remote host (`from=`).
+    pub restrict_from: bool,
+}
+
+impl Default for EphemeralKey {
+    fn default() -> Self {
+        EphemeralKey {
+            ttl: 60,
+            restrict_from: true,
+        }
+    }
+}
+
+impl EphemeralKey {
+    /// the directory to keep the key pairs of the living sessions.
+    pub fn get_dir(&self) -> PathBuf {
+        WORK_DIR_FILE("ephemeral")
+    }
+}
+
+// The local user certificate authority to sign the atsh public key.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CertAuthority {
+    /// the CA public key location, installed as `TrustedUserCAKeys` on remote host.
+    public: PathBuf,
+    /// the CA private key location.
+    private: PathBuf,
+    /// the validity interval of the certificate, see `ssh-keygen -V`.
+    pub validity: String,
+}
+
+impl Default for CertAuthority {
+    fn default() -> Self {
+        CertAuthority {
+            public: WORK_DIR_FILE("atsh_ca.pub"),
+            private: WORK_DIR_FILE("atsh_ca"),
+            validity: "+1h".to_string(),
+        }
+    }
+}
+
+impl CertAuthority {
+    /// the directory to keep the certificates signed for the records.
+    pub fn get_dir(&self) -> PathBuf {
+        WORK_DIR_FILE("certs")
+    }
+
+    pub fn get_public(&self) -> &Path {
+        self.public.as_path()
+    }
+
+    pub fn get_private(&self) -> &Path {
+        self.private.as_path()
+    }
+
+    /// read CA public key, if not exists, create the CA first
+    pub fn read_public(&self) -> Result<String, Error> {
+        if !self.private.is_file() {
+            warn!(file = ?self.private, "💡 CA key does not exist, will create one");
+            let status = Command::new("ssh-keygen")
+                .args(["-q", "-t", "ed25519", "-N", "", "-C", "atsh-ca", "-f"])
+                .arg(&self.private)
+                .status()?;
+            if !status.success() {
+                return Err(Error::other(format!(
+                    "Failed to generate CA key (exit code: {:?})",
+                    status.code()
+                )));
+            }
+            info!("✅ CA key generated successfully at: {:?}", self.private);
+        }
+        Ok(std::fs::read_to_string(&self.public)?)
+    }
+
+    /// sign the `public` key to a user certificate valid for the `principals`,
+    /// the certificate is written to `{stem}-cert.pub`.
+    pub fn sign(
+        &self,
+        public: &Path,
+        identity: &str,
+        principals: &str,
+        stem: &Path,
+    ) -> Result<PathBuf, Error> {
+        // make sure the CA exists
+        self.read_public()?;
+        // `ssh-keygen -s` writes the certificate next to the public key as `*-cert.pub`
+        let copy = stem.with_extension("pub");
+        std::fs::copy(public, &copy)?;
+        let status = Command::new("ssh-keygen")
+            .arg("-q")
+            .arg("-s")
+            .arg(&self.private)
+            .args(["-I", identity, "-n", principals, "-V", &self.validity])
+            .arg(&copy)
+            .status()?;
+        std::fs::remove_file(&copy)?;
+        if !status.success() {
+            return Err(Error::other(format!(
+                "Failed to sign certificate (exit code: {:?})",
+                status.code()
+            )));
+        }
+        debug!(
+            identity = identity,
+            principals = principals,
+            "sign certificate success"
+        );
+        Ok(PathBuf::from(format!("{}-cert.pub", stem.display())))
     }
 }
 
@@ -134,9 +282,8 @@ pub fn create_sshkey(
                 Some(p) => {
                     let p = p.as_ref();
                     if p.len() < 8 {
-                        return Err(Error::new(
-                            ErrorKind::InvalidInput,
-                            "password must be at least 8 characters long",
+                        return Err(Error::InvalidInput(
+                            "password must be at least 8 characters long".to_string(),
                         ));
                     }
                     debug!(
@@ -157,7 +304,7 @@ pub fn create_sshkey(
             args.push(&pass);
 
             // clean exist key
-            for p in vec![output, &output.with_extension("pub")] {
+            for p in [output, &output.with_extension("pub")] {
                 if p.is_file() {
                     warn!(file = ?p, "SSH Key exists, remove it");
                     std::fs::remove_file(p)?;
@@ -169,13 +316,10 @@ pub fn create_sshkey(
 
     // let status = Command::new("ssh-keygen").args(&args).status()?;
     if !status.success() {
-        return Err(Error::new(
-            ErrorKind::Other,
-            format!(
-                "Failed to generate SSH key (exit code: {:?})",
-                status.code()
-            ),
-        ));
+        return Err(Error::other(format!(
+            "Failed to generate SSH key (exit code: {:?})",
+            status.code()
+        )));
     }
 
     info!("✅ SSH key generated successfully at: {:?}", output);
@@ -272,7 +416,7 @@ mod tests {
         assert_eq!(s.unwrap(), output);
 
         let check = Command::new("ssh-keygen")
-            .args(&["-y", "-f", output.to_str().unwrap(), "-P", password])
+            .args(["-y", "-f", output.to_str().unwrap(), "-P", password])
             .status();
         assert!(check.is_ok());
         assert!(check.unwrap().success());
@@ -280,4 +424,37 @@ mod tests {
         // let s = create_sshkey(Some(password), &output, true);
         // assert!(s.is_ok());
     }
+
+    #[test]
+    fn test_ca_sign() {
+        let dir = std::env::temp_dir().join(format!("atsh-test-ca-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ca = CertAuthority {
+            public: dir.join("ca.pub"),
+            private: dir.join("ca"),
+            validity: "+5m".to_string(),
+        };
+        let key = create_sshkey(Option::<&str>::None, dir.join("user"), false).unwrap();
+        assert!(ca.read_public().unwrap().contains("atsh-ca"));
+
+        let cert = ca
+            .sign(
+                &key.with_extension("pub"),
+                "atsh:test",
+                "user,admin",
+                &dir.join("cert"),
+            )
+            .unwrap();
+        assert_eq!(cert, dir.join("cert-cert.pub"));
+
+        let output = Command::new("ssh-keygen")
+            .args(["-L", "-f", cert.to_str().unwrap()])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let output = String::from_utf8_lossy(&output.stdout);
+        assert!(output.contains("atsh:test"));
+        assert!(output.contains("admin"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
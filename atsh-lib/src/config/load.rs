@@ -1,22 +1,37 @@
+use crate::error::Error;
 use serde::{Deserialize, Serialize};
-use std::io::Error;
 use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
 use tracing::{debug, warn};
 
 use super::ctx::{get_work_dir, set_work_dir, WORK_DIR_FILE};
-use super::key::{create_sshkey, get_atshkey, set_atshkey, SSHKey};
+use super::key::{create_sshkey, get_atshkey, set_atshkey, CertAuthority, EphemeralKey, SSHKey};
+use crate::connection::ConnectOptions;
+use crate::context::Atsh;
 
-pub static CONFIG: LazyLock<Config> = LazyLock::new(|| Config::new());
+// the config of the default `Atsh`, never of the other one in scope
+pub static CONFIG: LazyLock<Config> = LazyLock::new(|| Atsh::global().scope(Config::new));
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Config {
     pub sshkey: SSHKey,
+    #[serde(default)]
+    pub ephemeral: EphemeralKey,
+    #[serde(default)]
+    pub ca: CertAuthority,
+    /// the default options to connect the remote servers.
+    #[serde(default)]
+    pub connection: ConnectOptions,
 }
 
 impl Config {
     pub fn new() -> Self {
-        let config = WORK_DIR_FILE("config.toml");
+        Self::open(get_work_dir())
+    }
+
+    /// load the `config.toml` in the work directory, the default is created if not exists.
+    pub(crate) fn open(dir: &Path) -> Self {
+        let config = dir.join("config.toml");
         // if config file not exists, create it
         if !config.is_file() {
             std::fs::write(
@@ -72,7 +87,7 @@ impl Config {
 
     /// get work directory
     pub fn get_work_dir(&self) -> &Path {
-        &get_work_dir()
+        get_work_dir()
     }
 
     /// set work directory
@@ -1,5 +1,13 @@
 mod ctx;
 mod key;
 mod load;
+mod profile;
 
-pub use load::CONFIG;
+pub(crate) use ctx::{get_work_dir, set_work_dir};
+pub use key::read_fd;
+pub(crate) use key::{check_atshkey, get_atshkey, set_atshkey};
+pub use load::{Config, CONFIG};
+pub use profile::{
+    create_profile, current_profile, pprint_profiles, profile_dir, profile_key_var, use_profile,
+    DEFAULT_PROFILE,
+};
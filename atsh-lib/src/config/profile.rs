@@ -0,0 +1,170 @@
+use crate::error::Error;
+use prettytable::{Cell, Row, Table};
+use rusqlite::{Connection, OpenFlags};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tracing::debug;
+
+use super::ctx::{base_work_dir, set_work_dir};
+use crate::storage::db::count;
+
+/// the profile in the work directory itself, always exists.
+pub const DEFAULT_PROFILE: &str = "default";
+
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// the profile name is used in the path and the environment variable, like `work-eu`.
+fn check_name(name: &str) -> Result<(), Error> {
+    if name.is_empty()
+        || name.len() > 32
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+    {
+        return Err(Error::InvalidInput(format!(
+            "invalid profile `{name}`, only 1-32 chars of `a-z0-9_-` are allowed"
+        )));
+    }
+    Ok(())
+}
+
+/// the work directory of the profile, `profiles/<name>` in the default one.
+pub fn profile_dir(name: &str) -> Result<PathBuf, Error> {
+    check_name(name)?;
+    if name == DEFAULT_PROFILE {
+        return Ok(base_work_dir());
+    }
+    Ok(base_work_dir().join("profiles").join(name))
+}
+
+/// the environment variable of the profile key, `ATSH_KEY_WORK_EU` for `work-eu`.
+pub fn profile_key_var(name: &str) -> String {
+    if name == DEFAULT_PROFILE {
+        return "ATSH_KEY".to_string();
+    }
+    format!("ATSH_KEY_{}", name.to_uppercase().replace('-', "_"))
+}
+
+/// the current profile, `default` if none is used.
+pub fn current_profile() -> &'static str {
+    PROFILE.get().map_or(DEFAULT_PROFILE, |p| p.as_str())
+}
+
+/// use the existing profile as the work directory,
+/// call before the `get_work_dir` like the `set_work_dir`.
+pub fn use_profile(name: &str) -> Result<(), Error> {
+    let dir = profile_dir(name)?;
+    if name != DEFAULT_PROFILE && !dir.is_dir() {
+        return Err(Error::InvalidInput(format!(
+            "profile `{name}` not found, create it by `atsh profile create {name}`"
+        )));
+    }
+    set_work_dir(&dir)?;
+    PROFILE
+        .set(name.to_string())
+        .map_err(|_| Error::InvalidInput("PROFILE already initialized".to_string()))?;
+    debug!(profile = name, work_dir = ?dir, "The profile by user");
+    Ok(())
+}
+
+/// create the work directory of the new profile.
+pub fn create_profile(name: &str) -> Result<PathBuf, Error> {
+    let dir = profile_dir(name)?;
+    if name == DEFAULT_PROFILE || dir.exists() {
+        return Err(Error::InvalidInput(format!(
+            "profile `{name}` already exists"
+        )));
+    }
+    // like `a-b` and `a_b`, two profiles never share one key
+    let var = profile_key_var(name);
+    if let Some(other) = list_profiles()?
+        .into_iter()
+        .find(|p| profile_key_var(p) == var)
+    {
+        return Err(Error::InvalidInput(format!(
+            "profile `{name}` uses the key `{var}` of profile `{other}`"
+        )));
+    }
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// the names of all profiles, the `default` is the first.
+pub fn list_profiles() -> Result<Vec<String>, Error> {
+    let mut names = vec![];
+    let dir = base_work_dir().join("profiles");
+    if dir.is_dir() {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type()?.is_dir() && check_name(&name).is_ok() {
+                names.push(name);
+            }
+        }
+    }
+    names.sort();
+    names.insert(0, DEFAULT_PROFILE.to_string());
+    Ok(names)
+}
+
+/// the number of records in the profile, never create the database.
+fn count_records(dir: &Path) -> Option<usize> {
+    let db = dir.join("atsh.db");
+    let conn = Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY).ok()?;
+    count(&conn).ok()
+}
+
+/// print the profiles, the current one is marked by `*`.
+pub fn pprint_profiles() -> Result<(), Error> {
+    let mut table = Table::new();
+    table.set_titles(Row::new(
+        ["", "profile", "records", "key", "path"]
+            .iter()
+            .map(|v| Cell::new(v).style_spec("bcFg"))
+            .collect::<Vec<Cell>>(),
+    ));
+    for name in list_profiles()? {
+        let dir = profile_dir(&name)?;
+        let active = if name == current_profile() { "*" } else { "" };
+        let records = count_records(&dir).unwrap_or_default();
+        table.add_row(Row::new(
+            [
+                active.to_string(),
+                name.clone(),
+                records.to_string(),
+                profile_key_var(&name),
+                dir.to_string_lossy().to_string(),
+            ]
+            .iter()
+            .map(|v| Cell::new(v).style_spec("lFc"))
+            .collect::<Vec<Cell>>(),
+        ));
+    }
+    table.printstd();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        assert_eq!(profile_dir("default").unwrap(), base_work_dir());
+        assert_eq!(
+            profile_dir("work-eu").unwrap(),
+            base_work_dir().join("profiles").join("work-eu")
+        );
+        for bad in ["", "Work", "../x", "a b", &"x".repeat(33)] {
+            assert!(profile_dir(bad).is_err(), "{bad}");
+        }
+        assert_eq!(profile_key_var("default"), "ATSH_KEY");
+        assert_eq!(profile_key_var("work-eu"), "ATSH_KEY_WORK_EU");
+        assert!(create_profile("default").is_err());
+        let dir = create_profile("test-profile-var").unwrap();
+        let e = create_profile("test_profile_var").unwrap_err();
+        assert!(e.to_string().contains("ATSH_KEY_TEST_PROFILE_VAR"), "{e}");
+        std::fs::remove_dir_all(dir).unwrap();
+        assert_eq!(list_profiles().unwrap()[0], "default");
+    }
+}
@@ -0,0 +1,353 @@
+use crate::error::Error;
+use base64::{engine::general_purpose, Engine as _};
+use prettytable::{Cell, Row, Table};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use tracing::{debug, info, warn};
+
+use super::ssh::{SSHSession, EPHEMERAL_MARKER};
+use crate::context::current;
+
+/// One entry of the `authorized_keys`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuthorizedKey {
+    /// the line number in `authorized_keys`, start from 1.
+    pub line: usize,
+    /// the options before the key, like `from="1.2.3.4",no-pty`.
+    pub options: Option<String>,
+    /// the key type, like `ssh-ed25519`.
+    pub key_type: String,
+    /// the base64 encoded key.
+    #[serde(skip)]
+    pub blob: String,
+    /// the comment after the key.
+    pub comment: Option<String>,
+    /// the fingerprint same as `ssh-keygen -l`, like `SHA256:...`.
+    pub fingerprint: String,
+}
+
+fn is_key_type(s: &str) -> bool {
+    s.starts_with("ssh-") || s.starts_with("ecdsa-") || s.starts_with("sk-")
+}
+
+/// split the options at the first whitespace which is not quoted.
+fn split_options(line: &str) -> (&str, &str) {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => return (&line[..i], line[i..].trim_start()),
+            _ => {}
+        }
+    }
+    (line, "")
+}
+
+/// the `SHA256:` fingerprint of the base64 encoded key.
+pub fn fingerprint(blob: &str) -> Option<String> {
+    let raw = general_purpose::STANDARD.decode(blob).ok()?;
+    let digest = Sha256::digest(&raw);
+    Some(format!(
+        "SHA256:{}",
+        general_purpose::STANDARD_NO_PAD.encode(digest)
+    ))
+}
+
+impl AuthorizedKey {
+    /// parse one line of `authorized_keys` or a public key file.
+    pub fn parse(line_no: usize, line: &str) -> Option<AuthorizedKey> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (options, rest) = match line.split_whitespace().next() {
+            Some(first) if is_key_type(first) => (None, line),
+            _ => {
+                let (options, rest) = split_options(line);
+                (Some(options.to_string()), rest)
+            }
+        };
+        let mut parts = rest.splitn(3, char::is_whitespace);
+        let key_type = parts.next().filter(|t| is_key_type(t))?.to_string();
+        let blob = parts.next()?.trim().to_string();
+        let comment = parts
+            .next()
+            .map(|c| c.trim().to_string())
+            .filter(|c| !c.is_empty());
+        let fingerprint = fingerprint(&blob)?;
+        Some(AuthorizedKey {
+            line: line_no,
+            options,
+            key_type,
+            blob,
+            comment,
+            fingerprint,
+        })
+    }
+
+    fn is_ephemeral(&self) -> bool {
+        self.comment
+            .as_deref()
+            .is_some_and(|c| c.starts_with(EPHEMERAL_MARKER))
+    }
+}
+
+/// parse all entries of `authorized_keys`, the invalid lines are ignored.
+pub fn parse_authorized_keys(data: &str) -> Vec<AuthorizedKey> {
+    data.lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let key = AuthorizedKey::parse(i + 1, line);
+            if key.is_none() && !line.trim().is_empty() && !line.trim().starts_with('#') {
+                warn!(line = i + 1, "invalid entry in authorized_keys");
+            }
+            key
+        })
+        .collect()
+}
+
+/// The public key granted to the remote server by `copy-id`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Grant {
+    pub fingerprint: String,
+    pub comment: Option<String>,
+    /// the local user who granted the key.
+    pub granted_by: String,
+    /// the unix timestamp when the key granted.
+    pub granted_at: u64,
+}
+
+/// The audit result of one entry.
+#[derive(Debug, Serialize)]
+pub struct KeyStatus {
+    #[serde(flatten)]
+    pub key: AuthorizedKey,
+    /// the atsh public key.
+    pub atsh: bool,
+    /// the key installed for one session by atsh.
+    pub ephemeral: bool,
+    /// the key granted by `copy-id`.
+    pub granted: bool,
+    /// the key is not installed by atsh.
+    pub unknown: bool,
+    /// the same key appears more than once.
+    pub duplicate: bool,
+    /// the entry has been removed in this audit.
+    pub removed: bool,
+}
+
+/// The audit result of one remote server.
+#[derive(Debug, Serialize)]
+pub struct KeyAudit {
+    pub index: usize,
+    pub name: Option<String>,
+    /// the remote server like `user@ip:port`.
+    pub remote: String,
+    pub keys: Vec<KeyStatus>,
+    /// the error if the audit failed, like server unreachable.
+    pub error: Option<String>,
+}
+
+fn strip_prefix(fingerprint: &str) -> &str {
+    fingerprint.strip_prefix("SHA256:").unwrap_or(fingerprint)
+}
+
+/// audit the `authorized_keys` of the session, and remove the entries by fingerprint.
+pub fn audit(
+    session: &SSHSession,
+    grants: &[Grant],
+    remove: &[impl AsRef<str>],
+) -> Result<Vec<KeyStatus>, Error> {
+    let file = session.authorized_keys()?;
+    let data = session.read_file(&file)?.unwrap_or_default();
+    let atsh = current()
+        .config()
+        .read_public()
+        .ok()
+        .and_then(|k| AuthorizedKey::parse(0, &k))
+        .map(|k| k.fingerprint);
+
+    let keys = parse_authorized_keys(&data);
+    let mut counts = HashMap::new();
+    for key in keys.iter() {
+        *counts.entry(key.fingerprint.clone()).or_insert(0) += 1;
+    }
+    let status = keys
+        .into_iter()
+        .map(|key| {
+            let is_atsh = atsh.as_ref() == Some(&key.fingerprint);
+            let ephemeral = key.is_ephemeral();
+            let granted = grants.iter().any(|g| g.fingerprint == key.fingerprint);
+            let removed = remove
+                .iter()
+                .any(|r| strip_prefix(r.as_ref()) == strip_prefix(&key.fingerprint));
+            KeyStatus {
+                atsh: is_atsh,
+                ephemeral,
+                granted,
+                unknown: !is_atsh && !ephemeral && !granted,
+                duplicate: counts[&key.fingerprint] > 1,
+                removed,
+                key,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let removed = status
+        .iter()
+        .filter(|s| s.removed)
+        .map(|s| s.key.line)
+        .collect::<Vec<_>>();
+    if !removed.is_empty() {
+        let mut data = data
+            .lines()
+            .enumerate()
+            .filter(|(i, _)| !removed.contains(&(i + 1)))
+            .map(|(_, line)| line)
+            .collect::<Vec<_>>()
+            .join("\n");
+        data.push('\n');
+        session.write_file(&file, &data, 0o600)?;
+        info!(total = removed.len(), "success remove authorized keys");
+    }
+    debug!(total = status.len(), "audit authorized keys");
+    Ok(status)
+}
+
+pub fn pprint(audits: &[KeyAudit]) {
+    let mut table = Table::new();
+    let titles = [
+        "index",
+        "remote",
+        "line",
+        "type",
+        "fingerprint",
+        "comment",
+        "status",
+    ];
+    table.set_titles(Row::new(
+        titles
+            .iter()
+            .map(|v| Cell::new(v).style_spec("bcFg"))
+            .collect::<Vec<Cell>>(),
+    ));
+    for audit in audits.iter() {
+        let remote = match &audit.name {
+            Some(name) => format!("{} ({})", audit.remote, name),
+            None => audit.remote.clone(),
+        };
+        if let Some(e) = &audit.error {
+            table.add_row(Row::new(vec![
+                Cell::new(&audit.index.to_string()).style_spec("lFc"),
+                Cell::new(&remote).style_spec("lFc"),
+                Cell::new(e).style_spec("lFrH5"),
+            ]));
+            continue;
+        }
+        for s in audit.keys.iter() {
+            let mut status = vec![];
+            if s.atsh {
+                status.push("atsh");
+            }
+            if s.ephemeral {
+                status.push("ephemeral");
+            }
+            if s.granted {
+                status.push("granted");
+            }
+            if s.unknown {
+                status.push("unknown");
+            }
+            if s.duplicate {
+                status.push("duplicate");
+            }
+            if s.removed {
+                status.push("removed");
+            }
+            let style = if s.removed || s.duplicate {
+                "lFr"
+            } else if s.unknown {
+                "lFy"
+            } else {
+                "lFc"
+            };
+            let row = [
+                audit.index.to_string(),
+                remote.clone(),
+                s.key.line.to_string(),
+                s.key.key_type.clone(),
+                s.key.fingerprint.clone(),
+                s.key.comment.clone().unwrap_or_default(),
+                status.join(","),
+            ];
+            table.add_row(Row::new(
+                row.iter()
+                    .map(|v| Cell::new(v).style_spec(style))
+                    .collect::<Vec<Cell>>(),
+            ));
+        }
+    }
+    table.printstd();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED25519: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIL3jHCIY5aSh8tXOR+hzhtKQqvaQ3gZjTc+hNKXfQ2fH";
+
+    #[test]
+    fn test_parse_authorized_keys() {
+        let data = format!(
+            "# comment line\n\
+             ssh-ed25519 {ED25519} alice@host\n\
+             \n\
+             from=\"1.2.3.4\",command=\"echo hello world\" ssh-ed25519 {ED25519}\n\
+             not a key\n\
+             expiry-time=\"202501010000\" ssh-ed25519 {ED25519} {EPHEMERAL_MARKER}:1-2-3\n"
+        );
+        let keys = parse_authorized_keys(&data);
+        assert_eq!(keys.len(), 3);
+
+        assert_eq!(keys[0].line, 2);
+        assert_eq!(keys[0].options, None);
+        assert_eq!(keys[0].key_type, "ssh-ed25519");
+        assert_eq!(keys[0].comment.as_deref(), Some("alice@host"));
+
+        assert_eq!(keys[1].line, 4);
+        assert_eq!(
+            keys[1].options.as_deref(),
+            Some("from=\"1.2.3.4\",command=\"echo hello world\"")
+        );
+        assert_eq!(keys[1].comment, None);
+        assert_eq!(keys[1].fingerprint, keys[0].fingerprint);
+
+        assert_eq!(keys[2].line, 6);
+        assert!(keys[2].is_ephemeral());
+        assert!(!keys[0].is_ephemeral());
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let output = current().work_dir().join("test_fingerprint");
+        crate::config::CONFIG
+            .create_sshkey(Option::<&str>::None, Some(&output), false)
+            .unwrap();
+        let public = std::fs::read_to_string(output.with_extension("pub")).unwrap();
+        let key = AuthorizedKey::parse(1, &public).unwrap();
+
+        let check = std::process::Command::new("ssh-keygen")
+            .args(["-l", "-E", "sha256", "-f", output.to_str().unwrap()])
+            .output()
+            .unwrap();
+        assert!(check.status.success());
+        let check = String::from_utf8_lossy(&check.stdout);
+        assert!(key.fingerprint.starts_with("SHA256:"));
+        assert!(check.contains(&key.fingerprint));
+        assert!(fingerprint("not base64!").is_none());
+    }
+}
@@ -0,0 +1,189 @@
+use std::str::FromStr;
+
+use crate::error::Error;
+
+use super::remote::{AuthMode, Remote};
+
+/// The changes of the remote server, only the given fields are changed.
+///
+/// The empty value of the optional fields (name, note, principals, totp, sudo password)
+/// clears it, the login target or mode changed means the server is authorized again.
+#[derive(Debug, Clone, Default)]
+pub struct RemoteEdit {
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub ip: Option<String>,
+    pub port: Option<u16>,
+    pub mode: Option<AuthMode>,
+    pub name: Option<String>,
+    pub note: Option<String>,
+    pub principals: Option<String>,
+    pub totp: Option<String>,
+    pub sudo_password: Option<String>,
+    /// replace all the tags, before `tag` and `untag`.
+    pub tags: Option<Vec<String>>,
+    /// add the tags.
+    pub tag: Vec<String>,
+    /// remove the tags.
+    pub untag: Vec<String>,
+    /// the connect options like `("connect_timeout", "5")`, empty value to unset.
+    pub options: Vec<(String, String)>,
+}
+
+/// the empty value means clear the field.
+fn optional(value: &Option<String>, old: &mut Option<String>) {
+    if let Some(v) = value {
+        *old = Some(v.clone()).filter(|v| !v.is_empty());
+    }
+}
+
+/// set the field if given, return whether it is changed.
+fn changed<T: PartialEq + Clone>(value: &Option<T>, old: &mut T) -> bool {
+    match value {
+        Some(v) if v != old => {
+            *old = v.clone();
+            true
+        }
+        _ => false,
+    }
+}
+
+impl RemoteEdit {
+    /// apply the changes to the remote server, the record is not updated.
+    pub fn apply(&self, remote: &mut Remote) -> Result<(), Error> {
+        let mut target = changed(&self.user, &mut remote.user);
+        target |= changed(&self.ip, &mut remote.ip);
+        target |= changed(&self.port, &mut remote.port);
+        target |= changed(&self.mode, &mut remote.mode);
+        // the login target or mode changed, authorize it again
+        if target {
+            remote.authorized = false;
+        }
+        if let Some(password) = &self.password {
+            remote.password = password.clone();
+        }
+        optional(&self.name, &mut remote.name);
+        optional(&self.note, &mut remote.note);
+        optional(&self.principals, &mut remote.principals);
+        optional(&self.totp, &mut remote.totp);
+        optional(&self.sudo_password, &mut remote.sudo_password);
+        if let Some(tags) = &self.tags {
+            remote.tags = tags.clone();
+        }
+        for tag in self.tag.iter() {
+            if !remote.tags.contains(tag) {
+                remote.tags.push(tag.clone());
+            }
+        }
+        remote.tags.retain(|t| !self.untag.contains(t));
+        for (key, value) in self.options.iter() {
+            remote.options.set(key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse one `field=value` like `note=expired soon` or `tags=prod,db`,
+/// the secrets are never parsed from the text.
+impl FromStr for RemoteEdit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, value) = s.split_once('=').ok_or_else(|| {
+            Error::InvalidInput("the edit is like `note=expired soon`".to_string())
+        })?;
+        let value = value.trim().to_string();
+        let mut edit = RemoteEdit::default();
+        match field.trim() {
+            "name" => edit.name = Some(value),
+            "note" => edit.note = Some(value),
+            "principals" => edit.principals = Some(value),
+            "tags" => {
+                edit.tags = Some(
+                    value
+                        .split(',')
+                        .map(|t| t.trim().to_string())
+                        .filter(|t| !t.is_empty())
+                        .collect(),
+                )
+            }
+            "user" => edit.user = Some(value),
+            "ip" => edit.ip = Some(value),
+            "port" => {
+                edit.port = Some(
+                    value
+                        .parse()
+                        .map_err(|_| Error::InvalidInput(format!("invalid port `{value}`")))?,
+                )
+            }
+            "mode" => edit.mode = Some(value.parse()?),
+            f => {
+                return Err(Error::InvalidInput(format!(
+                    "the field `{f}` can not be edited by `field=value`, one of name/note/principals/tags/user/ip/port/mode"
+                )))
+            }
+        }
+        Ok(edit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit() {
+        let mut remote = Remote {
+            user: "root".to_string(),
+            port: 22,
+            authorized: true,
+            tags: vec!["db".to_string()],
+            ..Default::default()
+        };
+        let edit = RemoteEdit {
+            user: Some("root".to_string()),
+            port: Some(22),
+            password: Some("new".to_string()),
+            note: Some("expired soon".to_string()),
+            tag: vec!["prod".to_string()],
+            untag: vec!["db".to_string()],
+            ..Default::default()
+        };
+        edit.apply(&mut remote).unwrap();
+        assert_eq!(remote.password, "new");
+        assert_eq!(remote.note.as_deref(), Some("expired soon"));
+        assert_eq!(remote.tags, vec!["prod"]);
+        // the same target and mode, still authorized
+        assert!(remote.authorized);
+
+        "note="
+            .parse::<RemoteEdit>()
+            .unwrap()
+            .apply(&mut remote)
+            .unwrap();
+        assert_eq!(remote.note, None);
+        "tags=web, db"
+            .parse::<RemoteEdit>()
+            .unwrap()
+            .apply(&mut remote)
+            .unwrap();
+        assert_eq!(remote.tags, vec!["web", "db"]);
+        assert!(remote.authorized);
+        "port=2222"
+            .parse::<RemoteEdit>()
+            .unwrap()
+            .apply(&mut remote)
+            .unwrap();
+        assert_eq!(remote.port, 2222);
+        assert!(!remote.authorized);
+
+        assert!("password=x".parse::<RemoteEdit>().is_err());
+        assert!("port=x".parse::<RemoteEdit>().is_err());
+        assert!("note".parse::<RemoteEdit>().is_err());
+        let edit = RemoteEdit {
+            options: vec![("unknown".to_string(), "1".to_string())],
+            ..Default::default()
+        };
+        assert!(edit.apply(&mut remote).is_err());
+    }
+}
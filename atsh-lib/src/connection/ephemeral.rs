@@ -0,0 +1,275 @@
+use crate::error::Error;
+use sha2::{Digest, Sha256};
+use std::fs::{File, TryLockError};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+use super::ssh::EPHEMERAL_MARKER;
+use crate::context::current;
+
+/// the file in the key directory to keep the id of this host.
+const HOST_ID: &str = ".host";
+
+/// The key pair of one session, the files are removed when dropped.
+pub struct SessionKey {
+    /// the unique id written as comment of the `authorized_keys` entry,
+    /// prefixed by the id of this host.
+    pub id: String,
+    /// the private key location.
+    private: PathBuf,
+    /// the `<id>.lock` locked while the session is alive, see `living_ids`.
+    lock: Option<File>,
+}
+
+impl SessionKey {
+    pub fn create(index: usize) -> Result<SessionKey, Error> {
+        let dir = current().config().ephemeral.get_dir();
+        if !dir.is_dir() {
+            std::fs::create_dir_all(&dir)?;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let id = format!("{}-{}-{}-{}", host_id()?, index, std::process::id(), now);
+        let private = dir.join(&id);
+        let lock = File::create(private.with_extension("lock"))?;
+        lock.lock()?;
+        // the files are removed by drop if the key is not generated
+        let key = SessionKey {
+            id,
+            private,
+            lock: Some(lock),
+        };
+
+        let status = Command::new("ssh-keygen")
+            .args([
+                "-q",
+                "-t",
+                "ed25519",
+                "-N",
+                "",
+                "-C",
+                EPHEMERAL_MARKER,
+                "-f",
+            ])
+            .arg(&key.private)
+            .status()?;
+        if !status.success() {
+            return Err(Error::other(format!(
+                "Failed to generate ephemeral key (exit code: {:?})",
+                status.code()
+            )));
+        }
+        debug!(id = key.id, "ephemeral key created");
+        Ok(key)
+    }
+
+    pub fn get_private(&self) -> &Path {
+        self.private.as_path()
+    }
+
+    pub fn read_public(&self) -> Result<String, Error> {
+        Ok(std::fs::read_to_string(self.private.with_extension("pub"))?)
+    }
+}
+
+impl Drop for SessionKey {
+    fn drop(&mut self) {
+        for p in [self.private.clone(), self.private.with_extension("pub")] {
+            if let Err(e) = std::fs::remove_file(&p) {
+                warn!(file = ?p, error = ?e, "failed to remove ephemeral key");
+            }
+        }
+        // unlock before removed, the open file can't be removed on windows
+        self.lock.take();
+        let _ = std::fs::remove_file(self.private.with_extension("lock"));
+        debug!(id = self.id, "ephemeral key removed");
+    }
+}
+
+/// the ids of the sessions which are still alive on this host.
+/// the key files left by crashes are removed here.
+pub fn living_ids() -> Result<Vec<String>, Error> {
+    let dir = current().config().ephemeral.get_dir();
+    let ttl = Duration::from_secs(current().config().ephemeral.ttl as u64 * 60);
+    living_in(&dir, ttl)
+}
+
+/// whether the session of the key is alive, it holds the lock of `<id>.lock` until dropped.
+/// if the file system can't lock, the key older than the `ttl` is left by crash.
+fn is_living(private: &Path, ttl: Duration) -> Result<bool, Error> {
+    let lock = match File::open(private.with_extension("lock")) {
+        Ok(lock) => lock,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    match lock.try_lock() {
+        Ok(()) => Ok(false),
+        Err(TryLockError::WouldBlock) => Ok(true),
+        Err(TryLockError::Error(e)) => {
+            warn!(error = ?e, "unable to lock the ephemeral key, check its age");
+            let age = private
+                .metadata()?
+                .modified()?
+                .elapsed()
+                .unwrap_or_default();
+            Ok(ttl.is_zero() || age <= ttl)
+        }
+    }
+}
+
+fn living_in(dir: &Path, ttl: Duration) -> Result<Vec<String>, Error> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut ids = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let id = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| Error::InvalidInput("invalid ephemeral key name".to_string()))?
+            .to_string();
+        if path.extension().is_some() || id.starts_with('.') {
+            continue;
+        }
+        if !is_living(&path, ttl)? {
+            warn!(id = id, "remove the ephemeral key left by previous session");
+            std::fs::remove_file(&path)?;
+            let _ = std::fs::remove_file(path.with_extension("pub"));
+            let _ = std::fs::remove_file(path.with_extension("lock"));
+            continue;
+        }
+        ids.push(id);
+    }
+    Ok(ids)
+}
+
+/// the random id of this host, the entries installed by the other hosts are never swept
+/// by this one unless expired.
+pub fn host_id() -> Result<String, Error> {
+    let file = current().config().ephemeral.get_dir().join(HOST_ID);
+    if let Ok(id) = std::fs::read_to_string(&file) {
+        let id = id.trim();
+        if !id.is_empty() {
+            return Ok(id.to_string());
+        }
+    }
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let seed = format!(
+        "{:?}-{}-{:?}",
+        SystemTime::now(),
+        std::process::id(),
+        std::env::var_os("HOSTNAME")
+    );
+    let id = Sha256::digest(seed.as_bytes())[..4]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+    std::fs::write(&file, &id)?;
+    debug!(id = id, "ephemeral host id created");
+    Ok(id)
+}
+
+/// remove the ephemeral entries of the `authorized_keys` content, `now` is like `202501010000`.
+/// the entry is removed if expired, or installed by `host` and `keep(id)` returns false.
+pub(crate) fn sweep(
+    data: &str,
+    now: &str,
+    host: &str,
+    keep: impl Fn(&str) -> bool,
+) -> (String, usize) {
+    let prefix = format!("{host}-");
+    let mut removed = 0;
+    let lines = data
+        .lines()
+        .filter(|line| {
+            let id = match line
+                .split_whitespace()
+                .last()
+                .and_then(|c| c.strip_prefix(EPHEMERAL_MARKER))
+                .and_then(|c| c.strip_prefix(':'))
+            {
+                Some(id) => id,
+                None => return true,
+            };
+            let expired = line
+                .split("expiry-time=\"")
+                .nth(1)
+                .and_then(|s| s.get(..12))
+                .map(|expiry| expiry < now)
+                .unwrap_or(false);
+            let mine = id.starts_with(&prefix);
+            if !expired && (!mine || keep(id)) {
+                return true;
+            }
+            debug!(id = id, expired = expired, "sweep ephemeral key");
+            removed += 1;
+            false
+        })
+        .collect::<Vec<_>>();
+    let mut data = lines.join("\n");
+    data.push('\n');
+    (data, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep() {
+        let data = format!(
+            "ssh-ed25519 AAAA user@laptop\n\
+             expiry-time=\"202501010000\" ssh-ed25519 AAAA {EPHEMERAL_MARKER}:me-1-2-3\n\
+             expiry-time=\"209901010000\" ssh-ed25519 AAAA {EPHEMERAL_MARKER}:me-1-2-4\n\
+             expiry-time=\"209901010000\" ssh-ed25519 AAAA {EPHEMERAL_MARKER}:me-1-2-5\n\
+             expiry-time=\"209901010000\" ssh-ed25519 AAAA {EPHEMERAL_MARKER}:other-1-2-3\n\
+             expiry-time=\"202501010000\" ssh-ed25519 AAAA {EPHEMERAL_MARKER}:other-1-2-4\n"
+        );
+        let (swept, n) = sweep(&data, "202610190000", "me", |id| id == "me-1-2-4");
+        assert_eq!(n, 3);
+        let ids = swept
+            .lines()
+            .map(|l| l.split_whitespace().last().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            [
+                "user@laptop",
+                &format!("{EPHEMERAL_MARKER}:me-1-2-4"),
+                &format!("{EPHEMERAL_MARKER}:other-1-2-3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_living() {
+        let dir = std::env::temp_dir().join(format!("atsh-living-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for id in ["me-1-2-3", "me-1-2-4", "me-1-2-5"] {
+            std::fs::write(dir.join(id), "private").unwrap();
+            std::fs::write(dir.join(id).with_extension("pub"), "public").unwrap();
+        }
+        // the alive session holds the lock, the crashed one left the lock file only
+        let lock = File::create(dir.join("me-1-2-3.lock")).unwrap();
+        lock.lock().unwrap();
+        File::create(dir.join("me-1-2-4.lock")).unwrap();
+        // never expired by the ttl 0
+        assert_eq!(living_in(&dir, Duration::ZERO).unwrap(), ["me-1-2-3"]);
+        let mut left = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(left, ["me-1-2-3", "me-1-2-3.lock", "me-1-2-3.pub"]);
+        drop(lock);
+        assert!(living_in(&dir, Duration::ZERO).unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+use serde::Serialize;
+use std::io::{Read, Write};
+
+use crate::error::Error;
+use tracing::debug;
+
+use super::ssh::SSHSession;
+
+/// the prompt of sudo, it is unique so that not confused with the command output.
+const SUDO_PROMPT: &str = "[atsh-sudo] password: ";
+
+/// quote the command as one argument of the remote shell.
+fn quote(cmd: &str) -> String {
+    format!("'{}'", cmd.replace('\'', r"'\''"))
+}
+
+/// The result of the command on one remote server.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ExecOutput {
+    /// the exit status of the command.
+    pub code: i32,
+    /// the output of the command, stderr is merged.
+    pub output: String,
+}
+
+/// Decode the output as utf-8, the character may be split in several reads.
+#[derive(Default)]
+pub(crate) struct Utf8Decoder {
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    /// decode the complete characters, the invalid ones are replaced.
+    pub(crate) fn feed(&mut self, data: &[u8]) -> String {
+        self.pending.extend_from_slice(data);
+        let keep = match std::str::from_utf8(&self.pending) {
+            Ok(_) => 0,
+            // the incomplete character at the end
+            Err(e) if e.error_len().is_none() => self.pending.len() - e.valid_up_to(),
+            Err(_) => 0,
+        };
+        let at = self.pending.len() - keep;
+        let text = String::from_utf8_lossy(&self.pending[..at]).to_string();
+        self.pending.drain(..at);
+        text
+    }
+
+    /// the data left at the end of output.
+    pub(crate) fn flush(&mut self) -> String {
+        String::from_utf8_lossy(&std::mem::take(&mut self.pending)).to_string()
+    }
+}
+
+/// Find the sudo prompt in the output, the prompt may be split in several reads.
+#[derive(Default)]
+struct PromptFilter {
+    pending: Vec<u8>,
+}
+
+impl PromptFilter {
+    /// feed the output, return the data without prompt and the count of prompts found.
+    fn feed(&mut self, data: &[u8]) -> (Vec<u8>, usize) {
+        let prompt = SUDO_PROMPT.as_bytes();
+        self.pending.extend_from_slice(data);
+        let mut out = vec![];
+        let mut found = 0;
+        while let Some(pos) = self.pending.windows(prompt.len()).position(|w| w == prompt) {
+            out.extend_from_slice(&self.pending[..pos]);
+            self.pending.drain(..pos + prompt.len());
+            found += 1;
+        }
+        // keep the tail which may be the beginning of a prompt
+        let keep = (1..prompt.len().min(self.pending.len() + 1))
+            .rev()
+            .find(|&n| self.pending.ends_with(&prompt[..n]))
+            .unwrap_or(0);
+        let at = self.pending.len() - keep;
+        out.extend(self.pending.drain(..at));
+        (out, found)
+    }
+
+    /// the data left at the end of output.
+    fn flush(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+impl SSHSession {
+    /// execute the command, the output (stderr is merged) is passed to `on_output`,
+    /// return the exit status.
+    /// the command is run by `sudo` on a PTY if the `sudo` password is given,
+    /// the password only answers the prompt and is never passed or logged.
+    pub fn exec(
+        &self,
+        cmd: &str,
+        sudo: Option<&str>,
+        mut on_output: impl FnMut(&[u8]),
+    ) -> Result<i32, Error> {
+        let mut channel = self.session.channel_session()?;
+        channel.handle_extended_data(ssh2::ExtendedData::Merge)?;
+        let mut buf = [0u8; 8192];
+        let password = match sudo {
+            Some(password) => {
+                // sudo reads the password from the terminal only
+                channel.request_pty("xterm", None, None)?;
+                channel.exec(&format!("sudo -p '{SUDO_PROMPT}' sh -c {}", quote(cmd)))?;
+                password
+            }
+            None => {
+                channel.exec(cmd)?;
+                loop {
+                    if let Err(e) = SSHSession::checkpoint() {
+                        let _ = channel.close();
+                        return Err(e);
+                    }
+                    let n = channel.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    on_output(&buf[..n]);
+                }
+                channel.wait_close()?;
+                return Ok(channel.exit_status()?);
+            }
+        };
+
+        let mut filter = PromptFilter::default();
+        let mut answered = false;
+        loop {
+            if let Err(e) = SSHSession::checkpoint() {
+                let _ = channel.close();
+                return Err(e);
+            }
+            let n = channel.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            let (out, found) = filter.feed(&buf[..n]);
+            if !out.is_empty() {
+                on_output(&out);
+            }
+            if found == 0 {
+                continue;
+            }
+            if answered {
+                // the prompt again means the password is wrong
+                let _ = channel.close();
+                return Err(Error::Auth(
+                    "sudo authentication failed, check the (sudo) password".to_string(),
+                ));
+            }
+            debug!("answer the sudo prompt");
+            channel.write_all(format!("{password}\n").as_bytes())?;
+            channel.flush()?;
+            answered = true;
+        }
+        let out = filter.flush();
+        if !out.is_empty() {
+            on_output(&out);
+        }
+        channel.wait_close()?;
+        Ok(channel.exit_status()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_filter() {
+        let mut filter = PromptFilter::default();
+        let (out, found) = filter.feed(b"hello\r\n[atsh-");
+        assert_eq!(out, b"hello\r\n");
+        assert_eq!(found, 0);
+        let (out, found) = filter.feed(b"sudo] password: \r\nroot\r\n[");
+        assert_eq!(out, b"\r\nroot\r\n");
+        assert_eq!(found, 1);
+        // not a prompt at last
+        assert_eq!(filter.flush(), b"[");
+
+        let mut filter = PromptFilter::default();
+        let (out, found) = filter.feed(format!("{SUDO_PROMPT}{SUDO_PROMPT}done").as_bytes());
+        assert_eq!(out, b"done");
+        assert_eq!(found, 2);
+    }
+
+    #[test]
+    fn test_utf8_decoder() {
+        let mut decoder = Utf8Decoder::default();
+        let data = "héllo 你好".as_bytes();
+        assert_eq!(decoder.feed(&data[..2]), "h");
+        assert_eq!(decoder.feed(&data[2..8]), "éllo ");
+        assert_eq!(decoder.feed(&data[8..]), "你好");
+        assert_eq!(decoder.feed(b"\xffok"), "\u{fffd}ok");
+        assert_eq!(decoder.feed(&data[7..8]), "");
+        assert_eq!(decoder.flush(), "\u{fffd}");
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("ls -l"), "'ls -l'");
+        assert_eq!(quote("echo 'a b'"), r"'echo '\''a b'\'''");
+    }
+}
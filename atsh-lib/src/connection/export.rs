@@ -0,0 +1,214 @@
+use crate::error::Error;
+use serde_yaml::value::{Mapping, Tag, TaggedValue};
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+
+use super::import::GroupsAs;
+use super::remote::{AuthMode, Remote, Remotes};
+use super::tags::Target;
+use crate::context::current;
+use crate::storage::vault;
+
+/// The options to export the remote servers as an ansible inventory.
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub target: Target,
+    /// group the hosts by the tags or note.
+    pub group_by: Option<GroupsAs>,
+    /// the vault password to include the encrypted passwords.
+    pub vault: Option<String>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            target: Target::All,
+            group_by: None,
+            vault: None,
+        }
+    }
+}
+
+/// the groups defined by ansible itself.
+const RESERVED: &[&str] = &["all", "ungrouped"];
+
+/// the ansible group name, only `a-z0-9_` are kept.
+fn group_name(s: &str) -> String {
+    let name = s
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{name}")
+    } else {
+        name
+    }
+}
+
+fn vaulted(data: &str, password: &str) -> Value {
+    Value::Tagged(Box::new(TaggedValue {
+        tag: Tag::new("vault"),
+        value: Value::String(vault::encrypt(data, password)),
+    }))
+}
+
+fn host_vars(remote: &Remote, options: &ExportOptions) -> Mapping {
+    let mut vars = Mapping::new();
+    vars.insert("ansible_host".into(), remote.ip.clone().into());
+    vars.insert("ansible_port".into(), Value::Number(remote.port.into()));
+    vars.insert("ansible_user".into(), remote.user.clone().into());
+    // the atsh key is only installed in the `key` mode
+    if remote.mode == AuthMode::Key {
+        let key = current()
+            .config()
+            .get_private()
+            .to_string_lossy()
+            .to_string();
+        vars.insert("ansible_ssh_private_key_file".into(), key.into());
+    }
+    if let Some(password) = &options.vault {
+        vars.insert(
+            "ansible_password".into(),
+            vaulted(&remote.password, password),
+        );
+        if let Some(sudo) = &remote.sudo_password {
+            vars.insert("ansible_become_password".into(), vaulted(sudo, password));
+        }
+    }
+    vars
+}
+
+impl Remotes {
+    /// render the remote servers as an ansible YAML inventory.
+    pub fn ansible(&self, options: &ExportOptions) -> Result<String, Error> {
+        // never export the password encrypted by the `ATSH_KEY`
+        if options.vault.is_some() {
+            current().get_enc_key()?;
+        }
+        let mut hosts = Mapping::new();
+        let mut groups: BTreeMap<String, Mapping> = BTreeMap::new();
+        for remote in self.0.iter() {
+            let mut host = remote
+                .name
+                .clone()
+                .filter(|n| !n.is_empty() && !n.contains(char::is_whitespace))
+                .unwrap_or_else(|| remote.ip.clone());
+            if hosts.contains_key(host.as_str()) {
+                host = format!("{host}_{}", remote.index);
+            }
+            hosts.insert(host.clone().into(), host_vars(remote, options).into());
+            let names = match options.group_by {
+                Some(GroupsAs::Tags) => remote.tags.iter().map(|t| group_name(t)).collect(),
+                Some(GroupsAs::Note) => remote
+                    .note
+                    .iter()
+                    .filter(|n| !n.trim().is_empty())
+                    .map(|n| group_name(n))
+                    .collect(),
+                None => vec![],
+            };
+            for name in names {
+                groups
+                    .entry(name)
+                    .or_default()
+                    .insert(host.clone().into(), Value::Null);
+            }
+        }
+
+        let mut children = Mapping::new();
+        for (name, members) in groups {
+            // the hosts and groups share the names, and `all`/`ungrouped` are reserved
+            let mut name = name;
+            while RESERVED.contains(&name.as_str())
+                || hosts.contains_key(name.as_str())
+                || children.contains_key(name.as_str())
+            {
+                name = format!("{name}_group");
+            }
+            let mut group = Mapping::new();
+            group.insert("hosts".into(), members.into());
+            children.insert(name.into(), group.into());
+        }
+
+        let mut all = Mapping::new();
+        all.insert("hosts".into(), hosts.into());
+        if !children.is_empty() {
+            all.insert("children".into(), children.into());
+        }
+        let mut inventory = Mapping::new();
+        inventory.insert("all".into(), all.into());
+        serde_yaml::to_string(&inventory).map_err(Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ansible() {
+        let remote = |index: usize, name: Option<&str>, tags: &[&str]| Remote {
+            index,
+            user: "root".to_string(),
+            password: "secret".to_string(),
+            ip: format!("10.0.0.{index}"),
+            port: 22,
+            name: name.map(|n| n.to_string()),
+            note: Some("Customer X".to_string()),
+            mode: AuthMode::Password,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        };
+        let remotes = Remotes(vec![
+            remote(1, Some("web"), &["prod", "web-eu"]),
+            remote(2, Some("web"), &["prod"]),
+            remote(3, None, &[]),
+        ]);
+        let mut options = ExportOptions {
+            group_by: Some(GroupsAs::Tags),
+            ..Default::default()
+        };
+        let yaml = remotes.ansible(&options).unwrap();
+        let inventory: Value = serde_yaml::from_str(&yaml).unwrap();
+        let all = &inventory["all"];
+        assert_eq!(all["hosts"]["web"]["ansible_host"], "10.0.0.1");
+        assert_eq!(all["hosts"]["web_2"]["ansible_port"], 22);
+        assert_eq!(all["hosts"]["10.0.0.3"]["ansible_user"], "root");
+        // no key file and password for the `password` mode without vault
+        assert!(all["hosts"]["web"]
+            .get("ansible_ssh_private_key_file")
+            .is_none());
+        assert!(!yaml.contains("secret"));
+        let prod = all["children"]["prod"]["hosts"].as_mapping().unwrap();
+        assert_eq!(prod.len(), 2);
+        assert!(all["children"]["web_eu"]["hosts"]
+            .as_mapping()
+            .unwrap()
+            .contains_key("web"));
+
+        options.group_by = Some(GroupsAs::Note);
+        let yaml = remotes.ansible(&options).unwrap();
+        let inventory: Value = serde_yaml::from_str(&yaml).unwrap();
+        let customer = inventory["all"]["children"]["customer_x"]["hosts"]
+            .as_mapping()
+            .unwrap();
+        assert_eq!(customer.len(), 3);
+
+        // the group never takes the name of a host or `all`
+        let remotes = Remotes(vec![
+            remote(1, Some("web"), &["web", "all"]),
+            remote(2, Some("web_group"), &[]),
+        ]);
+        options.group_by = Some(GroupsAs::Tags);
+        let yaml = remotes.ansible(&options).unwrap();
+        let inventory: Value = serde_yaml::from_str(&yaml).unwrap();
+        let children = inventory["all"]["children"].as_mapping().unwrap();
+        let names = children
+            .keys()
+            .map(|k| k.as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["all_group", "web_group_group"]);
+    }
+}
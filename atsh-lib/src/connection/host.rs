@@ -0,0 +1,109 @@
+use std::borrow::Cow;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::error::Error;
+
+fn invalid(msg: String) -> Error {
+    Error::InvalidInput(msg)
+}
+
+/// the hostname like `db-1.example.com`, see RFC 1123.
+fn is_hostname(host: &str) -> bool {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+/// the IPv6 address, the zone like `fe80::1%eth0` is allowed.
+fn is_ipv6(host: &str) -> bool {
+    let (addr, zone) = host.split_once('%').unwrap_or((host, "a"));
+    addr.parse::<Ipv6Addr>().is_ok()
+        && !zone.is_empty()
+        && zone.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// check the host is an IPv4, IPv6 without the brackets or a hostname.
+pub(crate) fn check_host(host: &str) -> Result<(), Error> {
+    if host.is_empty() {
+        return Err(invalid("missing host".to_string()));
+    }
+    if let Some(inner) = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        return Err(invalid(format!(
+            "the host `{host}` should be `{inner}` without the brackets"
+        )));
+    }
+    if host.contains(':') {
+        if is_ipv6(host) {
+            return Ok(());
+        }
+        return Err(invalid(format!("invalid IPv6 address `{host}`")));
+    }
+    // the dotted numbers are never a hostname, like `10.0.0.256`
+    if host
+        .split('.')
+        .all(|p| p.chars().all(|c| c.is_ascii_digit()))
+    {
+        if host.parse::<Ipv4Addr>().is_ok() {
+            return Ok(());
+        }
+        return Err(invalid(format!("invalid IPv4 address `{host}`")));
+    }
+    if is_hostname(host) {
+        return Ok(());
+    }
+    Err(invalid(format!("invalid host `{host}`")))
+}
+
+/// the host with the port or path like `[::1]:22`, IPv6 is in the brackets.
+pub(crate) fn bracket(host: &str) -> Cow<'_, str> {
+    if host.contains(':') {
+        Cow::Owned(format!("[{host}]"))
+    } else {
+        Cow::Borrowed(host)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_host() {
+        for good in [
+            "10.0.0.1",
+            "::1",
+            "2001:db8::8a2e:370:7334",
+            "fe80::1%eth0",
+            "localhost",
+            "db-1.example.com",
+            "db_1.local.",
+        ] {
+            assert!(check_host(good).is_ok(), "{good}");
+        }
+        for bad in [
+            "",
+            "[::1]",
+            "10.0.0.256",
+            "1.2.3",
+            "2001:db8:::1",
+            "fe80::1%",
+            "-db.example.com",
+            "db..example.com",
+            "db example.com",
+            "user@host",
+        ] {
+            assert!(check_host(bad).is_err(), "{bad}");
+        }
+        assert_eq!(bracket("::1"), "[::1]");
+        assert_eq!(bracket("10.0.0.1"), "10.0.0.1");
+    }
+}
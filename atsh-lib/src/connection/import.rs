@@ -0,0 +1,651 @@
+use crate::error::Error;
+use prettytable::{Cell, Row, Table};
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::remote::Remote;
+use super::tags::check_tag;
+
+/// The format of the import source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// csv with the header like `user,password,ip,port,name,note,tags`.
+    Csv,
+    /// ansible inventory in INI.
+    Ini,
+    /// ansible inventory in YAML.
+    Yaml,
+}
+
+impl FromStr for ImportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ImportFormat::Csv),
+            "ini" => Ok(ImportFormat::Ini),
+            "yaml" | "yml" => Ok(ImportFormat::Yaml),
+            _ => Err(Error::InvalidInput(format!(
+                "unknown import format `{s}`, one of csv/ini/yaml"
+            ))),
+        }
+    }
+}
+
+impl ImportFormat {
+    /// guess by the file extension, the ansible inventory has no extension usually.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => ImportFormat::Csv,
+            Some("yaml" | "yml") => ImportFormat::Yaml,
+            _ => ImportFormat::Ini,
+        }
+    }
+}
+
+/// Where the ansible groups are saved.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GroupsAs {
+    #[default]
+    Tags,
+    Note,
+}
+
+impl FromStr for GroupsAs {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tags" => Ok(GroupsAs::Tags),
+            "note" => Ok(GroupsAs::Note),
+            _ => Err(Error::InvalidInput(format!(
+                "unknown `{s}`, the groups are saved as tags or note"
+            ))),
+        }
+    }
+}
+
+/// One server of the import source.
+#[derive(Debug, Default)]
+pub struct ImportRow {
+    /// the line of csv or the host of ansible inventory.
+    pub source: String,
+    pub remote: Remote,
+    /// why it can not be imported.
+    pub error: Option<String>,
+    /// the index after imported.
+    pub index: Option<usize>,
+}
+
+impl ImportRow {
+    fn new(source: String, remote: Result<Remote, String>) -> Self {
+        match remote {
+            Ok(remote) => ImportRow {
+                source,
+                remote,
+                ..Default::default()
+            },
+            Err(e) => ImportRow {
+                source,
+                error: Some(e),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// read the servers from the file, the format is guessed by the extension if not given.
+pub fn read(
+    path: &Path,
+    format: Option<ImportFormat>,
+    groups: GroupsAs,
+) -> Result<Vec<ImportRow>, Error> {
+    let data = std::fs::read_to_string(path)?;
+    match format.unwrap_or_else(|| ImportFormat::from_path(path)) {
+        ImportFormat::Csv => read_csv(&data),
+        ImportFormat::Ini => Ok(read_ini(&data)?.resolve(groups)),
+        ImportFormat::Yaml => Ok(read_yaml(&data)?.resolve(groups)),
+    }
+}
+
+/// the csv columns, `host` is the same as `ip`.
+const COLUMNS: &[&str] = &[
+    "user",
+    "password",
+    "ip",
+    "host",
+    "port",
+    "name",
+    "note",
+    "mode",
+    "principals",
+    "sudo_password",
+    "tags",
+];
+
+fn read_csv(data: &str) -> Result<Vec<ImportRow>, Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(data.as_bytes());
+    let headers = reader
+        .headers()
+        .map_err(|e| Error::InvalidInput(e.to_string()))?
+        .iter()
+        .map(|h| h.to_lowercase())
+        .collect::<Vec<_>>();
+    if let Some(h) = headers.iter().find(|h| !COLUMNS.contains(&h.as_str())) {
+        return Err(Error::InvalidInput(format!(
+            "unknown column `{h}`, one of {}",
+            COLUMNS.join("/")
+        )));
+    }
+    for required in ["user", "ip|host"] {
+        if !required.split('|').any(|r| headers.iter().any(|h| h == r)) {
+            return Err(Error::InvalidInput(format!(
+                "the column `{required}` is required"
+            )));
+        }
+    }
+    let mut rows = vec![];
+    for record in reader.records() {
+        let record = record.map_err(|e| Error::InvalidInput(e.to_string()))?;
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        let vars = headers
+            .iter()
+            .map(|h| if h == "host" { "ip" } else { h.as_str() })
+            .zip(record.iter())
+            .filter(|(_, v)| !v.is_empty())
+            .collect::<BTreeMap<_, _>>();
+        rows.push(ImportRow::new(format!("line {line}"), csv_remote(&vars)));
+    }
+    Ok(rows)
+}
+
+fn csv_remote(vars: &BTreeMap<&str, &str>) -> Result<Remote, String> {
+    let get = |k: &str| vars.get(k).map(|v| v.to_string());
+    let tags: Vec<String> = get("tags")
+        .map(|t| t.split(',').map(|t| t.trim().to_string()).collect())
+        .unwrap_or_default();
+    for tag in tags.iter() {
+        check_tag(tag).map_err(|e| e.to_string())?;
+    }
+    Ok(Remote {
+        user: get("user").ok_or("missing user")?,
+        password: get("password").unwrap_or_default(),
+        ip: get("ip").ok_or("missing ip")?,
+        port: match vars.get("port") {
+            Some(p) => p.parse().map_err(|_| format!("invalid port `{p}`"))?,
+            None => 22,
+        },
+        name: get("name"),
+        note: get("note"),
+        mode: match vars.get("mode") {
+            Some(m) => m.parse().map_err(|e: Error| e.to_string())?,
+            None => Default::default(),
+        },
+        principals: get("principals"),
+        sudo_password: get("sudo_password"),
+        tags,
+        ..Default::default()
+    })
+}
+
+/// the value of the yaml var tagged `!vault`, it is never a valid value.
+const VAULT: &str = "\0!vault";
+
+/// the vars read from the inventory.
+const KEYS: &[&str] = &[
+    "ansible_user",
+    "ansible_ssh_user",
+    "ansible_password",
+    "ansible_ssh_pass",
+    "ansible_host",
+    "ansible_ssh_host",
+    "ansible_port",
+    "ansible_ssh_port",
+    "ansible_become_password",
+    "ansible_become_pass",
+    "ansible_sudo_pass",
+];
+
+/// The ansible inventory, the hosts are kept in order.
+#[derive(Debug, Default)]
+struct Inventory {
+    hosts: Vec<String>,
+    host_vars: BTreeMap<String, BTreeMap<String, String>>,
+    groups: BTreeMap<String, Group>,
+}
+
+#[derive(Debug, Default)]
+struct Group {
+    hosts: Vec<String>,
+    vars: BTreeMap<String, String>,
+    children: Vec<String>,
+}
+
+impl Inventory {
+    fn add_host(&mut self, group: &str, host: &str, vars: BTreeMap<String, String>) {
+        if !self.host_vars.contains_key(host) {
+            self.hosts.push(host.to_string());
+        }
+        self.host_vars
+            .entry(host.to_string())
+            .or_default()
+            .extend(vars);
+        let group = self.groups.entry(group.to_string()).or_default();
+        if !group.hosts.iter().any(|h| h == host) {
+            group.hosts.push(host.to_string());
+        }
+    }
+
+    /// the groups of the host, the ancestors first.
+    fn groups_of(&self, host: &str) -> Vec<String> {
+        let mut groups = self
+            .groups
+            .iter()
+            .filter(|(_, g)| g.hosts.iter().any(|h| h == host))
+            .map(|(n, _)| n.clone())
+            .collect::<Vec<_>>();
+        let mut i = 0;
+        while i < groups.len() {
+            let parents = self
+                .groups
+                .iter()
+                .filter(|(n, g)| g.children.contains(&groups[i]) && !groups.contains(n))
+                .map(|(n, _)| n.clone())
+                .collect::<Vec<_>>();
+            groups.extend(parents);
+            i += 1;
+        }
+        groups.reverse();
+        groups
+    }
+
+    fn remote(&self, host: &str, groups_as: GroupsAs) -> Result<Remote, String> {
+        let groups = self.groups_of(host);
+        // the host vars override the group vars, and the children override the parents
+        let mut vars = self
+            .groups
+            .get("all")
+            .map(|g| g.vars.clone())
+            .unwrap_or_default();
+        for group in groups.iter() {
+            vars.extend(self.groups[group].vars.clone());
+        }
+        vars.extend(self.host_vars[host].clone());
+        if let Some((key, _)) = vars
+            .iter()
+            .find(|(k, v)| *v == VAULT && KEYS.contains(&k.as_str()))
+        {
+            return Err(format!(
+                "the `!vault` value of `{key}` is not supported, decrypt it by `ansible-vault decrypt` first"
+            ));
+        }
+        let get = |keys: &[&str]| keys.iter().find_map(|k| vars.get(*k).cloned());
+
+        if host.contains('[') {
+            return Err("the host range is not supported".to_string());
+        }
+        let groups = groups
+            .into_iter()
+            .filter(|g| g != "all" && g != "ungrouped")
+            .map(|g| g.to_lowercase())
+            .collect::<Vec<_>>();
+        let mut remote = Remote {
+            user: get(&["ansible_user", "ansible_ssh_user"]).ok_or("missing ansible_user")?,
+            password: get(&["ansible_password", "ansible_ssh_pass"]).unwrap_or_default(),
+            ip: get(&["ansible_host", "ansible_ssh_host"]).unwrap_or_else(|| host.to_string()),
+            port: match get(&["ansible_port", "ansible_ssh_port"]) {
+                Some(p) => p
+                    .parse()
+                    .map_err(|_| format!("invalid ansible_port `{p}`"))?,
+                None => 22,
+            },
+            sudo_password: get(&[
+                "ansible_become_password",
+                "ansible_become_pass",
+                "ansible_sudo_pass",
+            ]),
+            ..Default::default()
+        };
+        if remote.ip != host {
+            remote.name = Some(host.to_string());
+        }
+        match groups_as {
+            GroupsAs::Tags => {
+                for group in groups.iter() {
+                    check_tag(group).map_err(|e| e.to_string())?;
+                }
+                remote.tags = groups;
+            }
+            GroupsAs::Note if !groups.is_empty() => {
+                remote.note = Some(format!("groups: {}", groups.join(",")));
+            }
+            GroupsAs::Note => {}
+        }
+        Ok(remote)
+    }
+
+    fn resolve(&self, groups_as: GroupsAs) -> Vec<ImportRow> {
+        self.hosts
+            .iter()
+            .map(|host| ImportRow::new(host.clone(), self.remote(host, groups_as)))
+            .collect()
+    }
+}
+
+/// split the line by whitespace, the quoted value is kept as one.
+fn split_args(line: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut quote = None;
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '"' | '\'') => quote = Some(c),
+            (None, c) if c.is_whitespace() => {
+                if !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                }
+            }
+            (None, c) => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        args.push(current);
+    }
+    args
+}
+
+fn unquote(s: &str) -> String {
+    split_args(s).join(" ")
+}
+
+fn read_ini(data: &str) -> Result<Inventory, Error> {
+    let mut inventory = Inventory::default();
+    let (mut group, mut kind) = ("ungrouped".to_string(), "hosts".to_string());
+    for (n, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            (group, kind) = match section.split_once(':') {
+                Some((g, k)) => (g.to_string(), k.to_string()),
+                None => (section.to_string(), "hosts".to_string()),
+            };
+            inventory.groups.entry(group.clone()).or_default();
+            continue;
+        }
+        let invalid = |msg: &str| Error::InvalidInput(format!("line {}: {msg} `{line}`", n + 1));
+        match kind.as_str() {
+            "hosts" => {
+                let args = split_args(line);
+                let Some((host, rest)) = args.split_first() else {
+                    return Err(invalid("missing host"));
+                };
+                let mut vars = BTreeMap::new();
+                for arg in rest.iter() {
+                    let (k, v) = arg.split_once('=').ok_or_else(|| invalid("invalid var"))?;
+                    vars.insert(k.to_string(), v.to_string());
+                }
+                inventory.add_host(&group, host, vars);
+            }
+            "vars" => {
+                let (k, v) = line.split_once('=').ok_or_else(|| invalid("invalid var"))?;
+                inventory
+                    .groups
+                    .entry(group.clone())
+                    .or_default()
+                    .vars
+                    .insert(k.trim().to_string(), unquote(v.trim()));
+            }
+            "children" => {
+                let children = &mut inventory.groups.entry(group.clone()).or_default().children;
+                children.push(line.to_string());
+            }
+            _ => return Err(invalid("unknown section")),
+        }
+    }
+    Ok(inventory)
+}
+
+fn yaml_vars(value: &Value) -> BTreeMap<String, String> {
+    let text = |v: &Value| match v {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        // never read the encrypted value as the plain one
+        Value::Tagged(t) if t.tag == "vault" => Some(VAULT.to_string()),
+        _ => None,
+    };
+    value
+        .as_mapping()
+        .map(|m| {
+            m.iter()
+                .filter_map(|(k, v)| Some((text(k)?, text(v)?)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn walk_yaml(inventory: &mut Inventory, name: &str, group: &Value) {
+    inventory.groups.entry(name.to_string()).or_default();
+    if let Some(hosts) = group.get("hosts").and_then(|h| h.as_mapping()) {
+        for (host, vars) in hosts.iter() {
+            if let Some(host) = host.as_str() {
+                inventory.add_host(name, host, yaml_vars(vars));
+            }
+        }
+    }
+    if let Some(vars) = group.get("vars") {
+        let vars = yaml_vars(vars);
+        inventory
+            .groups
+            .entry(name.to_string())
+            .or_default()
+            .vars
+            .extend(vars);
+    }
+    if let Some(children) = group.get("children").and_then(|c| c.as_mapping()) {
+        for (child, value) in children.iter() {
+            if let Some(child) = child.as_str() {
+                let group = inventory.groups.entry(name.to_string()).or_default();
+                group.children.push(child.to_string());
+                walk_yaml(inventory, child, value);
+            }
+        }
+    }
+}
+
+fn read_yaml(data: &str) -> Result<Inventory, Error> {
+    let value: Value =
+        serde_yaml::from_str(data).map_err(|e| Error::InvalidInput(e.to_string()))?;
+    let groups = value.as_mapping().ok_or_else(|| {
+        Error::InvalidInput("the inventory is not a mapping of groups".to_string())
+    })?;
+    let mut inventory = Inventory::default();
+    for (name, group) in groups.iter() {
+        if let Some(name) = name.as_str() {
+            walk_yaml(&mut inventory, name, group);
+        }
+    }
+    Ok(inventory)
+}
+
+pub fn pprint(rows: &[ImportRow]) {
+    let mut table = Table::new();
+    let titles = [
+        "source", "user", "ip", "port", "name", "tags", "note", "status",
+    ];
+    table.set_titles(Row::new(
+        titles
+            .iter()
+            .map(|v| Cell::new(v).style_spec("bcFg"))
+            .collect::<Vec<Cell>>(),
+    ));
+    for row in rows.iter() {
+        let remote = &row.remote;
+        let mut cells = vec![Cell::new(&row.source).style_spec("lFc")];
+        if let Some(e) = &row.error {
+            cells.push(Cell::new(e).style_spec("lFrH7"));
+            table.add_row(Row::new(cells));
+            continue;
+        }
+        cells.extend(
+            [
+                remote.user.clone(),
+                remote.ip.clone(),
+                remote.port.to_string(),
+                remote.name.clone().unwrap_or_default(),
+                remote.tags.join(","),
+                remote.note.clone().unwrap_or_default(),
+            ]
+            .iter()
+            .map(|v| Cell::new(v).style_spec("lFc")),
+        );
+        let status = match row.index {
+            Some(index) => format!("added {index}"),
+            None => "new".to_string(),
+        };
+        cells.push(Cell::new(&status).style_spec("lFg"));
+        table.add_row(Row::new(cells));
+    }
+    table.printstd();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::AuthMode;
+
+    #[test]
+    fn test_read_csv() {
+        let data = "user,host,password,port,tags,mode\n\
+                    root,10.0.0.1,pw,2222,\"prod,db\",password\n\
+                    ,10.0.0.2,pw,,,\n\
+                    root,10.0.0.3,,abc,,\n";
+        let rows = read_csv(data).unwrap();
+        assert_eq!(rows.len(), 3);
+        let remote = &rows[0].remote;
+        assert_eq!(rows[0].source, "line 2");
+        assert_eq!((remote.ip.as_str(), remote.port), ("10.0.0.1", 2222));
+        assert_eq!(remote.tags, vec!["prod", "db"]);
+        assert_eq!(remote.mode, AuthMode::Password);
+        assert_eq!(rows[1].error.as_deref(), Some("missing user"));
+        assert_eq!(rows[2].error.as_deref(), Some("invalid port `abc`"));
+
+        assert!(read_csv("user,address\nroot,1.1.1.1\n").is_err());
+        assert!(read_csv("user,port\nroot,22\n").is_err());
+    }
+
+    #[test]
+    fn test_read_ini() {
+        let data = r#"
+# the customer x
+jump ansible_host=10.0.0.9 ansible_user=admin
+
+[web]
+web1 ansible_host=10.0.0.1 ansible_password='p w'
+web2 ansible_host=10.0.0.2 ansible_port=2222
+
+[db]
+10.0.0.3 ansible_become_pass=sudo
+
+[prod:children]
+web
+db
+
+[prod:vars]
+ansible_user=deploy
+ansible_password=secret
+
+[all:vars]
+ansible_user=root
+"#;
+        let inventory = read_ini(data).unwrap();
+        let rows = inventory.resolve(GroupsAs::Tags);
+        let hosts = rows.iter().map(|r| r.source.as_str()).collect::<Vec<_>>();
+        assert_eq!(hosts, vec!["jump", "web1", "web2", "10.0.0.3"]);
+        assert!(rows.iter().all(|r| r.error.is_none()));
+
+        let jump = &rows[0].remote;
+        assert_eq!((jump.user.as_str(), jump.password.as_str()), ("admin", ""));
+        assert!(jump.tags.is_empty());
+        let web1 = &rows[1].remote;
+        assert_eq!(web1.name.as_deref(), Some("web1"));
+        assert_eq!(
+            (web1.user.as_str(), web1.password.as_str()),
+            ("deploy", "p w")
+        );
+        assert_eq!(web1.tags, vec!["prod", "web"]);
+        assert_eq!(rows[2].remote.port, 2222);
+        let db = &rows[3].remote;
+        assert_eq!((db.ip.as_str(), db.name.as_deref()), ("10.0.0.3", None));
+        assert_eq!(db.sudo_password.as_deref(), Some("sudo"));
+
+        let rows = inventory.resolve(GroupsAs::Note);
+        assert_eq!(rows[1].remote.note.as_deref(), Some("groups: prod,web"));
+        assert!(rows[1].remote.tags.is_empty());
+
+        assert!(read_ini("[web]\nweb1 ansible_host").is_err());
+        assert!(read_ini("[web]\n''").is_err());
+        assert!(read_ini("[web]\n\"\"").is_err());
+        let rows = read_ini("[1web]\nweb1 ansible_user=root")
+            .unwrap()
+            .resolve(GroupsAs::Tags);
+        assert!(rows[0].error.is_some());
+    }
+
+    #[test]
+    fn test_read_yaml() {
+        let data = r#"
+all:
+  vars:
+    ansible_user: root
+  hosts:
+    jump:
+      ansible_host: 10.0.0.9
+  children:
+    prod:
+      vars:
+        ansible_password: secret
+      children:
+        web:
+          hosts:
+            web1:
+              ansible_host: 10.0.0.1
+              ansible_port: 2222
+            web2:
+"#;
+        let rows = read_yaml(data).unwrap().resolve(GroupsAs::Tags);
+        let hosts = rows.iter().map(|r| r.source.as_str()).collect::<Vec<_>>();
+        assert_eq!(hosts, vec!["jump", "web1", "web2"]);
+        assert!(rows[0].remote.tags.is_empty());
+        let web1 = &rows[1].remote;
+        assert_eq!((web1.ip.as_str(), web1.port), ("10.0.0.1", 2222));
+        assert_eq!(
+            (web1.user.as_str(), web1.password.as_str()),
+            ("root", "secret")
+        );
+        assert_eq!(web1.tags, vec!["prod", "web"]);
+        assert_eq!(rows[2].remote.ip, "web2");
+
+        assert!(read_yaml("- web1").is_err());
+
+        let data = r#"
+web:
+  hosts:
+    web1:
+      ansible_user: root
+      ansible_password: !vault |
+        $ANSIBLE_VAULT;1.1;AES256
+        6162
+"#;
+        let rows = read_yaml(data).unwrap().resolve(GroupsAs::Tags);
+        assert!(rows[0].error.as_deref().unwrap().contains("!vault"));
+    }
+}
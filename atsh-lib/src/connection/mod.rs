@@ -1,5 +1,29 @@
+mod audit;
+mod edit;
+mod ephemeral;
+mod exec;
+mod export;
+mod host;
+mod import;
+mod options;
+mod otp;
+mod output;
+mod proxy;
 mod remote;
+mod sftp;
+mod shell;
 mod ssh;
+mod tags;
+mod uri;
 
-pub use remote::Remote;
+pub use audit::{pprint as pprint_audit, AuthorizedKey, Grant, KeyAudit, KeyStatus};
+pub use edit::RemoteEdit;
+pub use exec::ExecOutput;
+pub use export::ExportOptions;
+pub use import::{pprint as pprint_import, read as read_import, GroupsAs, ImportFormat, ImportRow};
+pub use options::ConnectOptions;
+pub use output::{Field, Format, ListOptions};
+pub use proxy::{proxy_tunnel, Proxy, ProxyKind, PROXY_TUNNEL};
 pub(crate) use remote::Remotes;
+pub use remote::{AuthMode, Outcome, Remote};
+pub use tags::{TagExpr, Target};
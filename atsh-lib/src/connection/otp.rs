@@ -0,0 +1,144 @@
+use crate::error::Error;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use ssh2::{KeyboardInteractivePrompt, Prompt};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+/// the time step of the code, RFC 6238 default.
+const STEP: u64 = 30;
+/// the digits of the code, Google Authenticator default.
+const DIGITS: u32 = 6;
+
+/// decode the base32 (RFC 4648) seed, the spaces, dashes and padding are ignored.
+fn base32_decode(seed: &str) -> Option<Vec<u8>> {
+    let mut bits = 0u64;
+    let mut len = 0;
+    let mut out = vec![];
+    for c in seed.chars() {
+        let v = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u64 - 'A' as u64,
+            c @ '2'..='7' => c as u64 - '2' as u64 + 26,
+            ' ' | '-' | '=' => continue,
+            _ => return None,
+        };
+        bits = (bits << 5) | v;
+        len += 5;
+        if len >= 8 {
+            len -= 8;
+            out.push((bits >> len) as u8);
+            bits &= (1 << len) - 1;
+        }
+    }
+    if out.is_empty() {
+        return None;
+    }
+    Some(out)
+}
+
+/// the code of the base32 `seed` at the unix `time`.
+pub fn totp_at(seed: &str, time: u64) -> Result<String, Error> {
+    let key = base32_decode(seed)
+        .ok_or_else(|| Error::InvalidInput("invalid base32 TOTP seed".to_string()))?;
+    let mut mac = Hmac::<Sha1>::new_from_slice(&key).map_err(Error::other)?;
+    mac.update(&(time / STEP).to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    // dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let code = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    Ok(format!(
+        "{:0width$}",
+        code % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    ))
+}
+
+/// the current code of the base32 `seed`.
+pub fn totp(seed: &str) -> Result<String, Error> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(Error::other)?
+        .as_secs();
+    totp_at(seed, now)
+}
+
+/// Answer the keyboard-interactive prompts with the password and TOTP code.
+pub struct Responder<'a> {
+    pub password: &'a str,
+    pub totp: Option<&'a str>,
+}
+
+impl KeyboardInteractivePrompt for Responder<'_> {
+    fn prompt<'b>(
+        &mut self,
+        _username: &str,
+        instructions: &str,
+        prompts: &[Prompt<'b>],
+    ) -> Vec<String> {
+        debug!(
+            instructions = instructions,
+            total = prompts.len(),
+            "keyboard-interactive"
+        );
+        prompts
+            .iter()
+            .map(|p| {
+                let text = p.text.to_lowercase();
+                // check the code first, like `One-time password:`
+                let otp = ["verification", "code", "otp", "token", "one-time"];
+                if otp.iter().any(|k| text.contains(k)) {
+                    return match self.totp.map(totp) {
+                        Some(Ok(code)) => code,
+                        Some(Err(e)) => {
+                            warn!(error = ?e, "failed to generate TOTP code");
+                            String::new()
+                        }
+                        None => {
+                            warn!(prompt = p.text.trim(), "no TOTP seed to answer");
+                            String::new()
+                        }
+                    };
+                }
+                if text.contains("password") {
+                    return self.password.to_string();
+                }
+                // the unknown secret prompt is most likely the password
+                if !p.echo {
+                    debug!(
+                        prompt = p.text.trim(),
+                        "answer unknown prompt with password"
+                    );
+                    return self.password.to_string();
+                }
+                warn!(prompt = p.text.trim(), "unknown prompt, answer empty");
+                String::new()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_totp() {
+        // RFC 6238 test seed `12345678901234567890` in base32
+        let seed = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+        assert_eq!(totp_at(seed, 59).unwrap(), "287082");
+        assert_eq!(totp_at(seed, 1111111109).unwrap(), "081804");
+        assert_eq!(totp_at(seed, 20000000000).unwrap(), "353130");
+        // lowercase and spaces are accepted
+        assert_eq!(
+            totp_at("gezd gnbv gy3t qojq gezd gnbv gy3t qojq", 59).unwrap(),
+            "287082"
+        );
+        assert!(totp_at("not-base32!", 59).is_err());
+        assert_eq!(totp(seed).unwrap().len(), 6);
+    }
+}
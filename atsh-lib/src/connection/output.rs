@@ -0,0 +1,399 @@
+use crate::error::Error;
+use prettytable::{Cell, Row, Table};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use super::remote::{Remote, Remotes};
+use super::tags::Target;
+use crate::context::current;
+
+/// The output format of the remote servers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Table,
+    Json,
+    Yaml,
+    Csv,
+    Tsv,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            _ => Err(Error::InvalidInput(format!(
+                "unknown format `{s}`, one of table/json/yaml/csv/tsv"
+            ))),
+        }
+    }
+}
+
+/// The field of the remote server, the name is stable for scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Index,
+    Name,
+    User,
+    Ip,
+    Port,
+    Tags,
+    Password,
+    Authorized,
+    Mode,
+    Principals,
+    Note,
+}
+
+const FIELDS: &[(Field, &str)] = &[
+    (Field::Index, "index"),
+    (Field::Name, "name"),
+    (Field::User, "user"),
+    (Field::Ip, "ip"),
+    (Field::Port, "port"),
+    (Field::Tags, "tags"),
+    (Field::Password, "password"),
+    (Field::Authorized, "authorized"),
+    (Field::Mode, "mode"),
+    (Field::Principals, "principals"),
+    (Field::Note, "note"),
+];
+
+impl Field {
+    pub fn name(&self) -> &'static str {
+        FIELDS
+            .iter()
+            .find(|(f, _)| f == self)
+            .map(|(_, n)| *n)
+            .unwrap_or_default()
+    }
+
+    /// the default fields, the privacy ones only if `all`.
+    fn defaults(all: bool) -> Vec<Field> {
+        let mut fields = vec![
+            Field::Index,
+            Field::Name,
+            Field::User,
+            Field::Ip,
+            Field::Port,
+            Field::Tags,
+        ];
+        if all {
+            fields.extend([Field::Password, Field::Authorized, Field::Mode, Field::Note]);
+        }
+        fields
+    }
+
+    fn value(&self, remote: &Remote) -> Value {
+        let optional = |v: &Option<String>| v.clone().map(Value::String).unwrap_or(Value::Null);
+        match self {
+            Field::Index => remote.index.into(),
+            Field::Name => optional(&remote.name),
+            Field::User => remote.user.clone().into(),
+            Field::Ip => remote.ip.clone().into(),
+            Field::Port => remote.port.into(),
+            Field::Tags => remote.tags.clone().into(),
+            Field::Password => remote.password.clone().into(),
+            Field::Authorized => remote.authorized.into(),
+            Field::Mode => remote.mode.to_string().into(),
+            Field::Principals => optional(&remote.principals),
+            Field::Note => optional(&remote.note),
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        FIELDS
+            .iter()
+            .find(|(_, n)| *n == s)
+            .map(|(f, _)| *f)
+            .ok_or_else(|| {
+                let names = FIELDS.iter().map(|(_, n)| *n).collect::<Vec<_>>();
+                Error::InvalidInput(format!("unknown field `{s}`, one of {}", names.join("/")))
+            })
+    }
+}
+
+/// The options to list the remote servers.
+#[derive(Debug, Clone)]
+pub struct ListOptions {
+    pub target: Target,
+    pub format: Format,
+    /// show the privacy fields like password.
+    pub all: bool,
+    /// the fields to output, the default fields if empty.
+    pub fields: Vec<Field>,
+    /// sort by the field, descending if `reverse`.
+    pub sort: Option<Field>,
+    pub reverse: bool,
+    /// keep the servers which the field contains the value (case insensitive).
+    pub filters: Vec<(Field, String)>,
+}
+
+impl Default for ListOptions {
+    fn default() -> Self {
+        ListOptions {
+            target: Target::All,
+            format: Format::default(),
+            all: false,
+            fields: vec![],
+            sort: None,
+            reverse: false,
+            filters: vec![],
+        }
+    }
+}
+
+/// the text of the value in table, csv and tsv.
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(v) => v.iter().map(text).collect::<Vec<_>>().join(","),
+        v => v.to_string(),
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a
+            .as_u64()
+            .unwrap_or_default()
+            .cmp(&b.as_u64().unwrap_or_default()),
+        _ => text(a).cmp(&text(b)),
+    }
+}
+
+/// the selected fields and values of one remote server.
+type Fields = Vec<(Field, Value)>;
+
+/// serialize the row as a map in the fields order.
+struct Record<'a>(&'a [(Field, Value)]);
+
+impl Serialize for Record<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (field, value) in self.0.iter() {
+            map.serialize_entry(field.name(), value)?;
+        }
+        map.end()
+    }
+}
+
+fn delimited(fields: &[Field], rows: &[Fields], delimiter: u8) -> Result<String, Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(vec![]);
+    writer
+        .write_record(fields.iter().map(|f| f.name()))
+        .map_err(Error::other)?;
+    for row in rows.iter() {
+        writer
+            .write_record(row.iter().map(|(_, v)| text(v)))
+            .map_err(Error::other)?;
+    }
+    let data = writer.into_inner().map_err(Error::other)?;
+    String::from_utf8(data).map_err(Error::other)
+}
+
+/// a part of the password, sliced by characters.
+fn mask_password(value: &str) -> String {
+    let chars = value.chars().collect::<Vec<_>>();
+    if chars.len() <= 8 {
+        return value.to_string();
+    }
+    let head = chars[..3].iter().collect::<String>();
+    let tail = chars[chars.len() - 5..].iter().collect::<String>();
+    format!("{head}..{tail}")
+}
+
+fn table(fields: &[Field], rows: &[Fields], mask: bool) -> Table {
+    let mut table = Table::new();
+    table.set_titles(Row::new(
+        fields
+            .iter()
+            .map(|f| Cell::new(f.name()).style_spec("bcFg"))
+            .collect(),
+    ));
+    for row in rows.iter() {
+        table.add_row(Row::new(
+            row.iter()
+                .map(|(field, value)| {
+                    let mut value = text(value);
+                    // only show a part of the password without `ATSH_KEY`
+                    if mask && *field == Field::Password {
+                        value = mask_password(&value);
+                    }
+                    Cell::new(&value).style_spec("lFc")
+                })
+                .collect(),
+        ));
+    }
+    table
+}
+
+impl Remotes {
+    /// the rows of the selected fields after filter and sort.
+    fn rows(&self, options: &ListOptions) -> Result<(Vec<Field>, Vec<Fields>), Error> {
+        let fields = if options.fields.is_empty() {
+            Field::defaults(options.all)
+        } else {
+            options.fields.clone()
+        };
+        if !options.all && fields.contains(&Field::Password) {
+            return Err(Error::InvalidInput(
+                "the `password` field is only output with `--all`".to_string(),
+            ));
+        }
+        // the order or matches reveal the password too
+        let filtered = options.filters.iter().any(|(f, _)| *f == Field::Password);
+        if !options.all && (filtered || options.sort == Some(Field::Password)) {
+            return Err(Error::InvalidInput(
+                "the `password` field is only filtered or sorted with `--all`".to_string(),
+            ));
+        }
+        let mut remotes = self
+            .0
+            .iter()
+            .filter(|r| {
+                options.filters.iter().all(|(field, value)| {
+                    text(&field.value(r))
+                        .to_lowercase()
+                        .contains(&value.to_lowercase())
+                })
+            })
+            .collect::<Vec<_>>();
+        if let Some(sort) = options.sort {
+            remotes.sort_by(|a, b| compare(&sort.value(a), &sort.value(b)));
+        }
+        if options.reverse {
+            remotes.reverse();
+        }
+        let rows = remotes
+            .iter()
+            .map(|r| fields.iter().map(|f| (*f, f.value(r))).collect())
+            .collect();
+        Ok((fields, rows))
+    }
+
+    /// render the remote servers in the machine-readable format.
+    pub fn render(&self, options: &ListOptions) -> Result<String, Error> {
+        let (fields, rows) = self.rows(options)?;
+        // never output the password encrypted or broken
+        if fields.contains(&Field::Password) {
+            current().get_enc_key()?;
+        }
+        let records = rows.iter().map(|r| Record(r)).collect::<Vec<_>>();
+        match options.format {
+            Format::Json => serde_json::to_string_pretty(&records).map_err(Error::other),
+            Format::Yaml => serde_yaml::to_string(&records).map_err(Error::other),
+            Format::Csv => delimited(&fields, &rows, b','),
+            Format::Tsv => delimited(&fields, &rows, b'\t'),
+            Format::Table => Ok(table(&fields, &rows, false).to_string()),
+        }
+    }
+
+    /// print the remote servers, the table is printed with colors.
+    pub fn print(&self, options: &ListOptions) -> Result<(), Error> {
+        if options.format != Format::Table {
+            let data = self.render(options)?;
+            if data.ends_with('\n') {
+                print!("{data}");
+            } else {
+                println!("{data}");
+            }
+            return Ok(());
+        }
+        let (fields, rows) = self.rows(options)?;
+        table(&fields, &rows, current().get_enc_key().is_err()).printstd();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remotes() -> Remotes {
+        let remote = |index: usize, ip: &str, port: u16, tags: &[&str]| Remote {
+            index,
+            user: "root".to_string(),
+            password: "secret".to_string(),
+            ip: ip.to_string(),
+            port,
+            name: Some(format!("srv{index}")),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        };
+        Remotes(vec![
+            remote(1, "10.0.0.2", 2222, &["prod", "db"]),
+            remote(2, "10.0.0.1", 22, &[]),
+        ])
+    }
+
+    #[test]
+    fn test_render() {
+        let remotes = remotes();
+        let mut options = ListOptions {
+            format: Format::Csv,
+            fields: vec![Field::Index, Field::Ip, Field::Tags, Field::Note],
+            sort: Some(Field::Port),
+            ..Default::default()
+        };
+        assert_eq!(
+            remotes.render(&options).unwrap(),
+            "index,ip,tags,note\n2,10.0.0.1,,\n1,10.0.0.2,\"prod,db\",\n"
+        );
+
+        options.format = Format::Tsv;
+        options.reverse = true;
+        options.filters = vec![(Field::Ip, "0.0.2".to_string())];
+        assert_eq!(
+            remotes.render(&options).unwrap(),
+            "index\tip\ttags\tnote\n1\t10.0.0.2\tprod,db\t\n"
+        );
+
+        options.format = Format::Json;
+        options.filters.clear();
+        let json: Value = serde_json::from_str(&remotes.render(&options).unwrap()).unwrap();
+        assert_eq!(json[0]["index"], 1);
+        assert_eq!(json[0]["tags"][1], "db");
+        assert_eq!(json[1]["note"], Value::Null);
+
+        options.format = Format::Yaml;
+        let yaml = remotes.render(&options).unwrap();
+        assert!(yaml.starts_with("- index: 1\n  ip: 10.0.0.2\n"));
+
+        // the password needs `--all`
+        options.fields = vec![Field::Password];
+        assert!(remotes.render(&options).is_err());
+        options.fields = vec![Field::Index];
+        options.sort = Some(Field::Password);
+        assert!(remotes.render(&options).is_err());
+        options.sort = None;
+        options.filters = vec![(Field::Password, "sec".to_string())];
+        assert!(remotes.render(&options).is_err());
+        assert!(!remotes
+            .render(&ListOptions::default())
+            .unwrap()
+            .contains("secret"));
+    }
+
+    #[test]
+    fn test_mask_password() {
+        assert_eq!(mask_password("short"), "short");
+        assert_eq!(mask_password("0123456789"), "012..56789");
+        assert_eq!(mask_password("密码密码密码密码密码"), "密码密..码密码密码");
+    }
+}
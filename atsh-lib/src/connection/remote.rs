@@ -1,18 +1,85 @@
-use prettytable::{Cell, Row, Table};
+use crate::error::Error;
+use crate::event::{Event, Operation};
+use rusqlite::Connection;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::io::{BufRead, BufReader};
-use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tracing::{debug, info, warn};
 
+use super::audit::{audit, AuthorizedKey, Grant, KeyAudit, KeyStatus};
+use super::ephemeral::{host_id, living_ids, SessionKey};
+use super::exec::{ExecOutput, Utf8Decoder};
+use super::host::{bracket, check_host};
+use super::import::ImportRow;
+use super::options::ConnectOptions;
+use super::otp::totp;
 use super::ssh::SSHSession;
-use crate::config::CONFIG;
+use super::tags::{check_tag, Target};
+use crate::config::{current_profile, profile_dir};
+use crate::context::current;
 use crate::storage::db::{
-    delete_index, get_connection, insert, query_all, query_index, update_authorized,
+    check_key, db_init, delete_grant, delete_index, get_connection, insert, insert_by,
+    insert_grant, query_all, query_grants, query_index, query_target, update, update_authorized,
 };
 use crate::storage::secure::{decrypt, encrypt};
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+/// The result of one remote server in the command on multiple servers.
+#[derive(Debug, Serialize)]
+pub struct Outcome<T> {
+    pub index: usize,
+    pub name: Option<String>,
+    /// the remote server like `user@ip:port`.
+    pub remote: String,
+    /// the result if success.
+    pub value: Option<T>,
+    /// the error if failed, like server unreachable.
+    pub error: Option<String>,
+}
+
+/// How the remote server is logged in without password.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    /// install the atsh public key to `authorized_keys` once.
+    #[default]
+    Key,
+    /// install a fresh key pair for every session and remove it on logout.
+    Ephemeral,
+    /// trust the atsh CA once and login with a short-lived certificate.
+    Certificate,
+    /// login with the stored password over ssh2, never touch the remote key files.
+    Password,
+}
+
+impl std::fmt::Display for AuthMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AuthMode::Key => write!(f, "key"),
+            AuthMode::Ephemeral => write!(f, "ephemeral"),
+            AuthMode::Certificate => write!(f, "certificate"),
+            AuthMode::Password => write!(f, "password"),
+        }
+    }
+}
+
+impl FromStr for AuthMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "key" => Ok(AuthMode::Key),
+            "ephemeral" => Ok(AuthMode::Ephemeral),
+            "certificate" | "cert" => Ok(AuthMode::Certificate),
+            "password" => Ok(AuthMode::Password),
+            _ => Err(Error::InvalidInput(format!("unknown auth mode `{s}`"))),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Remote {
     /// the index of the remote server.
     // #[serde(rename = "idx")]
@@ -22,7 +89,7 @@ pub struct Remote {
     /// the login password.
     #[serde(deserialize_with = "depass", serialize_with = "enpass")]
     pub password: String,
-    /// the login id address.
+    /// the login host, IPv4, IPv6 without brackets or hostname.
     pub ip: String,
     /// the login port.
     pub port: u16,
@@ -32,6 +99,32 @@ pub struct Remote {
     pub name: Option<String>,
     /// the note for the server.
     pub note: Option<String>,
+    /// the way to login without password.
+    #[serde(default)]
+    pub mode: AuthMode,
+    /// the certificate principals separated by comma, default is the login user.
+    #[serde(default)]
+    pub principals: Option<String>,
+    /// the base32 TOTP seed to answer the one-time password prompt.
+    #[serde(
+        default,
+        deserialize_with = "depass_opt",
+        serialize_with = "enpass_opt"
+    )]
+    pub totp: Option<String>,
+    /// the sudo password if it is different from the login password.
+    #[serde(
+        default,
+        deserialize_with = "depass_opt",
+        serialize_with = "enpass_opt"
+    )]
+    pub sudo_password: Option<String>,
+    /// the tags to group the servers, like `prod`, `db`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// the options to connect the server over the `[connection]` of `config.toml`.
+    #[serde(default, skip_serializing_if = "ConnectOptions::is_empty")]
+    pub options: ConnectOptions,
 }
 
 fn depass<'de, D>(deserializer: D) -> Result<String, D::Error>
@@ -39,7 +132,7 @@ where
     D: Deserializer<'de>,
 {
     let password = String::deserialize(deserializer)?;
-    Ok(decrypt(&password))
+    decrypt(&password).map_err(serde::de::Error::custom)
 }
 
 fn enpass<S>(password: &String, serializer: S) -> Result<S::Ok, S::Error>
@@ -49,159 +142,661 @@ where
     Serialize::serialize(&encrypt(password), serializer)
 }
 
+/// the local user who run the atsh, used for logging.
+fn local_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+fn depass_opt<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let secret = Option::<String>::deserialize(deserializer)?;
+    secret
+        .map(decrypt)
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+fn enpass_opt<S>(secret: &Option<String>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    Serialize::serialize(&secret.as_ref().map(encrypt), serializer)
+}
+
 // impl display for Remote
 impl std::fmt::Display for Remote {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}@{}:{}", self.user, self.ip, self.port,)
+        write!(f, "{}@{}:{}", self.user, bracket(&self.ip), self.port)
+    }
+}
+
+/// the bytes of the local file or directory, the symlinked directories are skipped.
+fn local_size(path: &Path) -> u64 {
+    let Ok(meta) = std::fs::metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
     }
+    std::fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| !(e.file_type().is_ok_and(|t| t.is_symlink()) && e.path().is_dir()))
+        .map(|e| local_size(&e.path()))
+        .sum()
 }
 
 impl Remote {
+    /// emit the event of finished or failed by the result of the operation.
+    fn observe<T>(&self, operation: Operation, result: Result<T, Error>) -> Result<T, Error> {
+        let remote = self.to_string();
+        current().emit(match &result {
+            Ok(_) => Event::Finished { remote, operation },
+            Err(e) => Event::Failed {
+                remote,
+                operation,
+                error: e.to_string(),
+            },
+        });
+        result
+    }
+
+    /// check the record before it is added.
+    fn check_new(&self, conn: &Connection) -> Result<(), Error> {
+        check_host(&self.ip)?;
+        self.options.check_record()?;
+        if let Some(seed) = &self.totp {
+            // make sure the seed is valid before saved
+            totp(seed)?;
+        }
+        for tag in self.tags.iter() {
+            check_tag(tag)?;
+        }
+        // the same `user@ip:port` is added only once
+        if let Some(idx) = query_target(conn, &self.user, &self.ip, self.port)? {
+            return Err(Error::InvalidInput(format!(
+                "{self} already exists as index {idx}"
+            )));
+        }
+        Ok(())
+    }
+
     pub fn add_record(&self) -> Result<usize, Error> {
         // Force check the ATSH_KEY exist or not
-        CONFIG.get_enc_key()?;
+        current().get_enc_key()?;
+        // the key is never installed on a new record
+        let remote = Remote {
+            authorized: false,
+            ..self.clone()
+        };
         let n = {
-            let conn = get_connection().lock();
-            insert(&conn, &self)
-        }
-        .map_err(|e| Error::new(std::io::ErrorKind::Other, e))?;
+            let conn = get_connection()?.lock();
+            remote.check_new(&conn)?;
+            insert(&conn, &remote)?
+        };
         info!(remote = self.to_string(), "success add record");
         Ok(n)
     }
 
+    /// check the password (and TOTP) by opening a session, nothing is changed on the server.
+    pub fn verify(&self) -> Result<(), Error> {
+        self.observe(Operation::Verify, self.run_verify())
+    }
+
+    fn run_verify(&self) -> Result<(), Error> {
+        SSHSession::new(self)?;
+        info!(remote = self.to_string(), "success verify login");
+        Ok(())
+    }
+
+    /// update the record by index, include the tags.
+    pub fn update_record(&self) -> Result<(), Error> {
+        // the secrets are never saved in plaintext without ATSH_KEY
+        current().get_enc_key()?;
+        check_host(&self.ip)?;
+        self.options.check_record()?;
+        if let Some(seed) = &self.totp {
+            totp(seed)?;
+        }
+        for tag in self.tags.iter() {
+            check_tag(tag)?;
+        }
+        let n = {
+            let conn = get_connection()?.lock();
+            update(&conn, self)
+        }?;
+        if n == 0 {
+            return Err(Error::RemoteNotFound(self.index));
+        }
+        info!(remote = self.to_string(), "success update record");
+        Ok(())
+    }
+
     pub fn delete_record(&self) -> Result<(), Error> {
         // 删除数据库
-        let conn = get_connection().lock();
-        delete_index(&conn, self.index).map_err(|e| Error::new(std::io::ErrorKind::Other, e))?;
+        let conn = get_connection()?.lock();
+        delete_index(&conn, self.index)?;
         info!(remote = self.to_string(), "success delete record");
         Ok(())
     }
 
     pub fn add_auth(&self) -> Result<(), Error> {
+        self.observe(Operation::Authenticate, self.run_add_auth())
+    }
+
+    fn run_add_auth(&self) -> Result<(), Error> {
         // check the `ATSH_KEY` exist or not
         // if not exist, { kind: Other, error: "Authentication failed (username/password)" }
-        let _ = CONFIG.get_enc_key()?;
-        let session = SSHSession::new(&self.user, &self.password, &self.ip, self.port)?;
-        session.authenticate()?;
+        let _ = current().get_enc_key()?;
+        let session = SSHSession::new(self)?;
+        match self.mode {
+            AuthMode::Key => {
+                session.authenticate(&current().config().read_public()?)?;
+            }
+            AuthMode::Certificate => session.trust_ca(&current().config().ca.read_public()?)?,
+            // nothing to install until the session starts
+            AuthMode::Ephemeral => return Ok(()),
+            // the password is verified by the session, nothing to install
+            AuthMode::Password => return Ok(()),
+        }
+        current().emit(Event::KeyInstalled {
+            remote: self.to_string(),
+        });
         // 更新数据库
         if !self.authorized {
             // update authorized to database
             // self.authorized = true;
-            let conn = get_connection().lock();
-            update_authorized(&conn, self.index, true)
-                .map_err(|e| Error::new(std::io::ErrorKind::Other, e))?;
+            let conn = get_connection()?.lock();
+            update_authorized(&conn, self.index, true)?;
         }
         info!(remote = self.to_string(), "success add authenticate");
         Ok(())
     }
 
     pub fn remove_auth(&self) -> Result<(), Error> {
-        let session = SSHSession::new(&self.user, &self.password, &self.ip, self.port)?;
-        session.revoke()?;
+        self.observe(Operation::Revoke, self.run_remove_auth())
+    }
+
+    fn run_remove_auth(&self) -> Result<(), Error> {
+        if self.mode == AuthMode::Password {
+            debug!(
+                remote = self.to_string(),
+                "password mode, nothing to remove"
+            );
+            return Ok(());
+        }
+        let session = SSHSession::new(self)?;
+        if self.authorized && self.mode == AuthMode::Key {
+            session.revoke(&current().config().read_public()?)?;
+            current().emit(Event::KeyRevoked {
+                remote: self.to_string(),
+            });
+        }
+        // the CA may be trusted by other records, so keep it
+        // the ephemeral entries left by crashes of this host, all of them are useless now,
+        // the entries of the other hosts are still used by their sessions
+        let n = session.sweep_ephemeral(&host_id()?, |_| false)?;
+        debug!(remote = self.to_string(), total = n, "sweep ephemeral keys");
         info!(remote = self.to_string(), "success remove authenticate");
         Ok(())
     }
 
-    pub fn login(&self, reauth: bool) -> Result<(), Error> {
-        // 如果没有认证，或者通过 `--auth` 参数重新认证
-        if !self.authorized || reauth {
-            self.add_auth()?;
+    /// remove the expired ephemeral entries and the ones of this host not belong to a living session.
+    pub fn sweep_ephemeral(&self) -> Result<usize, Error> {
+        let ids = living_ids()?;
+        let session = SSHSession::new(self)?;
+        let n = session.sweep_ephemeral(&host_id()?, |id| ids.iter().any(|i| i == id))?;
+        info!(
+            remote = self.to_string(),
+            total = n,
+            "success sweep ephemeral keys"
+        );
+        Ok(n)
+    }
+
+    /// grant the public key (not the atsh key) to login the remote server,
+    /// return false if it already exists, then it is not recorded as granted.
+    pub fn copy_id(&self, public: &str) -> Result<bool, Error> {
+        self.observe(Operation::CopyId, self.run_copy_id(public))
+    }
+
+    fn run_copy_id(&self, public: &str) -> Result<bool, Error> {
+        let key = AuthorizedKey::parse(0, public)
+            .ok_or_else(|| Error::InvalidInput("invalid public key".to_string()))?;
+        let session = SSHSession::new(self)?;
+        if !session.authenticate(public)? {
+            // never revoke the key added by others later
+            warn!(
+                remote = self.to_string(),
+                key = key.fingerprint,
+                "public key already exists, skip grant"
+            );
+            return Ok(false);
         }
+        current().emit(Event::KeyInstalled {
+            remote: self.to_string(),
+        });
+        let grant = Grant {
+            fingerprint: key.fingerprint.clone(),
+            comment: key.comment.clone(),
+            granted_by: local_user(),
+            granted_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        {
+            let conn = get_connection()?.lock();
+            insert_grant(&conn, self.index, &grant)
+        }?;
+        info!(
+            remote = self.to_string(),
+            key = grant.fingerprint,
+            comment = grant.comment,
+            by = grant.granted_by,
+            "success copy id"
+        );
+        Ok(true)
+    }
+
+    /// revoke the public key granted by `copy_id`, return false if it not found on the server.
+    pub fn revoke_id(&self, public: &str) -> Result<bool, Error> {
+        self.observe(Operation::RevokeId, self.run_revoke_id(public))
+    }
+
+    fn run_revoke_id(&self, public: &str) -> Result<bool, Error> {
+        let key = AuthorizedKey::parse(0, public)
+            .ok_or_else(|| Error::InvalidInput("invalid public key".to_string()))?;
+        let granted = {
+            let conn = get_connection()?.lock();
+            query_grants(&conn, self.index)
+        }?
+        .iter()
+        .any(|g| g.fingerprint == key.fingerprint);
+        if !granted {
+            return Err(Error::InvalidInput(format!(
+                "the key {} is not granted by copy-id, remove it by `keys audit --remove`",
+                key.fingerprint
+            )));
+        }
+        let session = SSHSession::new(self)?;
+        let revoked = session.revoke(public)?;
+        if !revoked {
+            warn!(
+                remote = self.to_string(),
+                key = key.fingerprint,
+                "public key not found"
+            );
+        } else {
+            current().emit(Event::KeyRevoked {
+                remote: self.to_string(),
+            });
+        }
+        {
+            let conn = get_connection()?.lock();
+            delete_grant(&conn, self.index, &key.fingerprint)
+        }?;
+        info!(
+            remote = self.to_string(),
+            key = key.fingerprint,
+            comment = key.comment,
+            by = local_user(),
+            "success revoke id"
+        );
+        Ok(revoked)
+    }
 
-        let sshkey = CONFIG.get_private();
-        Command::new("ssh")
-            .arg(format!("{}@{}", self.user, self.ip))
-            .arg("-p")
-            .arg(self.port.to_string())
-            .arg("-i")
-            .arg(sshkey.to_str().unwrap())
-            .status()?;
+    /// audit the `authorized_keys` of the remote server, and remove the entries by fingerprint.
+    pub fn audit_keys(&self, remove: &[impl AsRef<str>]) -> Result<Vec<KeyStatus>, Error> {
+        let grants = {
+            let conn = get_connection()?.lock();
+            query_grants(&conn, self.index)
+        }?;
+        let session = SSHSession::new(self)?;
+        let keys = audit(&session, &grants, remove)?;
+        let conn = get_connection()?.lock();
+        if self.authorized && keys.iter().any(|k| k.atsh && k.removed) {
+            // the next login must install the atsh key again
+            update_authorized(&conn, self.index, false)?;
+            debug!(
+                remote = self.to_string(),
+                "atsh key removed, reset authorized"
+            );
+        }
+        // the granted key removed is never revoked by `revoke_id`
+        for key in keys.iter().filter(|k| k.granted && k.removed) {
+            delete_grant(&conn, self.index, &key.key.fingerprint)?;
+            debug!(
+                remote = self.to_string(),
+                key = key.key.fingerprint,
+                "granted key removed, delete the grant"
+            );
+        }
+        info!(
+            remote = self.to_string(),
+            total = keys.len(),
+            "success audit authorized keys"
+        );
+        Ok(keys)
+    }
+
+    /// execute the command on the remote server, return the exit status and the output.
+    /// the output is also emitted as `Event::Output` while the command is running.
+    /// with `sudo` the prompt is answered by the sudo password or the login password.
+    pub fn exec(&self, cmd: &str, sudo: bool) -> Result<ExecOutput, Error> {
+        self.observe(Operation::Exec, self.run_exec(cmd, sudo))
+    }
+
+    fn run_exec(&self, cmd: &str, sudo: bool) -> Result<ExecOutput, Error> {
+        let session = SSHSession::new(self)?;
+        let password = sudo.then(|| self.sudo_password.as_deref().unwrap_or(&self.password));
+        let remote = self.to_string();
+        let mut decoder = Utf8Decoder::default();
+        let mut output = String::new();
+        let mut emit = |data: String| {
+            if data.is_empty() {
+                return;
+            }
+            output.push_str(&data);
+            current().emit(Event::Output {
+                remote: remote.clone(),
+                data,
+            });
+        };
+        let code = session.exec(cmd, password, |data| emit(decoder.feed(data)))?;
+        emit(decoder.flush());
+        info!(remote = remote, sudo = sudo, code = code, "success exec");
+        Ok(ExecOutput { code, output })
+    }
+
+    /// sign the atsh public key to a certificate for this record.
+    pub fn sign_certificate(&self) -> Result<PathBuf, Error> {
+        current().config().try_get_private()?;
+        let dir = current().config().ca.get_dir();
+        if !dir.is_dir() {
+            std::fs::create_dir_all(&dir)?;
+        }
+        let cert = current().config().ca.sign(
+            current().config().get_public(),
+            &format!("atsh:{}", self),
+            self.principals.as_deref().unwrap_or(&self.user),
+            &dir.join(self.index.to_string()),
+        )?;
+        info!(remote = self.to_string(), cert = ?cert, "success sign certificate");
+        Ok(cert)
+    }
+
+    /// the spawned `ssh`/`scp` with the connect options.
+    fn ssh_command(&self, program: &str) -> Result<Command, Error> {
+        let options = self.options.or(&current().config().connection);
+        let mut command = Command::new(program);
+        command.args(options.to_args()?);
+        if let Some(proxy) = options.get_proxy()? {
+            // the credentials are never in the arguments which are seen by everyone
+            command.envs(proxy.to_envs());
+        }
+        Ok(command)
+    }
+
+    /// run `f` with the identity options of `ssh`/`scp` which is allowed to login the remote server.
+    fn with_identity<T>(
+        &self,
+        reauth: bool,
+        f: impl FnOnce(&[String]) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let identity = |key: &Path, cert: Option<&Path>| {
+            let mut args = vec!["-i".to_string(), key.display().to_string()];
+            if let Some(cert) = cert {
+                args.push("-o".to_string());
+                args.push(format!("CertificateFile={}", cert.display()));
+            }
+            args
+        };
+        match self.mode {
+            AuthMode::Key | AuthMode::Certificate => {
+                // 如果没有认证，或者通过 `--auth` 参数重新认证
+                if !self.authorized || reauth {
+                    debug!(remote = self.to_string(), "no authorized, try authenticate");
+                    self.add_auth()?;
+                }
+                if self.mode == AuthMode::Key {
+                    return f(&identity(current().config().get_private(), None));
+                }
+                let cert = self.sign_certificate()?;
+                f(&identity(current().config().get_private(), Some(&cert)))
+            }
+            AuthMode::Ephemeral => {
+                let key = SessionKey::create(self.index)?;
+                {
+                    let ids = living_ids()?;
+                    let session = SSHSession::new(self)?;
+                    session.sweep_ephemeral(&host_id()?, |id| ids.iter().any(|i| i == id))?;
+                    session.install_ephemeral(
+                        &key.read_public()?,
+                        &key.id,
+                        current().config().ephemeral.ttl,
+                        current().config().ephemeral.restrict_from,
+                    )?;
+                }
+                info!(
+                    remote = self.to_string(),
+                    id = key.id,
+                    "success install ephemeral key"
+                );
+                let result = f(&identity(key.get_private(), None));
+                // the session may be closed by the server during a long login, so reconnect
+                let session = SSHSession::new(self)?;
+                session.sweep_ephemeral(&host_id()?, |id| id != key.id)?;
+                info!(
+                    remote = self.to_string(),
+                    id = key.id,
+                    "success remove ephemeral key"
+                );
+                result
+            }
+            AuthMode::Password => Err(Error::InvalidInput(
+                "no identity in password mode, use the ssh2 session instead".to_string(),
+            )),
+        }
+    }
+
+    pub fn login(&self, reauth: bool) -> Result<(), Error> {
+        self.observe(Operation::Login, self.run_login(reauth))
+    }
+
+    fn run_login(&self, reauth: bool) -> Result<(), Error> {
+        if self.mode == AuthMode::Password {
+            let code = SSHSession::new(self)?.shell()?;
+            info!(remote = self.to_string(), code = code, "success login");
+            return Ok(());
+        }
+        self.with_identity(reauth, |identity| {
+            self.ssh_command("ssh")?
+                .arg(format!("{}@{}", self.user, self.ip))
+                .arg("-p")
+                .arg(self.port.to_string())
+                .args(identity)
+                .status()
+                .map_err(Error::from)
+        })?;
         info!(remote = self.to_string(), "success login");
         Ok(())
     }
 
-    fn scp(&self, args: &Vec<&str>) -> Result<(), Error> {
-        // 如果没有认证，则先认证
-        if !self.authorized {
-            debug!(remote = self.to_string(), "no authorized, try authenticate");
-            self.add_auth()?;
-        }
+    fn scp(&self, args: &[&str]) -> Result<(), Error> {
         // info!("\n🚨 scp {}\n🚨 input `y` to run and other to cancel.", cmd);
         // let mut read = String::new();
         // std::io::stdin().read_line(&mut read)?;
         // let read = read.trim();
         // if read == "y" {}
-        debug!(args=?args, "scp");
-        let stderr = Command::new("scp")
-            .args(args)
-            .stderr(Stdio::piped())
-            .spawn()?
-            .stderr;
-        if stderr.is_none() {
-            return Err(Error::new(ErrorKind::BrokenPipe, "stderr is none"));
-        }
-        let reader = BufReader::new(stderr.unwrap());
-        reader
-            .lines()
-            .filter_map(|line| line.ok())
-            .for_each(|line| println!("{}", line));
-        Ok(())
+        self.with_identity(false, |identity| {
+            debug!(args=?args, "scp");
+            let mut child = self
+                .ssh_command("scp")?
+                .args(identity)
+                .args(args)
+                .stderr(Stdio::piped())
+                .spawn()?;
+            let stderr = child.stderr.take();
+            if stderr.is_none() {
+                return Err(Error::other("stderr is none"));
+            }
+            // the errors are logged and returned, never printed by the library
+            let reader = BufReader::new(stderr.unwrap());
+            let lines = reader
+                .lines()
+                .map_while(Result::ok)
+                .inspect(|line| warn!(remote = self.to_string(), "scp: {line}"))
+                .collect::<Vec<_>>();
+            // wait the transfer finished before the ephemeral key removed
+            let status = child.wait()?;
+            if !status.success() {
+                return Err(Error::Transport(format!(
+                    "scp failed ({status}): {}",
+                    lines.join("; ")
+                )));
+            }
+            Ok(())
+        })
+    }
+
+    /// emit the bytes of the file transferred by `scp`, only at the start and end.
+    fn transferred(&self, path: &Path, bytes: u64, total: u64) {
+        current().emit(Event::Transferred {
+            remote: self.to_string(),
+            path: path.to_path_buf(),
+            bytes,
+            total,
+        });
     }
 
     pub fn upload(&self, from: &str, to: &str) -> Result<(), Error> {
+        self.observe(Operation::Upload, self.run_upload(from, to))
+    }
+
+    fn run_upload(&self, from: &str, to: &str) -> Result<(), Error> {
         // debug!(from = ?from, to=?to, "upload");
         // scp -r -P 22 -i /home/idhyt/.ssh/id_rsa ./test.txt idhyt@1.2.3.4:/tmp
+        if self.mode == AuthMode::Password {
+            let n = SSHSession::new(self)?.upload(Path::new(from), Path::new(to))?;
+            info!(from=?from, to=?to, size = n, "susccess upload");
+            return Ok(());
+        }
         let port = self.port.to_string();
-        let remote = format!("{}@{}:{}", self.user, self.ip, to);
-        let cmd = vec![
-            "-r",
-            "-P",
-            &port,
-            "-i",
-            CONFIG.get_private().to_str().unwrap(),
-            from,
-            &remote,
-        ];
+        // the IPv6 is in brackets, or `scp` takes its colon as the path
+        let remote = format!("{}@{}:{}", self.user, bracket(&self.ip), to);
+        let cmd = vec!["-r", "-P", &port, from, &remote];
+        let total = local_size(Path::new(from));
+        self.transferred(Path::new(from), 0, total);
         self.scp(&cmd)?;
+        self.transferred(Path::new(from), total, total);
         info!(from=?from, to=?to, "susccess upload");
         Ok(())
     }
 
     pub fn download(&self, from: &str, to: &str) -> Result<(), Error> {
+        self.observe(Operation::Download, self.run_download(from, to))
+    }
+
+    fn run_download(&self, from: &str, to: &str) -> Result<(), Error> {
         // debug!(from = ?from, to=?to, "download");
         // scp -r -P 22 -i /home/idhyt/.ssh/id_rsa idhyt@1.2.3.4:/tmp/test.txt ./
+        if self.mode == AuthMode::Password {
+            let n = SSHSession::new(self)?.download(Path::new(from), Path::new(to))?;
+            info!(from=?from, to=?to, size = n, "susccess download");
+            return Ok(());
+        }
         let port = self.port.to_string();
-        let remote = format!("{}@{}:{}", self.user, self.ip, from);
-        let cmd = vec![
-            "-r",
-            "-P",
-            &port,
-            "-i",
-            CONFIG.get_private().to_str().unwrap(),
-            &remote,
-            to,
-        ];
+        let remote = format!("{}@{}:{}", self.user, bracket(&self.ip), from);
+        let cmd = vec!["-r", "-P", &port, &remote, to];
+        // the size is known after downloaded, it is put into the directory like `scp -r`
+        let local = match Path::new(from).file_name() {
+            Some(name) if Path::new(to).is_dir() => Path::new(to).join(name),
+            _ => PathBuf::from(to),
+        };
         self.scp(&cmd)?;
+        let total = local_size(&local);
+        self.transferred(Path::new(from), total, total);
         info!(from=?from, to=?to, "susccess download");
         Ok(())
     }
+
+    /// list the remote directory by SFTP, the entry name and whether it is a directory.
+    pub fn list_dir(&self, dir: &str) -> Result<Vec<(String, bool)>, Error> {
+        self.observe(Operation::ListDir, self.run_list_dir(dir))
+    }
+
+    fn run_list_dir(&self, dir: &str) -> Result<Vec<(String, bool)>, Error> {
+        let entries = SSHSession::new(self)?.list_dir(Path::new(dir))?;
+        debug!(
+            remote = self.to_string(),
+            dir = dir,
+            total = entries.len(),
+            "list dir"
+        );
+        Ok(entries)
+    }
 }
 
 // #[derive(Serialize, Deserialize)]
 pub struct Remotes(pub Vec<Remote>);
 
+/// add the rows in one transaction encrypted by the key, the error of the failed row is set,
+/// nothing is added if any row failed unless `partial`.
+fn insert_rows(
+    conn: &mut Connection,
+    rows: &mut [ImportRow],
+    partial: bool,
+    key: &str,
+) -> Result<usize, Error> {
+    let tx = conn.transaction()?;
+    for row in rows.iter_mut().filter(|r| r.error.is_none()) {
+        let remote = &row.remote;
+        let result = if remote.password.is_empty() {
+            Err(Error::InvalidInput("missing password".to_string()))
+        } else {
+            remote.check_new(&tx).and_then(|_| {
+                insert_by(&tx, remote, Some(key))
+                    .and_then(|_| query_target(&tx, &remote.user, &remote.ip, remote.port))
+                    .map_err(Error::from)
+            })
+        };
+        match result {
+            Ok(index) => row.index = index,
+            Err(e) => row.error = Some(e.to_string()),
+        }
+    }
+    let failed = rows.iter().filter(|r| r.error.is_some()).count();
+    if failed > 0 && !partial {
+        tx.rollback()?;
+        rows.iter_mut().for_each(|r| r.index = None);
+        return Err(Error::InvalidInput(format!(
+            "{failed} of {} rows failed, nothing is added",
+            rows.len()
+        )));
+    }
+    tx.commit()?;
+    let total = rows.len() - failed;
+    info!(total = total, failed = failed, "success insert records");
+    Ok(total)
+}
+
 impl Remotes {
     fn load() -> Result<Remotes, Error> {
-        let conn = get_connection().lock();
-        let remotes = query_all(&conn).map_err(|e| Error::new(std::io::ErrorKind::Other, e))?;
+        let conn = get_connection()?.lock();
+        let remotes = query_all(&conn)?;
         Ok(Remotes(remotes))
     }
     pub fn get(idx: usize) -> Result<Option<Remote>, Error> {
         let remote = {
-            let conn = get_connection().lock();
+            let conn = get_connection()?.lock();
             query_index(&conn, idx)
-        }
-        .map_err(|e| Error::new(std::io::ErrorKind::Other, e))?;
+        }?;
 
         if remote.is_some() {
             info!(index = idx, "susccess get remote");
@@ -214,10 +809,7 @@ impl Remotes {
     pub fn try_get(idx: usize) -> Result<Remote, Error> {
         let remote = Remotes::get(idx)?;
         if remote.is_none() {
-            return Err(Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("index {} remote not found", idx),
-            ));
+            return Err(Error::RemoteNotFound(idx));
         }
         Ok(remote.unwrap())
     }
@@ -228,6 +820,22 @@ impl Remotes {
         Ok(remotes)
     }
 
+    /// the remote servers by index, tag expression or all.
+    pub fn select(target: &Target) -> Result<Remotes, Error> {
+        match target {
+            Target::Index(idx) => Ok(Remotes(vec![Remotes::try_get(*idx)?])),
+            Target::All => Remotes::get_all(),
+            Target::Tags(expr) => {
+                let mut remotes = Remotes::load()?;
+                remotes.0.retain(|r| expr.matches(&r.tags));
+                if remotes.0.is_empty() {
+                    warn!(tags = ?expr, "no remote matched the tags");
+                }
+                Ok(remotes)
+            }
+        }
+    }
+
     pub fn add(
         user: &str,
         password: &str,
@@ -245,6 +853,12 @@ impl Remotes {
             authorized: false,
             name: name.as_ref().map(|n| n.as_ref().to_string()),
             note: note.as_ref().map(|n| n.as_ref().to_string()),
+            mode: AuthMode::Key,
+            principals: None,
+            totp: None,
+            sudo_password: None,
+            tags: vec![],
+            options: ConnectOptions::default(),
         };
         // we not authorized the remote server until the first login
         // remote.authorized();
@@ -252,7 +866,47 @@ impl Remotes {
         remote.add_record()
     }
 
-    pub fn delete(indexs: &Vec<usize>) -> Result<usize, Error> {
+    /// add the imported servers in one transaction, the error of the failed row is set,
+    /// nothing is added if any row failed unless `partial`.
+    pub fn import(rows: &mut [ImportRow], partial: bool) -> Result<usize, Error> {
+        let key = current().get_enc_key()?;
+        let mut conn = get_connection()?.lock();
+        insert_rows(&mut conn, rows, partial, &key)
+    }
+
+    /// copy the servers to another profile encrypted by its key, like the `import`.
+    pub fn copy(
+        rows: &mut [ImportRow],
+        profile: &str,
+        key: &str,
+        partial: bool,
+    ) -> Result<usize, Error> {
+        if profile == current_profile() {
+            return Err(Error::InvalidInput(format!(
+                "can't copy to the current profile `{profile}`"
+            )));
+        }
+        let dir = profile_dir(profile)?;
+        if !dir.is_dir() {
+            return Err(Error::InvalidInput(format!(
+                "profile `{profile}` not found"
+            )));
+        }
+        let mut conn = db_init(&dir.join("atsh.db"))?;
+        // never mix the passwords encrypted by different keys in one profile
+        if !check_key(&conn, key)? {
+            return Err(Error::Crypto(format!(
+                "the key of profile `{profile}` is wrong"
+            )));
+        }
+        // the atsh key of the other profile is not installed yet
+        rows.iter_mut().for_each(|r| r.remote.authorized = false);
+        let total = insert_rows(&mut conn, rows, partial, key)?;
+        info!(profile = profile, total = total, "success copy records");
+        Ok(total)
+    }
+
+    pub fn delete(indexs: &[usize]) -> Result<usize, Error> {
         let remotes: Vec<Remote> = indexs
             .iter()
             .collect::<std::collections::HashSet<_>>()
@@ -261,9 +915,11 @@ impl Remotes {
             .collect::<Result<Vec<_>, _>>()?;
         debug!(total = remotes.len(), "delete");
         for remote in remotes.iter() {
+            // the servers done are kept deleted when cancelled
+            SSHSession::checkpoint()?;
             debug!(index = remote.index, "delete");
             // remove auth
-            if remote.authorized {
+            if remote.authorized || remote.mode == AuthMode::Ephemeral {
                 remote.remove_auth()?;
             }
             // delete database
@@ -272,50 +928,53 @@ impl Remotes {
         Ok(remotes.len())
     }
 
-    pub fn pprint(&self, all: bool) {
-        let mut table = Table::new();
-        let mut titles = vec!["index", "name", "user", "ip", "port"];
-        if all {
-            titles.push("password");
-            titles.push("authorized");
-            titles.push("note");
-        }
-        table.set_titles(Row::new(
-            titles
-                .iter()
-                .map(|v| Cell::new(v).style_spec("bcFg"))
-                .collect::<Vec<Cell>>(),
-        ));
-
-        for remote in self.0.iter() {
-            let mut row = vec![
-                remote.index.to_string(),
-                remote.name.clone().unwrap_or_else(|| "".to_string()),
-                remote.user.clone(),
-                remote.ip.clone(),
-                remote.port.to_string(),
-            ];
-            if all {
-                if let Ok(_) = CONFIG.get_enc_key() {
-                    row.push(remote.password.clone());
-                } else {
-                    row.push(format!(
-                        "{}..{}",
-                        &remote.password[..3],
-                        &remote.password[remote.password.len() - 5..]
-                    ));
+    /// run `f` on all remote servers,
+    /// the failure of one server is reported in the result instead of stop.
+    pub fn each<T>(&self, f: impl Fn(&Remote) -> Result<T, Error>) -> Vec<Outcome<T>> {
+        self.0
+            .iter()
+            .map(|remote| {
+                // the servers left are failed when cancelled
+                let (value, error) = match SSHSession::checkpoint().and_then(|_| f(remote)) {
+                    Ok(value) => (Some(value), None),
+                    Err(e) => {
+                        warn!(remote = remote.to_string(), error = ?e, "failed on remote");
+                        (None, Some(e.to_string()))
+                    }
+                };
+                Outcome {
+                    index: remote.index,
+                    name: remote.name.clone(),
+                    remote: remote.to_string(),
+                    value,
+                    error,
                 }
-                // row.push(remote.password.clone());
-                row.push(remote.authorized.to_string());
-                row.push(remote.note.clone().unwrap_or_else(|| "".to_string()));
-            }
-            table.add_row(Row::new(
-                row.iter()
-                    .map(|v| Cell::new(v).style_spec("lFc"))
-                    .collect::<Vec<Cell>>(),
-            ));
-        }
-        debug!("the remote list total: {}", self.0.len());
-        table.printstd();
+            })
+            .collect()
+    }
+
+    /// audit the `authorized_keys` of all remote servers,
+    /// the failure of one server is reported in the result instead of stop.
+    pub fn audit(&self, remove: &[impl AsRef<str>]) -> Vec<KeyAudit> {
+        self.0
+            .iter()
+            .map(|remote| {
+                let result = SSHSession::checkpoint().and_then(|_| remote.audit_keys(remove));
+                let (keys, error) = match result {
+                    Ok(keys) => (keys, None),
+                    Err(e) => {
+                        warn!(remote = remote.to_string(), error = ?e, "audit failed");
+                        (vec![], Some(e.to_string()))
+                    }
+                };
+                KeyAudit {
+                    index: remote.index,
+                    name: remote.name.clone(),
+                    remote: remote.to_string(),
+                    keys,
+                    error,
+                }
+            })
+            .collect()
     }
 }
@@ -0,0 +1,153 @@
+use crate::context::current;
+use crate::error::Error;
+use crate::event::Event;
+use ssh2::{OpenFlags, OpenType, Sftp};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+use super::ssh::SSHSession;
+
+/// the mode of the uploaded file, keep the local one if possible.
+fn file_mode(local: &Path) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(meta) = std::fs::metadata(local) {
+            return (meta.permissions().mode() & 0o777) as i32;
+        }
+    }
+    let _ = local;
+    0o644
+}
+
+/// the target like `scp -r`, put it into the directory if the target is a directory.
+fn target(to: &Path, from: &Path, is_dir: bool) -> PathBuf {
+    match from.file_name() {
+        Some(name) if is_dir => to.join(name),
+        _ => to.to_path_buf(),
+    }
+}
+
+/// copy the file and emit the bytes transferred, return the total bytes.
+fn copy(
+    src: &mut impl Read,
+    dst: &mut impl Write,
+    remote: &str,
+    path: &Path,
+    total: u64,
+) -> Result<u64, Error> {
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut bytes = 0;
+    loop {
+        SSHSession::checkpoint()?;
+        let n = src.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        dst.write_all(&buf[..n])?;
+        bytes += n as u64;
+        current().emit(Event::Transferred {
+            remote: remote.to_string(),
+            path: path.to_path_buf(),
+            bytes,
+            total,
+        });
+    }
+    Ok(bytes)
+}
+
+fn upload_path(sftp: &Sftp, peer: &str, local: &Path, remote: &Path) -> Result<u64, Error> {
+    if local.is_dir() {
+        if sftp.stat(remote).is_err() {
+            sftp.mkdir(remote, 0o755)?;
+        }
+        let mut total = 0;
+        for entry in std::fs::read_dir(local)? {
+            let entry = entry?;
+            let path = entry.path();
+            // the linked directory may contain itself, so never follow it
+            if entry.file_type()?.is_symlink() && path.is_dir() {
+                warn!(path = ?path, "skip the symlink to directory");
+                continue;
+            }
+            total += upload_path(sftp, peer, &path, &remote.join(entry.file_name()))?;
+        }
+        return Ok(total);
+    }
+    let mut src = File::open(local)?;
+    let mut dst = sftp.open_mode(
+        remote,
+        OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+        file_mode(local),
+        OpenType::File,
+    )?;
+    let total = src.metadata()?.len();
+    let n = copy(&mut src, &mut dst, peer, local, total)?;
+    debug!(from = ?local, to = ?remote, size = n, "upload file");
+    Ok(n)
+}
+
+fn download_path(sftp: &Sftp, peer: &str, remote: &Path, local: &Path) -> Result<u64, Error> {
+    let stat = sftp.stat(remote)?;
+    if stat.is_dir() {
+        std::fs::create_dir_all(local)?;
+        let mut total = 0;
+        // the `.` and `..` are filtered out
+        for (path, _) in sftp.readdir(remote)? {
+            let name = match path.file_name() {
+                Some(name) => name.to_owned(),
+                None => continue,
+            };
+            total += download_path(sftp, peer, &path, &local.join(name))?;
+        }
+        return Ok(total);
+    }
+    let mut src = sftp.open(remote)?;
+    let mut dst = File::create(local)?;
+    let n = copy(
+        &mut src,
+        &mut dst,
+        peer,
+        remote,
+        stat.size.unwrap_or_default(),
+    )?;
+    debug!(from = ?remote, to = ?local, size = n, "download file");
+    Ok(n)
+}
+
+impl SSHSession {
+    /// upload the local file or directory by SFTP, return the total bytes.
+    pub fn upload(&self, local: &Path, remote: &Path) -> Result<u64, Error> {
+        let sftp = self.session.sftp()?;
+        let is_dir = sftp.stat(remote).is_ok_and(|s| s.is_dir());
+        upload_path(&sftp, &self.remote, local, &target(remote, local, is_dir))
+    }
+
+    /// download the remote file or directory by SFTP, return the total bytes.
+    pub fn download(&self, remote: &Path, local: &Path) -> Result<u64, Error> {
+        let sftp = self.session.sftp()?;
+        download_path(
+            &sftp,
+            &self.remote,
+            remote,
+            &target(local, remote, local.is_dir()),
+        )
+    }
+
+    /// list the remote directory by SFTP, the entry name and whether it is a directory.
+    pub fn list_dir(&self, dir: &Path) -> Result<Vec<(String, bool)>, Error> {
+        let sftp = self.session.sftp()?;
+        let mut entries = sftp
+            .readdir(dir)?
+            .into_iter()
+            .filter_map(|(path, stat)| {
+                let name = path.file_name()?.to_string_lossy().to_string();
+                Some((name, stat.is_dir()))
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+        Ok(entries)
+    }
+}
@@ -0,0 +1,186 @@
+use crossterm::terminal;
+use ssh2::Channel;
+use std::io::{ErrorKind, Read, Write};
+
+use crate::error::Error;
+use std::time::Duration;
+use tracing::debug;
+
+use super::ssh::SSHSession;
+
+/// the idle time between two polls of the channel and local input.
+const POLL: Duration = Duration::from_millis(10);
+
+/// restore the local terminal even if the bridge panics.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> Result<RawMode, Error> {
+        terminal::enable_raw_mode()?;
+        Ok(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// write all data to the non-blocking channel.
+fn write_all(channel: &mut Channel, mut data: &[u8]) -> std::io::Result<()> {
+    while !data.is_empty() {
+        match channel.write(data) {
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(POLL),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// The local input read without blocking, so nothing is left reading the stdin
+/// after the remote shell exits, or it would swallow the next keystrokes.
+#[cfg(unix)]
+struct Input;
+
+#[cfg(unix)]
+impl Input {
+    fn open() -> Input {
+        Input
+    }
+
+    /// `Ok(None)` if nothing to read, `Ok(Some(0))` if the stdin is closed.
+    fn try_read(&mut self, buf: &mut [u8]) -> std::io::Result<Option<usize>> {
+        let mut fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: one valid `pollfd` and never wait
+        match unsafe { libc::poll(&mut fd, 1, 0) } {
+            0 => return Ok(None),
+            n if n < 0 => {
+                let e = std::io::Error::last_os_error();
+                if e.kind() == ErrorKind::Interrupted {
+                    return Ok(None);
+                }
+                return Err(e);
+            }
+            _ => {}
+        }
+        // read the fd directly, the buffer of `std::io::stdin` would hide the data from `poll`
+        // SAFETY: the buffer is valid for `buf.len()` bytes
+        let n = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+        if n < 0 {
+            let e = std::io::Error::last_os_error();
+            if matches!(e.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock) {
+                return Ok(None);
+            }
+            return Err(e);
+        }
+        Ok(Some(n as usize))
+    }
+}
+
+/// the console has no `poll`, so read it in the background,
+/// the reader ends at the next keystroke after the shell exits.
+#[cfg(not(unix))]
+struct Input(std::sync::mpsc::Receiver<Vec<u8>>);
+
+#[cfg(not(unix))]
+impl Input {
+    fn open() -> Input {
+        let (tx, rx) = std::sync::mpsc::channel::<Vec<u8>>();
+        std::thread::spawn(move || {
+            let mut stdin = std::io::stdin();
+            let mut buf = [0u8; 1024];
+            while let Ok(n) = stdin.read(&mut buf) {
+                if n == 0 || tx.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+        Input(rx)
+    }
+
+    fn try_read(&mut self, buf: &mut [u8]) -> std::io::Result<Option<usize>> {
+        use std::sync::mpsc::TryRecvError;
+        match self.0.try_recv() {
+            Ok(data) => {
+                let n = data.len().min(buf.len());
+                buf[..n].copy_from_slice(&data[..n]);
+                Ok(Some(n))
+            }
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Ok(Some(0)),
+        }
+    }
+}
+
+impl SSHSession {
+    /// open an interactive shell on a remote PTY, the local terminal is bridged in raw mode.
+    /// return the exit status of the remote shell.
+    pub fn shell(&self) -> Result<i32, Error> {
+        let mut size = terminal::size().unwrap_or((80, 24));
+        let term = std::env::var("TERM").unwrap_or_else(|_| "xterm".to_string());
+        let mut channel = self.session.channel_session()?;
+        channel.request_pty(&term, None, Some((size.0 as u32, size.1 as u32, 0, 0)))?;
+        channel.shell()?;
+
+        let raw = RawMode::enable()?;
+        self.session.set_blocking(false);
+        let mut stdout = std::io::stdout();
+        let mut input = Input::open();
+        let mut buf = [0u8; 8192];
+        let mut keys = [0u8; 1024];
+        let mut closed = false;
+        let result = loop {
+            let mut idle = true;
+            match channel.read(&mut buf) {
+                Ok(0) if channel.eof() => break Ok(()),
+                Ok(0) => {}
+                Ok(n) => {
+                    idle = false;
+                    if let Err(e) = stdout.write_all(&buf[..n]).and_then(|_| stdout.flush()) {
+                        break Err(e);
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => break Err(e),
+            }
+            match input.try_read(&mut keys) {
+                _ if closed => {}
+                // stdin closed, let the remote shell know
+                Ok(Some(0)) => {
+                    closed = true;
+                    let _ = channel.send_eof();
+                }
+                Ok(Some(n)) => {
+                    idle = false;
+                    if let Err(e) = write_all(&mut channel, &keys[..n]) {
+                        break Err(e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => break Err(e),
+            }
+            let current = terminal::size().unwrap_or(size);
+            if current != size {
+                size = current;
+                debug!(cols = size.0, rows = size.1, "resize remote pty");
+                let _ = channel.request_pty_size(size.0 as u32, size.1 as u32, None, None);
+            }
+            if idle {
+                // only sent if the keepalive interval is elapsed
+                let _ = self.session.keepalive_send();
+                std::thread::sleep(POLL);
+            }
+        };
+        self.session.set_blocking(true);
+        drop(raw);
+        result?;
+        channel.wait_close()?;
+        Ok(channel.exit_status()?)
+    }
+}
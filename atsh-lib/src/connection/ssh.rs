@@ -1,80 +1,438 @@
-use ssh2::Session;
-use std::io::{Error, Read, Write};
-use std::net::TcpStream;
-use std::path::Path;
-use tracing::debug;
+use ssh2::{MethodType, Session};
+use std::io::{Read, Write};
 
-use crate::config::CONFIG;
+use crate::context::current;
+use crate::error::Error;
+use crate::event::Event;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+use super::audit::{parse_authorized_keys, AuthorizedKey};
+use super::ephemeral::sweep;
+use super::host::bracket;
+use super::options::{prefer, ConnectOptions};
+use super::otp::Responder;
+use super::remote::Remote;
+
+/// the comment prefix of the `authorized_keys` entries installed for one session.
+pub const EPHEMERAL_MARKER: &str = "atsh-ephemeral";
+const SSHD_CONFIG: &str = "/etc/ssh/sshd_config";
+/// the file of user CA keys when the sshd not configured yet.
+const ATSH_CA_KEYS: &str = "/etc/ssh/atsh_ca.pub";
 
 pub struct SSHSession {
-    session: Session,
+    pub(super) session: Session,
+    /// the remote like `user@ip:port`, for the events.
+    pub(super) remote: String,
 }
 
 impl SSHSession {
-    pub fn new(user: &str, password: &str, ip: &str, port: u16) -> Result<SSHSession, Error> {
-        let tcp = TcpStream::connect(format!("{ip}:{port}"))?;
+    pub fn new(remote: &Remote) -> Result<SSHSession, Error> {
+        let options = remote.options.or(&current().config().connection);
+        let mut retry = 0;
+        let session = loop {
+            match Self::handshake(remote, &options) {
+                Ok(session) => break session,
+                // the auth error is never retried, it will fail again
+                Err(e @ Error::Transport(_)) if retry < options.get_retries() => {
+                    let delay = options.get_backoff(retry);
+                    let delay = Self::remaining()?.map_or(delay, |left| left.min(delay));
+                    warn!(remote = remote.to_string(), error = %e, retry = retry + 1, delay = ?delay, "connect failed, retry later");
+                    std::thread::sleep(delay);
+                    retry += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        session.set_timeout(Self::millis(Self::limit(options.get_auth_timeout())?));
+        Self::userauth(&session, remote)?;
+        current().emit(Event::Authenticated {
+            remote: remote.to_string(),
+        });
+        // the timeout of the async call is kept for the whole session
+        session.set_timeout(Self::millis(Self::remaining()?));
+        if options.get_keepalive() > 0 {
+            session.set_keepalive(true, options.get_keepalive());
+        }
+        debug!(remote = remote.to_string(), "create session success");
+        Ok(SSHSession {
+            session,
+            remote: remote.to_string(),
+        })
+    }
+
+    /// connect the tcp and exchange the keys, the timeouts are from the options.
+    fn handshake(remote: &Remote, options: &ConnectOptions) -> Result<Session, Error> {
+        let timeout = Self::limit(options.get_connect_timeout())?;
+        current().emit(Event::Connecting {
+            remote: remote.to_string(),
+        });
+        let (host, port) = (remote.ip.as_str(), remote.port);
+        let tcp = match options.get_proxy()? {
+            Some(proxy) => Self::connect(&proxy.host, proxy.port, timeout)
+                .and_then(|tcp| proxy.tunnel(tcp, host, port, timeout))
+                .map_err(|e| {
+                    let host = bracket(host);
+                    Error::Transport(format!("connect {host}:{port} via {proxy} failed: {e}"))
+                })?,
+            None => Self::connect(host, port, timeout).map_err(|e| {
+                let host = bracket(host);
+                Error::Transport(format!("connect {host}:{port} failed: {e}"))
+            })?,
+        };
         let mut session = Session::new()?;
+        Self::method_pref(&session, options)?;
+        session.set_timeout(Self::millis(Self::limit(options.get_handshake_timeout())?));
         session.set_tcp_stream(tcp);
+        current().emit(Event::Handshake {
+            remote: remote.to_string(),
+        });
         session.handshake()?;
-        session.userauth_password(user, password)?;
-        debug!(ip = ip, port = port, "create session success");
-        Ok(SSHSession { session })
+        Ok(session)
+    }
+
+    /// the algorithms of the options over the default ones of libssh2.
+    fn method_pref(session: &Session, options: &ConnectOptions) -> Result<(), Error> {
+        let methods: [(&Option<String>, &[MethodType]); 4] = [
+            (&options.kex, &[MethodType::Kex]),
+            (&options.hostkey, &[MethodType::HostKey]),
+            (
+                &options.ciphers,
+                &[MethodType::CryptCs, MethodType::CryptSc],
+            ),
+            (&options.macs, &[MethodType::MacCs, MethodType::MacSc]),
+        ];
+        for (algorithms, types) in methods {
+            let Some(algorithms) = algorithms else {
+                continue;
+            };
+            for &method in types {
+                let supported = session.supported_algs(method)?;
+                let prefs = prefer(algorithms, &supported);
+                // the unsupported ones are ignored by libssh2, so at least one is required
+                if !prefs.split(',').any(|n| supported.contains(&n)) {
+                    return Err(Error::InvalidInput(format!(
+                        "none of `{algorithms}` is supported, one of `{}`",
+                        supported.join(",")
+                    )));
+                }
+                debug!(prefs = prefs, "set the algorithms");
+                session.method_pref(method, &prefs)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// the time left of the async call, see `AsyncAtsh`.
+    fn remaining() -> Result<Option<Duration>, Error> {
+        #[cfg(feature = "async")]
+        return crate::asynchronous::remaining();
+        #[cfg(not(feature = "async"))]
+        Ok(None)
+    }
+
+    /// fail if the async call is cancelled or timed out, checked between the steps
+    /// so that nothing more is done on the blocking thread.
+    pub(crate) fn checkpoint() -> Result<(), Error> {
+        Self::remaining().map(|_| ())
+    }
+
+    /// the timeout no longer than the time left of the async call.
+    fn limit(timeout: Option<Duration>) -> Result<Option<Duration>, Error> {
+        Ok(match (timeout, Self::remaining()?) {
+            (Some(timeout), Some(left)) => Some(timeout.min(left)),
+            (timeout, left) => timeout.or(left),
+        })
     }
-    pub fn authenticate(&self) -> Result<(), Error> {
-        // check public key exist in $HOME/.ssh/id_rsa.pub
-        let pub_key = CONFIG.read_public()?;
+
+    /// the milliseconds of `Session::set_timeout`, 0 means no timeout.
+    fn millis(timeout: Option<Duration>) -> u32 {
+        timeout.map_or(0, |t| t.as_millis().clamp(1, u32::MAX as u128) as u32)
+    }
+
+    /// connect the addresses of the host one by one, the hostname may have IPv4 and IPv6 both.
+    pub(super) fn connect(
+        host: &str,
+        port: u16,
+        timeout: Option<Duration>,
+    ) -> std::io::Result<TcpStream> {
+        let mut error = None;
+        for addr in (host, port).to_socket_addrs()? {
+            let tcp = match timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            match tcp {
+                Ok(tcp) => return Ok(tcp),
+                Err(e) => {
+                    debug!(addr = %addr, error = %e, "connect failed, try the next address");
+                    error = Some(e)
+                }
+            }
+        }
+        Err(error.unwrap_or_else(|| std::io::Error::other(format!("no address of `{host}`"))))
+    }
+
+    /// login by password, or keyboard-interactive which answers the password and TOTP code.
+    fn userauth(session: &Session, remote: &Remote) -> Result<(), Error> {
+        let methods = session.auth_methods(&remote.user)?.to_string();
+        debug!(methods = methods, "server auth methods");
+        let mut error = None;
+        if methods.split(',').any(|m| m == "password") {
+            if let Err(e) = session.userauth_password(&remote.user, &remote.password) {
+                debug!(error = ?e, "password authentication failed");
+                error = Some(e);
+            }
+        }
+        // the partial success of password also need keyboard-interactive, like 2FA
+        if !session.authenticated() && methods.split(',').any(|m| m == "keyboard-interactive") {
+            let mut responder = Responder {
+                password: &remote.password,
+                totp: remote.totp.as_deref(),
+            };
+            if let Err(e) = session.userauth_keyboard_interactive(&remote.user, &mut responder) {
+                debug!(error = ?e, "keyboard-interactive authentication failed");
+                error = Some(e);
+            }
+        }
+        if session.authenticated() {
+            return Ok(());
+        }
+        Err(match error {
+            Some(e) => e.into(),
+            None => Error::Auth(format!(
+                "no supported authentication methods in `{methods}`"
+            )),
+        })
+    }
+
+    /// install the public key to `authorized_keys`, return false if it already exists.
+    pub fn authenticate(&self, pub_key: &str) -> Result<bool, Error> {
+        let key = AuthorizedKey::parse(0, pub_key)
+            .ok_or_else(|| Error::InvalidInput("invalid public key".to_string()))?;
         // get remote server home dir
-        let remote_home = self.read_exec("echo $HOME")?;
-        debug!("remote home: {}", remote_home);
-        let remote_keys = format!("{}/.ssh/authorized_keys", remote_home);
-        let file = Path::new(&remote_keys);
-        match self.read_file(file)? {
+        let file = self.authorized_keys()?;
+        match self.read_file(&file)? {
             Some(data) => {
-                if data.contains(&pub_key) {
+                if parse_authorized_keys(&data)
+                    .iter()
+                    .any(|k| k.blob == key.blob)
+                {
                     debug!("public key exist in authorized_keys");
+                    return Ok(false);
                 } else {
                     debug!("add the public key to authorized_keys");
                     let mut data = data;
-                    data.push_str("\n");
-                    data.push_str(&pub_key);
-                    self.write_file(file, &data, 0o600)?;
+                    if !data.is_empty() && !data.ends_with('\n') {
+                        data.push('\n');
+                    }
+                    data.push_str(pub_key.trim());
+                    data.push('\n');
+                    self.write_file(&file, &data, 0o600)?;
                 }
             }
             None => {
                 debug!(
-                    "authorized_keys not found in {} and we will create one",
-                    remote_keys
+                    "authorized_keys not found in {:?} and we will create one",
+                    file
                 );
-                self.write_file(file, &pub_key, 0o600)?;
+                self.write_file(&file, &format!("{}\n", pub_key.trim()), 0o600)?;
             }
         }
         debug!("remote authenticate success");
-        Ok(())
+        Ok(true)
     }
 
-    pub fn revoke(&self) -> Result<(), Error> {
-        let public_key = CONFIG.read_public()?;
-        let remote_home = self.read_exec("echo $HOME")?;
-        debug!("remote home: {}", remote_home);
-        let remote_key = format!("{}/.ssh/authorized_keys", remote_home);
-        let file = Path::new(&remote_key);
-        match self.read_file(file)? {
+    /// remove the public key from `authorized_keys`, return false if it not found.
+    pub fn revoke(&self, pub_key: &str) -> Result<bool, Error> {
+        let key = AuthorizedKey::parse(0, pub_key)
+            .ok_or_else(|| Error::InvalidInput("invalid public key".to_string()))?;
+        let file = self.authorized_keys()?;
+        match self.read_file(&file)? {
             Some(data) => {
-                if data.contains(&public_key) {
+                let matched =
+                    |line: &str| AuthorizedKey::parse(0, line).is_some_and(|k| k.blob == key.blob);
+                if data.lines().any(matched) {
                     debug!("public key found in authorized_keys, we will revoke it");
-                    let data = data.replace(&public_key, "");
+                    let mut data = data
+                        .lines()
+                        .filter(|line| !matched(line))
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    data.push('\n');
                     self.write_file(&file, &data, 0o600)?;
                     debug!("remote revoke success");
+                    return Ok(true);
                 } else {
                     debug!("public key not found in authorized_keys, skip revoke");
                 }
             }
             None => {
-                debug!("authorized_keys not found in {}, skip revoke", remote_key);
+                debug!("authorized_keys not found in {:?}, skip revoke", file);
+            }
+        }
+        Ok(false)
+    }
+
+    /// append a restricted entry to `authorized_keys` for the key of one session only.
+    /// `id` is written as the entry comment so that it can be removed later.
+    pub fn install_ephemeral(
+        &self,
+        public: &str,
+        id: &str,
+        ttl: u32,
+        restrict_from: bool,
+    ) -> Result<(), Error> {
+        let mut options = vec![];
+        if ttl > 0 {
+            // the `expiry-time` is interpreted in the remote system time zone
+            let expiry = self.read_exec(&format!(
+                "date -d '+{ttl} minutes' +%Y%m%d%H%M 2>/dev/null || date -v+{ttl}M +%Y%m%d%H%M"
+            ))?;
+            if expiry.len() != 12 || !expiry.chars().all(|c| c.is_ascii_digit()) {
+                return Err(Error::Transport(format!(
+                    "unable to compute the expiry time on remote host: {expiry:?}"
+                )));
+            }
+            options.push(format!("expiry-time=\"{expiry}\""));
+        }
+        if restrict_from {
+            // SSH_CLIENT is `client_ip client_port server_port`
+            let client = self.read_exec("echo $SSH_CLIENT")?;
+            match client.split_whitespace().next() {
+                Some(ip) => options.push(format!("from=\"{ip}\"")),
+                None => warn!("client address not found on remote host, skip the `from=` option"),
+            }
+        }
+        // the `type base64` part of the public key, we use our own comment
+        let key = public
+            .split_whitespace()
+            .take(2)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut entry = format!("{key} {EPHEMERAL_MARKER}:{id}");
+        if !options.is_empty() {
+            entry = format!("{} {entry}", options.join(","));
+        }
+
+        let file = self.authorized_keys()?;
+        let mut data = self.read_file(&file)?.unwrap_or_default();
+        if !data.is_empty() && !data.ends_with('\n') {
+            data.push('\n');
+        }
+        data.push_str(&entry);
+        data.push('\n');
+        self.write_file(&file, &data, 0o600)?;
+        debug!(id = id, "install ephemeral key success");
+        Ok(())
+    }
+
+    /// remove the ephemeral entries of `authorized_keys` which expired,
+    /// or installed by `host` and `keep(id)` returns false, see `ephemeral::sweep`.
+    pub fn sweep_ephemeral(&self, host: &str, keep: impl Fn(&str) -> bool) -> Result<usize, Error> {
+        let file = self.authorized_keys()?;
+        let data = match self.read_file(&file)? {
+            Some(data) => data,
+            None => return Ok(0),
+        };
+        let now = self.read_exec("date +%Y%m%d%H%M")?;
+        let (data, removed) = sweep(&data, &now, host, keep);
+        if removed > 0 {
+            self.write_file(&file, &data, 0o600)?;
+        }
+        Ok(removed)
+    }
+
+    /// trust the user CA by `TrustedUserCAKeys` of sshd, the login user must be root.
+    pub fn trust_ca(&self, ca_public: &str) -> Result<(), Error> {
+        let sshd_config = Path::new(SSHD_CONFIG);
+        let config = self.read_file(sshd_config)?.ok_or_else(|| {
+            Error::Auth(format!(
+                "unable to read {SSHD_CONFIG}, the CA must be installed by root"
+            ))
+        })?;
+        let ca_public = ca_public.trim();
+        let ca = AuthorizedKey::parse(0, ca_public)
+            .ok_or_else(|| Error::InvalidInput("invalid CA public key".to_string()))?;
+        // reuse the CA file already configured, otherwise add ours
+        let configured = config.lines().find_map(|line| {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some(k) if k.eq_ignore_ascii_case("TrustedUserCAKeys") => words.next(),
+                _ => None,
             }
+        });
+        let ca_file = PathBuf::from(configured.unwrap_or(ATSH_CA_KEYS));
+        let mut keys = self.read_file(&ca_file)?.unwrap_or_default();
+        if parse_authorized_keys(&keys)
+            .iter()
+            .any(|k| k.key_type == ca.key_type && k.blob == ca.blob)
+        {
+            debug!(file = ?ca_file, "CA already trusted");
+        } else {
+            if !keys.is_empty() && !keys.ends_with('\n') {
+                keys.push('\n');
+            }
+            keys.push_str(ca_public);
+            keys.push('\n');
+            self.write_file(&ca_file, &keys, 0o644)?;
         }
+        if configured.is_none() {
+            // the directive must be placed before any `Match` block
+            let mut lines = config.lines().collect::<Vec<_>>();
+            let directive = format!("TrustedUserCAKeys {ATSH_CA_KEYS}");
+            let at = lines
+                .iter()
+                .position(|l| {
+                    l.split_whitespace()
+                        .next()
+                        .is_some_and(|k| k.eq_ignore_ascii_case("Match"))
+                })
+                .unwrap_or(lines.len());
+            lines.insert(at, &directive);
+            let mut patched = lines.join("\n");
+            patched.push('\n');
+            // check the new config before it replaces the running one,
+            // a broken `sshd_config` locks everyone out on the next restart
+            let tmp = format!("{SSHD_CONFIG}.atsh");
+            self.write_file(Path::new(&tmp), &patched, 0o644)?;
+            let (status, output) = self.exec_status(&format!(
+                "(sshd -t -f {tmp} || /usr/sbin/sshd -t -f {tmp}) && mv -f {tmp} {SSHD_CONFIG}"
+            ))?;
+            if status != 0 {
+                let _ = self.exec_status(&format!("rm -f {tmp}"));
+                return Err(Error::Transport(format!(
+                    "invalid sshd config with `TrustedUserCAKeys`, nothing changed: {output}"
+                )));
+            }
+            let (status, output) = self.exec_status(
+                "systemctl reload sshd || systemctl reload ssh || service ssh reload || \
+                 kill -HUP $(cat /var/run/sshd.pid)",
+            )?;
+            if status != 0 {
+                // restore the original config, the sshd still runs with it
+                self.write_file(sshd_config, &config, 0o644)?;
+                return Err(Error::Transport(format!(
+                    "failed to reload sshd with `TrustedUserCAKeys`, the config is restored: {output}"
+                )));
+            }
+        }
+        debug!(file = ?ca_file, "trust CA success");
         Ok(())
     }
+
+    /// the `authorized_keys` location of the login user on remote host.
+    pub fn authorized_keys(&self) -> Result<PathBuf, Error> {
+        let remote_home = self.read_exec("echo $HOME")?;
+        debug!("remote home: {}", remote_home);
+        Ok(PathBuf::from(format!(
+            "{}/.ssh/authorized_keys",
+            remote_home
+        )))
+    }
+
     fn read_exec(&self, cmd: &str) -> Result<String, Error> {
         let mut channel = self.session.channel_session()?;
         channel.exec(cmd)?;
@@ -84,7 +442,20 @@ impl SSHSession {
         Ok(data.trim().to_string())
     }
 
-    fn read_file(&self, file: &Path) -> Result<Option<String>, Error> {
+    /// execute the command, return the exit status and the output (stdout and stderr)
+    fn exec_status(&self, cmd: &str) -> Result<(i32, String), Error> {
+        let mut channel = self.session.channel_session()?;
+        channel.handle_extended_data(ssh2::ExtendedData::Merge)?;
+        channel.exec(cmd)?;
+        let mut data = String::new();
+        channel.read_to_string(&mut data)?;
+        channel.wait_close()?;
+        Ok((channel.exit_status()?, data.trim().to_string()))
+    }
+
+    /// read the file by `scp`, `None` if not found. the other errors like permission denied
+    /// are returned, or the file is taken as empty and replaced.
+    pub fn read_file(&self, file: &Path) -> Result<Option<String>, Error> {
         match self.session.scp_recv(file) {
             Ok(cs) => {
                 let (mut channel, _) = cs;
@@ -93,17 +464,25 @@ impl SSHSession {
                 Ok(Some(data))
             }
             Err(e) => {
-                debug!(
-                    file=?file,
-                    error=?e,
-                    "read file with error (not found or permission denied?)"
-                );
-                Ok(None)
+                // libssh2 never tells why `scp` failed, but the status of SFTP does
+                let stat = self.session.sftp().and_then(|sftp| sftp.stat(file));
+                if let Err(s) = stat {
+                    // LIBSSH2_FX_NO_SUCH_FILE
+                    if s.code() == ssh2::ErrorCode::SFTP(2) {
+                        debug!(file=?file, error=?e, "read file not found");
+                        return Ok(None);
+                    }
+                }
+                Err(Error::Transport(format!(
+                    "unable to read {}: {}",
+                    file.display(),
+                    e.message()
+                )))
             }
         }
     }
 
-    fn write_file(&self, file: &Path, data: &str, mode: i32) -> Result<(), Error> {
+    pub fn write_file(&self, file: &Path, data: &str, mode: i32) -> Result<(), Error> {
         let mut channel = self.session.scp_send(file, mode, data.len() as u64, None)?;
         channel.write_all(data.as_bytes())?;
         channel.send_eof()?;
@@ -0,0 +1,187 @@
+use crate::error::Error;
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+/// check the tag name, like `prod`, `db` or `customer-x`.
+pub fn check_tag(tag: &str) -> Result<(), Error> {
+    let valid = tag.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid || tag == "all" {
+        return Err(Error::InvalidInput(format!("invalid tag `{tag}`, start with a letter and only `a-z0-9-_.` are allowed (`all` is reserved)")));
+    }
+    Ok(())
+}
+
+/// The tag expression to select the servers, like `prod & (db | cache) & !customer-x`.
+/// `,` is the same as `|`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagExpr {
+    Tag(String),
+    Not(Box<TagExpr>),
+    And(Box<TagExpr>, Box<TagExpr>),
+    Or(Box<TagExpr>, Box<TagExpr>),
+}
+
+impl TagExpr {
+    /// whether the tags of the server match the expression.
+    pub fn matches(&self, tags: &[String]) -> bool {
+        match self {
+            TagExpr::Tag(t) => tags.iter().any(|tag| tag == t),
+            TagExpr::Not(e) => !e.matches(tags),
+            TagExpr::And(a, b) => a.matches(tags) && b.matches(tags),
+            TagExpr::Or(a, b) => a.matches(tags) || b.matches(tags),
+        }
+    }
+}
+
+/// the recursive descent parser, `!` binds tighter than `&`, and `&` tighter than `|`.
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+        self.chars.peek().copied()
+    }
+
+    fn error(msg: String) -> Error {
+        Error::InvalidInput(msg)
+    }
+
+    fn or(&mut self) -> Result<TagExpr, Error> {
+        let mut expr = self.and()?;
+        while let Some('|' | ',') = self.peek() {
+            self.chars.next();
+            expr = TagExpr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<TagExpr, Error> {
+        let mut expr = self.not()?;
+        while let Some('&') = self.peek() {
+            self.chars.next();
+            expr = TagExpr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<TagExpr, Error> {
+        match self.peek() {
+            Some('!') => {
+                self.chars.next();
+                Ok(TagExpr::Not(Box::new(self.not()?)))
+            }
+            Some('(') => {
+                self.chars.next();
+                let expr = self.or()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.chars.next();
+                        Ok(expr)
+                    }
+                    _ => Err(Self::error("missing `)` in tag expression".to_string())),
+                }
+            }
+            Some(_) => {
+                let mut tag = String::new();
+                while let Some(c) = self
+                    .chars
+                    .next_if(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                {
+                    tag.push(c);
+                }
+                if tag.is_empty() {
+                    let c = self.chars.next().unwrap_or_default();
+                    return Err(Self::error(format!("unexpected `{c}` in tag expression")));
+                }
+                check_tag(&tag)?;
+                Ok(TagExpr::Tag(tag))
+            }
+            None => Err(Self::error("incomplete tag expression".to_string())),
+        }
+    }
+}
+
+impl FromStr for TagExpr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            chars: s.chars().peekable(),
+        };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(c) => Err(Parser::error(format!("unexpected `{c}` in tag expression"))),
+        }
+    }
+}
+
+/// The remote servers to operate, by index, tag expression or `all`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    All,
+    Index(usize),
+    Tags(TagExpr),
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "all" {
+            return Ok(Target::All);
+        }
+        if let Ok(idx) = s.parse() {
+            return Ok(Target::Index(idx));
+        }
+        s.parse().map(Target::Tags).map_err(|e| {
+            Error::InvalidInput(format!(
+                "`{s}` is not an index, tag expression or `all`: {e}"
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(v: &[&str]) -> Vec<String> {
+        v.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn test_tag_expr() {
+        let expr: TagExpr = "prod & (db | cache) & !customer-x".parse().unwrap();
+        assert!(expr.matches(&tags(&["prod", "db"])));
+        assert!(expr.matches(&tags(&["cache", "prod", "eu"])));
+        assert!(!expr.matches(&tags(&["prod", "db", "customer-x"])));
+        assert!(!expr.matches(&tags(&["db"])));
+
+        // `&` binds tighter than `|`, `,` is `|`
+        let expr: TagExpr = "a,b&c".parse().unwrap();
+        assert!(expr.matches(&tags(&["a"])));
+        assert!(!expr.matches(&tags(&["b"])));
+        assert!(expr.matches(&tags(&["b", "c"])));
+
+        for bad in ["", "prod &", "(prod", "prod)", "1db", "all", "prod $ db"] {
+            assert!(bad.parse::<TagExpr>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_target() {
+        assert_eq!("all".parse::<Target>().unwrap(), Target::All);
+        assert_eq!("12".parse::<Target>().unwrap(), Target::Index(12));
+        assert_eq!(
+            "prod".parse::<Target>().unwrap(),
+            Target::Tags(TagExpr::Tag("prod".to_string()))
+        );
+        assert!("-1".parse::<Target>().is_err());
+    }
+}
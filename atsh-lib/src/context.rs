@@ -0,0 +1,456 @@
+use crate::error::Error;
+use crate::event::{Event, Observer};
+use parking_lot::Mutex;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, OnceLock};
+use tracing::{debug, info};
+
+use crate::config::{check_atshkey, get_atshkey, get_work_dir, set_atshkey, Config, CONFIG};
+use crate::connection::{
+    pprint_audit, AuthorizedKey, ExecOutput, ExportOptions, ImportRow, KeyAudit, ListOptions,
+    Outcome, Remote, RemoteEdit, Remotes, Target,
+};
+use crate::storage::db::{db_init, Database};
+
+type Result<T> = std::result::Result<T, Error>;
+
+static GLOBAL: LazyLock<Atsh> = LazyLock::new(|| Atsh::new(get_work_dir().clone(), true));
+
+thread_local! {
+    // the `Atsh` in scope of the current thread, the last is used
+    static SCOPE: RefCell<Vec<Atsh>> = const { RefCell::new(vec![]) };
+}
+
+/// the `Atsh` in scope, or the default one.
+///
+/// the scope is per thread, a thread spawned in the scope starts with the default one,
+/// so pass the `Atsh` to it (like `AsyncAtsh` does) or capture `current()` before
+/// spawning and run the thread in its `scope`.
+pub(crate) fn current() -> Atsh {
+    SCOPE
+        .with(|s| s.borrow().last().cloned())
+        .unwrap_or_else(|| Atsh::global().clone())
+}
+
+/// pop the `Atsh` out of scope, even if the call panics.
+struct ScopeGuard;
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        SCOPE.with(|s| s.borrow_mut().pop());
+    }
+}
+
+struct Inner {
+    work_dir: PathBuf,
+    /// the default one uses the `CONFIG` and `ATSH_KEY` like before.
+    global: bool,
+    config: OnceLock<Config>,
+    key: Mutex<Option<String>>,
+    conn: Mutex<Option<Database>>,
+    observer: Mutex<Option<Arc<dyn Observer>>>,
+}
+
+/// The context owns the config, database and key of one work directory,
+/// so more than one vault can be opened in one process.
+///
+/// The free functions of `atsh` are the same as the methods of `Atsh::global()`.
+#[derive(Clone)]
+pub struct Atsh(Arc<Inner>);
+
+impl std::fmt::Debug for Atsh {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // never show the key
+        f.debug_struct("Atsh")
+            .field("work_dir", &self.0.work_dir)
+            .field("global", &self.0.global)
+            .finish()
+    }
+}
+
+impl Atsh {
+    fn new(work_dir: PathBuf, global: bool) -> Self {
+        Atsh(Arc::new(Inner {
+            work_dir,
+            global,
+            config: OnceLock::new(),
+            key: Mutex::new(None),
+            conn: Mutex::new(None),
+            observer: Mutex::new(None),
+        }))
+    }
+
+    /// the default one in the work directory of `initialize`, by `ATSH_KEY`.
+    pub fn global() -> &'static Atsh {
+        &GLOBAL
+    }
+
+    /// open the work directory, it is created if not exists.
+    /// the key is not read from the environment, set it by `set_enc_key`.
+    pub fn open(work_dir: impl AsRef<Path>) -> Result<Atsh> {
+        let work_dir = work_dir.as_ref().to_path_buf();
+        if !work_dir.exists() {
+            std::fs::create_dir_all(&work_dir)?;
+        }
+        debug!(work_dir = ?work_dir, "open atsh");
+        Ok(Atsh::new(work_dir, false))
+    }
+
+    /// run in the scope of this `Atsh`, the config, database and key are its own.
+    pub(crate) fn scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        SCOPE.with(|s| s.borrow_mut().push(self.clone()));
+        let _guard = ScopeGuard;
+        f()
+    }
+
+    pub fn work_dir(&self) -> &Path {
+        &self.0.work_dir
+    }
+
+    pub fn config(&self) -> &Config {
+        if self.0.global {
+            return &CONFIG;
+        }
+        self.0
+            .config
+            .get_or_init(|| self.scope(|| Config::open(&self.0.work_dir)))
+    }
+
+    /// get `ATSH_KEY` which used to encrypt the data like password
+    pub fn get_enc_key(&self) -> Result<String> {
+        if self.0.global {
+            return get_atshkey();
+        }
+        self.0.key.lock().clone().ok_or_else(|| {
+            Error::Crypto(format!(
+                "💥 Set the key of {:?} to protect password",
+                self.0.work_dir
+            ))
+        })
+    }
+
+    /// set `ATSH_KEY` which used to encrypt the data like password
+    pub fn set_enc_key(&self, key: Option<impl AsRef<str>>) -> Result<()> {
+        if self.0.global {
+            return set_atshkey(key);
+        }
+        if let Some(key) = &key {
+            check_atshkey(key.as_ref())?;
+        }
+        *self.0.key.lock() = key.map(|k| k.as_ref().to_string());
+        info!(work_dir = ?self.0.work_dir, "🔑 Set the key");
+        Ok(())
+    }
+
+    /// receive the events of the remote operations, `None` to stop.
+    pub fn set_observer(&self, observer: Option<Arc<dyn Observer>>) {
+        *self.0.observer.lock() = observer;
+    }
+
+    pub(crate) fn emit(&self, event: Event) {
+        // never call the observer with the lock held, it may set another one
+        let observer = self.0.observer.lock().clone();
+        if let Some(observer) = observer {
+            observer.on_event(&event);
+        }
+    }
+
+    /// the database `atsh.db` in the work directory, opened once.
+    pub(crate) fn connection(&self) -> Result<Database> {
+        let mut conn = self.0.conn.lock();
+        if let Some(db) = conn.as_ref() {
+            return Ok(db.clone());
+        }
+        let db = Database::new(db_init(&self.0.work_dir.join("atsh.db"))?);
+        *conn = Some(db.clone());
+        Ok(db)
+    }
+
+    pub fn add(
+        &self,
+        user: &str,
+        password: &str,
+        ip: &str,
+        port: u16,
+        name: &Option<impl AsRef<str>>,
+        note: &Option<impl AsRef<str>>,
+    ) -> Result<usize> {
+        self.scope(|| Remotes::add(user, password, ip, port, name, note))
+    }
+
+    pub fn add_remote(&self, remote: &Remote) -> Result<usize> {
+        self.scope(|| remote.add_record())
+    }
+
+    /// check the login of the remote server before it is added.
+    pub fn verify_remote(&self, remote: &Remote) -> Result<()> {
+        self.scope(|| remote.verify())
+    }
+
+    /// add the servers in one transaction, the failed rows are reported in `error`,
+    /// nothing is added if any row failed unless `partial`.
+    pub fn import(&self, rows: &mut [ImportRow], partial: bool) -> Result<usize> {
+        self.scope(|| Remotes::import(rows, partial))
+    }
+
+    /// copy the remote servers to another profile, encrypted by the key of that profile.
+    pub fn copy_to_profile(
+        &self,
+        rows: &mut [ImportRow],
+        profile: &str,
+        key: &str,
+        partial: bool,
+    ) -> Result<usize> {
+        self.scope(|| Remotes::copy(rows, profile, key, partial))
+    }
+
+    /// render the remote servers as an ansible YAML inventory.
+    pub fn export_ansible(&self, options: &ExportOptions) -> Result<String> {
+        self.scope(|| Remotes::select(&options.target)?.ansible(options))
+    }
+
+    /// update the remote server by its index.
+    pub fn update_remote(&self, remote: &Remote) -> Result<()> {
+        self.scope(|| remote.update_record())
+    }
+
+    /// apply the changes to the remote server by its index, return the updated one.
+    pub fn edit(&self, index: usize, edit: &RemoteEdit) -> Result<Remote> {
+        self.scope(|| {
+            let mut remote = Remotes::try_get(index)?;
+            edit.apply(&mut remote)?;
+            remote.update_record()?;
+            Ok(remote)
+        })
+    }
+
+    /// install the atsh key (or trust the CA) to the remote server by its index.
+    pub fn authenticate(&self, index: usize) -> Result<()> {
+        self.scope(|| Remotes::try_get(index)?.add_auth())
+    }
+
+    pub fn remove(&self, index: &[usize]) -> Result<usize> {
+        self.scope(|| Remotes::delete(index))
+    }
+
+    pub fn get(&self, index: usize) -> Result<Option<Remote>> {
+        self.scope(|| Remotes::get(index))
+    }
+
+    pub fn try_get(&self, index: usize) -> Result<Remote> {
+        self.scope(|| Remotes::try_get(index))
+    }
+
+    pub fn get_all(&self) -> Result<Vec<Remote>> {
+        self.scope(|| Ok(Remotes::get_all()?.0))
+    }
+
+    /// the remote servers by index, tag expression or all.
+    pub fn select(&self, target: &Target) -> Result<Vec<Remote>> {
+        self.scope(|| Ok(Remotes::select(target)?.0))
+    }
+
+    pub fn pprint(&self, all: bool) -> Result<()> {
+        self.list(&ListOptions {
+            all,
+            ..Default::default()
+        })
+    }
+
+    /// print the remote servers in the format, with the fields, filters and sort.
+    pub fn list(&self, options: &ListOptions) -> Result<()> {
+        self.scope(|| {
+            let remotes = Remotes::select(&options.target)?;
+            debug!("the remote list total: {}", remotes.0.len());
+            remotes.print(options)
+        })
+    }
+
+    /// render the remote servers in the format, like json for scripts.
+    pub fn render(&self, options: &ListOptions) -> Result<String> {
+        self.scope(|| Remotes::select(&options.target)?.render(options))
+    }
+
+    /// audit the `authorized_keys` of the remote servers.
+    /// the entries matched the `remove` fingerprints will be removed.
+    pub fn audit_keys(&self, target: &Target, remove: &[impl AsRef<str>]) -> Result<Vec<KeyAudit>> {
+        self.scope(|| Ok(Remotes::select(target)?.audit(remove)))
+    }
+
+    /// grant the public key (file or string) to login the remote servers,
+    /// the value is false if the key already exists on that server.
+    pub fn copy_id(&self, target: &Target, key: &str) -> Result<Vec<Outcome<bool>>> {
+        let key = read_public_key(key)?;
+        self.scope(|| Ok(Remotes::select(target)?.each(|remote| remote.copy_id(&key))))
+    }
+
+    /// revoke the public key (file or string) granted by `copy_id`,
+    /// the value is false if the key not found on that server.
+    pub fn revoke_id(&self, target: &Target, key: &str) -> Result<Vec<Outcome<bool>>> {
+        let key = read_public_key(key)?;
+        self.scope(|| Ok(Remotes::select(target)?.each(|remote| remote.revoke_id(&key))))
+    }
+
+    /// execute the command on the remote servers, the output is also emitted as `Event::Output`.
+    /// the failure of one server is reported in the result instead of stop.
+    pub fn exec(
+        &self,
+        target: &Target,
+        command: &str,
+        sudo: bool,
+    ) -> Result<Vec<Outcome<ExecOutput>>> {
+        self.scope(|| Ok(Remotes::select(target)?.each(|remote| remote.exec(command, sudo))))
+    }
+
+    pub fn pprint_keys(&self, audits: &[KeyAudit]) {
+        pprint_audit(audits)
+    }
+
+    // auth params means try auth against the server
+    pub fn login(&self, index: usize, auth: bool) -> Result<()> {
+        self.scope(|| Remotes::try_get(index)?.login(auth))
+    }
+
+    pub fn upload(&self, index: usize, path: &[impl AsRef<str>]) -> Result<()> {
+        if path.len() != 2 {
+            return Err(Error::InvalidInput(
+                "path format error, like `upload -p /local/path /remote/path`".to_string(),
+            ));
+        }
+        let (local, remote) = (path[0].as_ref(), path[1].as_ref());
+        if !Path::new(local).exists() {
+            return Err(Error::InvalidInput("the upload file not found".to_string()));
+        }
+
+        self.scope(|| Remotes::try_get(index)?.upload(local, remote))
+    }
+
+    pub fn download(&self, index: usize, path: &[impl AsRef<str>]) -> Result<()> {
+        if path.len() != 2 {
+            return Err(Error::InvalidInput(
+                "path format error, like `upload -p /remote/path /local/path`".to_string(),
+            ));
+        }
+        let (remote, local) = (path[0].as_ref(), path[1].as_ref());
+
+        self.scope(|| Remotes::try_get(index)?.download(remote, local))
+    }
+
+    /// list the directory on the remote server, the entry name and whether it is a directory.
+    pub fn list_dir(&self, index: usize, dir: &str) -> Result<Vec<(String, bool)>> {
+        self.scope(|| Remotes::try_get(index)?.list_dir(dir))
+    }
+}
+
+/// read the public key from file or the key string itself.
+fn read_public_key(key: &str) -> Result<String> {
+    let path = Path::new(key);
+    let key = if path.is_file() {
+        std::fs::read_to_string(path)?
+    } else {
+        key.to_string()
+    };
+    if AuthorizedKey::parse(0, &key).is_none() {
+        return Err(Error::InvalidInput(
+            "invalid public key, like `ssh-ed25519 AAAA... comment` or the `.pub` file".to_string(),
+        ));
+    }
+    Ok(key.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context() {
+        let dirs = [
+            "test.atsh.d/ctx-work",
+            "test.atsh.d/ctx-home",
+            "test.atsh.d/ctx-broken",
+        ];
+        for dir in dirs {
+            if Path::new(dir).exists() {
+                std::fs::remove_dir_all(dir).unwrap();
+            }
+        }
+        let work = Atsh::open(dirs[0]).unwrap();
+        let home = Atsh::open(dirs[1]).unwrap();
+        // the key is never shared with others
+        assert!(work.get_enc_key().is_err());
+        assert!(work.set_enc_key(Some("key")).is_err());
+        work.set_enc_key(Some("work-key")).unwrap();
+        home.set_enc_key(Some("home-key")).unwrap();
+
+        let remote = |user: &str| Remote {
+            user: user.to_string(),
+            password: format!("{user}-password"),
+            ip: "10.0.0.1".to_string(),
+            port: 22,
+            ..Default::default()
+        };
+        work.add_remote(&remote("alice")).unwrap();
+        work.add_remote(&remote("bob")).unwrap();
+        home.add_remote(&remote("carol")).unwrap();
+        assert_eq!(work.get_all().unwrap().len(), 2);
+        let carol = home.try_get(1).unwrap();
+        assert_eq!(carol.password, "carol-password");
+
+        // the own config and ssh key path of the work directory
+        assert!(work.config().get_private().starts_with(dirs[0]));
+        assert!(Path::new(dirs[0]).join("config.toml").is_file());
+        assert!(home.config().get_private().starts_with(dirs[1]));
+        assert_eq!(current().work_dir(), Atsh::global().work_dir());
+
+        // reopen by the same key
+        drop(work);
+        let work = Atsh::open(dirs[0]).unwrap();
+        // the edit without the key is rejected, or the password is saved in plaintext
+        let bob = Remote {
+            index: 2,
+            password: "new-password".to_string(),
+            ..remote("bob")
+        };
+        let e = work.scope(|| bob.update_record()).unwrap_err();
+        assert!(matches!(e, Error::Crypto(_)), "{e}");
+        work.set_enc_key(Some("work-key")).unwrap();
+        assert_eq!(work.try_get(2).unwrap().password, "bob-password");
+
+        // the database can't be opened, never panic
+        let broken = Path::new("test.atsh.d/ctx-broken");
+        std::fs::create_dir_all(broken.join("atsh.db")).unwrap();
+        assert!(Atsh::open(broken).unwrap().get_all().is_err());
+    }
+
+    #[test]
+    fn test_observer() {
+        let atsh = Atsh::open("test.atsh.d/ctx-observer").unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        atsh.set_observer(Some(Arc::new(tx)));
+        // nothing is listening on the port
+        let remote = Remote {
+            user: "root".to_string(),
+            ip: "127.0.0.1".to_string(),
+            port: 1,
+            ..Default::default()
+        };
+        assert!(atsh.verify_remote(&remote).is_err());
+        let events = rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(
+            events[0],
+            Event::Connecting {
+                remote: "root@127.0.0.1:1".to_string()
+            }
+        );
+        assert!(matches!(
+            events.last(),
+            Some(Event::Failed {
+                operation: crate::event::Operation::Verify,
+                ..
+            })
+        ));
+        // the global one is not observed
+        assert!(Atsh::global().0.observer.lock().is_none());
+    }
+}
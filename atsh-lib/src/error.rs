@@ -0,0 +1,147 @@
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::fmt;
+
+/// The error of the atsh library, the `code` is stable to tell one from another, like by the app.
+#[derive(Debug)]
+pub enum Error {
+    /// the local file or process, like the config and `ssh-keygen`.
+    Io(std::io::Error),
+    /// the database `atsh.db`.
+    Storage(rusqlite::Error),
+    /// the `ATSH_KEY` is missing or wrong.
+    Crypto(String),
+    /// the remote server is unreachable, or the ssh session failed.
+    Transport(String),
+    /// the remote server rejected the login, like the wrong password.
+    Auth(String),
+    /// no remote server of the index.
+    RemoteNotFound(usize),
+    /// the invalid argument, like the uri, tag, inventory or the existing record.
+    InvalidInput(String),
+}
+
+impl Error {
+    /// the stable code of the error.
+    pub fn code(&self) -> u16 {
+        match self {
+            Error::Io(_) => 1000,
+            Error::Storage(_) => 1001,
+            Error::Crypto(_) => 1002,
+            Error::Transport(_) => 1003,
+            Error::Auth(_) => 1004,
+            Error::RemoteNotFound(_) => 1005,
+            Error::InvalidInput(_) => 1006,
+        }
+    }
+
+    /// the local error like `std::io::Error::other`.
+    pub(crate) fn other(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        Error::Io(std::io::Error::other(e))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Storage(e) => write!(f, "database error: {e}"),
+            Error::Crypto(msg)
+            | Error::Transport(msg)
+            | Error::Auth(msg)
+            | Error::InvalidInput(msg) => write!(f, "{msg}"),
+            Error::RemoteNotFound(index) => write!(f, "index {index} remote not found"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Storage(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        match e {
+            // the error of decrypting the column, see `from_row`
+            rusqlite::Error::FromSqlConversionFailure(_, _, e) if e.is::<Error>() => {
+                *e.downcast::<Error>().expect("checked by `is`")
+            }
+            e => Error::Storage(e),
+        }
+    }
+}
+
+impl From<ssh2::Error> for Error {
+    fn from(e: ssh2::Error) -> Self {
+        // LIBSSH2_ERROR_AUTHENTICATION_FAILED and LIBSSH2_ERROR_PUBLICKEY_UNVERIFIED
+        match e.code() {
+            ssh2::ErrorCode::Session(-18 | -19) => Error::Auth(e.to_string()),
+            _ => Error::Transport(e.to_string()),
+        }
+    }
+}
+
+/// keep the `?` of the callers returning `std::io::Result`.
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> Self {
+        use std::io::ErrorKind;
+        let kind = match &e {
+            Error::Io(e) => return std::io::Error::new(e.kind(), e.to_string()),
+            Error::Storage(_) | Error::Transport(_) => ErrorKind::Other,
+            Error::Crypto(_) | Error::Auth(_) => ErrorKind::PermissionDenied,
+            Error::RemoteNotFound(_) => ErrorKind::NotFound,
+            Error::InvalidInput(_) => ErrorKind::InvalidInput,
+        };
+        std::io::Error::new(kind, e)
+    }
+}
+
+/// `{"code": 1002, "message": "..."}`, like the response of the app.
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Error", 2)?;
+        state.serialize_field("code", &self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error() {
+        let e = Error::RemoteNotFound(3);
+        assert_eq!(e.code(), 1005);
+        assert_eq!(e.to_string(), "index 3 remote not found");
+        assert_eq!(
+            serde_json::to_string(&e).unwrap(),
+            r#"{"code":1005,"message":"index 3 remote not found"}"#
+        );
+
+        // the crypto error in the row is not a storage error
+        let row = rusqlite::Error::FromSqlConversionFailure(
+            1,
+            rusqlite::types::Type::Text,
+            Box::new(Error::Crypto("wrong key".to_string())),
+        );
+        assert_eq!(Error::from(row).code(), 1002);
+        assert_eq!(Error::from(rusqlite::Error::InvalidQuery).code(), 1001);
+
+        let io: std::io::Error = Error::InvalidInput("bad".to_string()).into();
+        assert_eq!(io.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(io.to_string(), "bad");
+    }
+}
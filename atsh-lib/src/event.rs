@@ -0,0 +1,98 @@
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// The operation of the remote server which is finished or failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Verify,
+    Authenticate,
+    Revoke,
+    CopyId,
+    RevokeId,
+    Login,
+    Exec,
+    Upload,
+    Download,
+    ListDir,
+}
+
+/// The event of the remote operations, the `remote` is like `user@ip:port`.
+///
+/// It is serialized like `{"event": "transferred", "remote": "...", ...}` for the frontend.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// the tcp connection is started.
+    Connecting { remote: String },
+    /// the tcp is connected, the ssh handshake is started.
+    Handshake { remote: String },
+    /// the ssh session is logged in.
+    Authenticated { remote: String },
+    /// the public key is installed to `authorized_keys`, or the CA is trusted.
+    KeyInstalled { remote: String },
+    /// the public key is removed from `authorized_keys`.
+    KeyRevoked { remote: String },
+    /// the bytes of the file transferred by SFTP so far, and the size of the file.
+    /// `scp` emits it only at the start and end of the path (file or directory).
+    Transferred {
+        remote: String,
+        path: PathBuf,
+        bytes: u64,
+        total: u64,
+    },
+    /// the output of the command executed, stderr is merged.
+    Output { remote: String, data: String },
+    Finished {
+        remote: String,
+        operation: Operation,
+    },
+    Failed {
+        remote: String,
+        operation: Operation,
+        error: String,
+    },
+}
+
+/// The observer of the events, set by `Atsh::set_observer`.
+///
+/// It is called on the thread of the operation, so keep it quick,
+/// or send the events to a channel like `std::sync::mpsc::Sender<Event>`.
+pub trait Observer: Send + Sync {
+    fn on_event(&self, event: &Event);
+}
+
+impl<F: Fn(&Event) + Send + Sync> Observer for F {
+    fn on_event(&self, event: &Event) {
+        self(event)
+    }
+}
+
+impl Observer for std::sync::mpsc::Sender<Event> {
+    fn on_event(&self, event: &Event) {
+        // the receiver is gone, nobody cares
+        let _ = self.send(event.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event() {
+        let event = Event::Failed {
+            remote: "root@1.2.3.4:22".to_string(),
+            operation: Operation::CopyId,
+            error: "timeout".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"failed","remote":"root@1.2.3.4:22","operation":"copy_id","error":"timeout"}"#
+        );
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        tx.on_event(&event);
+        assert_eq!(rx.recv().unwrap(), event);
+    }
+}
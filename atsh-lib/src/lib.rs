@@ -1,26 +1,43 @@
+#[cfg(feature = "async")]
+mod asynchronous;
 mod config;
 mod connection;
+mod context;
+mod error;
+mod event;
 mod storage;
 
 pub mod atsh {
-    use std::io::{Error, ErrorKind};
+    pub use crate::error::Error;
+    pub use crate::event::{Event, Observer, Operation};
     use std::path::Path;
     use tracing::debug;
 
-    use crate::connection::Remotes;
     use crate::storage::log::setup_logging;
 
     type Result<T> = std::result::Result<T, Error>;
 
     // export the objects to the outside
-    pub use crate::config::CONFIG;
-    pub use crate::connection::Remote;
+    pub use crate::config::{
+        create_profile, current_profile, pprint_profiles, profile_key_var, read_fd, use_profile,
+        CONFIG, DEFAULT_PROFILE,
+    };
+    pub use crate::connection::{
+        proxy_tunnel, AuthMode, AuthorizedKey, ConnectOptions, ExecOutput, ExportOptions, Field,
+        Format, Grant, GroupsAs, ImportFormat, ImportRow, KeyAudit, KeyStatus, ListOptions,
+        Outcome, Proxy, ProxyKind, Remote, RemoteEdit, TagExpr, Target, PROXY_TUNNEL,
+    };
+    pub use crate::context::Atsh;
+
+    #[cfg(feature = "async")]
+    pub use crate::asynchronous::{AsyncAtsh, Cancel};
 
     pub fn initialize(work_dir: Option<impl AsRef<Path>>) -> Result<()> {
+        // never load the `CONFIG` before the work directory is set
         if let Some(p) = work_dir {
-            CONFIG.set_work_dir(p)?;
+            crate::config::set_work_dir(p)?;
         }
-        let w = CONFIG.get_work_dir();
+        let w = crate::config::get_work_dir();
         setup_logging(w)?;
         debug!("success initialize at {:?}", w);
         Ok(())
@@ -34,41 +51,127 @@ pub mod atsh {
         name: &Option<impl AsRef<str>>,
         note: &Option<impl AsRef<str>>,
     ) -> Result<usize> {
-        Remotes::add(user, password, ip, port, name, note)
+        Atsh::global().add(user, password, ip, port, name, note)
     }
 
     pub fn add_remote(remote: &Remote) -> Result<usize> {
-        add(
-            &remote.user,
-            &remote.password,
-            &remote.ip,
-            remote.port,
-            &remote.name,
-            &remote.note,
-        )
+        Atsh::global().add_remote(remote)
     }
 
-    pub fn remove(index: &Vec<usize>) -> Result<usize> {
-        Remotes::delete(index)
+    /// check the login of the remote server before it is added.
+    pub fn verify_remote(remote: &Remote) -> Result<()> {
+        Atsh::global().verify_remote(remote)
+    }
+
+    /// read the servers to import from csv or ansible inventory, see `import`.
+    pub fn read_import(
+        path: impl AsRef<Path>,
+        format: Option<ImportFormat>,
+        groups: GroupsAs,
+    ) -> Result<Vec<ImportRow>> {
+        crate::connection::read_import(path.as_ref(), format, groups)
+    }
+
+    /// add the servers in one transaction, the failed rows are reported in `error`,
+    /// nothing is added if any row failed unless `partial`.
+    pub fn import(rows: &mut [ImportRow], partial: bool) -> Result<usize> {
+        Atsh::global().import(rows, partial)
+    }
+
+    /// copy the remote servers to another profile, encrypted by the key of that profile.
+    pub fn copy_to_profile(
+        rows: &mut [ImportRow],
+        profile: &str,
+        key: &str,
+        partial: bool,
+    ) -> Result<usize> {
+        Atsh::global().copy_to_profile(rows, profile, key, partial)
+    }
+
+    /// render the remote servers as an ansible YAML inventory.
+    pub fn export_ansible(options: &ExportOptions) -> Result<String> {
+        Atsh::global().export_ansible(options)
+    }
+
+    pub fn pprint_import(rows: &[ImportRow]) {
+        crate::connection::pprint_import(rows)
+    }
+
+    /// update the remote server by its index.
+    pub fn update_remote(remote: &Remote) -> Result<()> {
+        Atsh::global().update_remote(remote)
+    }
+
+    /// apply the changes to the remote server by its index, return the updated one.
+    pub fn edit(index: usize, edit: &RemoteEdit) -> Result<Remote> {
+        Atsh::global().edit(index, edit)
+    }
+
+    /// install the atsh key (or trust the CA) to the remote server by its index.
+    pub fn authenticate(index: usize) -> Result<()> {
+        Atsh::global().authenticate(index)
+    }
+
+    pub fn remove(index: &[usize]) -> Result<usize> {
+        Atsh::global().remove(index)
     }
 
     pub fn get(index: usize) -> Result<Option<Remote>> {
-        Remotes::get(index)
+        Atsh::global().get(index)
     }
 
     pub fn try_get(index: usize) -> Result<Remote> {
-        Remotes::try_get(index)
+        Atsh::global().try_get(index)
     }
 
     pub fn get_all() -> Result<Vec<Remote>> {
-        let remotes = Remotes::get_all()?;
-        Ok(remotes.0)
+        Atsh::global().get_all()
+    }
+
+    /// the remote servers by index, tag expression or all.
+    pub fn select(target: &Target) -> Result<Vec<Remote>> {
+        Atsh::global().select(target)
     }
 
     pub fn pprint(all: bool) -> Result<()> {
-        let remotes = Remotes::get_all()?;
-        remotes.pprint(all);
-        Ok(())
+        Atsh::global().pprint(all)
+    }
+
+    /// print the remote servers in the format, with the fields, filters and sort.
+    pub fn list(options: &ListOptions) -> Result<()> {
+        Atsh::global().list(options)
+    }
+
+    /// render the remote servers in the format, like json for scripts.
+    pub fn render(options: &ListOptions) -> Result<String> {
+        Atsh::global().render(options)
+    }
+
+    /// audit the `authorized_keys` of the remote servers.
+    /// the entries matched the `remove` fingerprints will be removed.
+    pub fn audit_keys(target: &Target, remove: &[impl AsRef<str>]) -> Result<Vec<KeyAudit>> {
+        Atsh::global().audit_keys(target, remove)
+    }
+
+    /// grant the public key (file or string) to login the remote servers,
+    /// the value is false if the key already exists on that server.
+    pub fn copy_id(target: &Target, key: &str) -> Result<Vec<Outcome<bool>>> {
+        Atsh::global().copy_id(target, key)
+    }
+
+    /// revoke the public key (file or string) granted by `copy_id`,
+    /// the value is false if the key not found on that server.
+    pub fn revoke_id(target: &Target, key: &str) -> Result<Vec<Outcome<bool>>> {
+        Atsh::global().revoke_id(target, key)
+    }
+
+    /// execute the command on the remote servers, the output is also emitted as `Event::Output`.
+    pub fn exec(target: &Target, command: &str, sudo: bool) -> Result<Vec<Outcome<ExecOutput>>> {
+        Atsh::global().exec(target, command, sudo)
+    }
+
+    pub fn pprint_keys(audits: &[KeyAudit]) {
+        Atsh::global().pprint_keys(audits)
     }
 
     // pub fn list(all: bool) -> Result<()> {
@@ -81,8 +184,7 @@ pub mod atsh {
 
     // auth params means try auth against the server
     pub fn login(index: usize, auth: bool) -> Result<()> {
-        let remote = Remotes::try_get(index)?;
-        remote.login(auth)
+        Atsh::global().login(index, auth)
     }
 
     #[deprecated(
@@ -92,12 +194,11 @@ pub mod atsh {
     pub fn copy(index: usize, path: &str) -> Result<()> {
         let paths = path.split('=').collect::<Vec<&str>>();
         if paths.len() != 2 {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                "path format error, like `from=to`",
+            return Err(Error::InvalidInput(
+                "path format error, like `from=to`".to_string(),
             ));
         }
-        let remote = Remotes::try_get(index)?;
+        let remote = try_get(index)?;
         if std::path::PathBuf::from(paths[0]).exists() {
             remote.upload(paths[0], paths[1])
         } else {
@@ -105,30 +206,16 @@ pub mod atsh {
         }
     }
 
-    pub fn upload(index: usize, path: &Vec<impl AsRef<str>>) -> Result<()> {
-        if path.len() != 2 {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                "path format error, like `upload -p /local/path /remote/path`",
-            ));
-        }
-        let (local, remote) = (path[0].as_ref(), path[1].as_ref());
-        if !Path::new(local).exists() {
-            return Err(Error::new(ErrorKind::NotFound, "the upload file not found"));
-        }
-
-        Remotes::try_get(index)?.upload(local, remote)
+    pub fn upload(index: usize, path: &[impl AsRef<str>]) -> Result<()> {
+        Atsh::global().upload(index, path)
     }
 
-    pub fn download(index: usize, path: &Vec<impl AsRef<str>>) -> Result<()> {
-        if path.len() != 2 {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                "path format error, like `upload -p /remote/path /local/path`",
-            ));
-        }
-        let (remote, local) = (path[0].as_ref(), path[1].as_ref());
+    pub fn download(index: usize, path: &[impl AsRef<str>]) -> Result<()> {
+        Atsh::global().download(index, path)
+    }
 
-        Remotes::try_get(index)?.download(remote, local)
+    /// list the directory on the remote server, the entry name and whether it is a directory.
+    pub fn list_dir(index: usize, dir: &str) -> Result<Vec<(String, bool)>> {
+        Atsh::global().list_dir(index, dir)
     }
 }
@@ -1,20 +1,36 @@
-use parking_lot::Mutex;
-use rusqlite::{params, Connection, Result};
+use parking_lot::{ArcMutexGuard, Mutex, RawMutex};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, Type, ValueRef};
+use rusqlite::{params, Connection, Result, Row};
 use std::path::Path;
-use std::sync::OnceLock;
+use std::sync::Arc;
 use tracing::{debug, warn};
 
-use super::secure::{decrypt, encrypt};
-use crate::config::CONFIG;
-use crate::connection::Remote;
+use super::secure::{decrypt, encrypt, encrypt_by, is_encrypted_by};
+use crate::connection::{AuthMode, ConnectOptions, Grant, Remote};
+use crate::context::current;
+use crate::error::Error;
 
-static DATABASE: OnceLock<Mutex<Connection>> = OnceLock::new();
+/// The database `atsh.db` of one `Atsh`, shared by the clones.
+#[derive(Clone)]
+pub struct Database(Arc<Mutex<Connection>>);
 
-pub fn get_connection() -> &'static Mutex<Connection> {
-    DATABASE.get_or_init(|| Mutex::new(db_init(&CONFIG.work_dir_file("atsh.db")).unwrap()))
+impl Database {
+    pub(crate) fn new(conn: Connection) -> Self {
+        Database(Arc::new(Mutex::new(conn)))
+    }
+
+    /// the guard keeps the database alive, so it outlives the `Database`.
+    pub fn lock(&self) -> ArcMutexGuard<RawMutex, Connection> {
+        self.0.lock_arc()
+    }
+}
+
+/// the database of the `Atsh` in scope.
+pub fn get_connection() -> std::result::Result<Database, Error> {
+    current().connection()
 }
 
-fn db_init(p: &Path) -> Result<Connection> {
+pub(crate) fn db_init(p: &Path) -> Result<Connection> {
     if p.is_file() {
         debug!(file=?p, "Loading Database exists and using");
     } else {
@@ -38,13 +54,156 @@ fn db_init(p: &Path) -> Result<Connection> {
     )?;
     // 创建索引
     // conn.execute("CREATE INDEX IF NOT EXISTS idx_remote ON records (idx)", [])?;
+    // the public keys granted to the remote server by `copy-id`
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS grants (
+            idx INTEGER NOT NULL,  -- the index of records
+            fingerprint TEXT NOT NULL,
+            comment TEXT,
+            granted_by TEXT NOT NULL,
+            granted_at INTEGER NOT NULL,
+            UNIQUE(idx, fingerprint)
+        )",
+        [],
+    )?;
+    // the tags of the remote servers, many-to-many
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS tags (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS record_tags (
+            idx INTEGER NOT NULL,  -- the index of records
+            tag_id INTEGER NOT NULL,  -- the id of tags
+            UNIQUE(idx, tag_id)
+        )",
+        [],
+    )?;
+    migrate(&conn)?;
     Ok(conn)
 }
 
+/// the columns added after the first release, (name, definition)
+const MIGRATIONS: &[(&str, &str)] = &[
+    ("mode", "TEXT NOT NULL DEFAULT 'key'"),
+    ("principals", "TEXT"),
+    ("totp", "TEXT"),
+    ("sudo_password", "TEXT"),
+    ("options", "TEXT"),
+];
+
+/// add the new columns to the database created by the old version
+fn migrate(conn: &Connection) -> Result<()> {
+    let columns = conn
+        .prepare("SELECT name FROM pragma_table_info('records')")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>>>()?;
+    for (name, definition) in MIGRATIONS {
+        if columns.iter().any(|c| c == name) {
+            continue;
+        }
+        debug!(column = name, "Migrating database with new column");
+        conn.execute(
+            &format!("ALTER TABLE records ADD COLUMN {name} {definition}"),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+impl ToSql for AuthMode {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+impl FromSql for AuthMode {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+/// the options are saved as json, `NULL` if no option is set.
+impl ToSql for ConnectOptions {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>> {
+        if self.is_empty() {
+            return Ok(ToSqlOutput::from(rusqlite::types::Null));
+        }
+        let json = serde_json::to_string(self)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+        Ok(ToSqlOutput::from(json))
+    }
+}
+
+impl FromSql for ConnectOptions {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Null => Ok(ConnectOptions::default()),
+            value => {
+                serde_json::from_str(value.as_str()?).map_err(|e| FromSqlError::Other(e.into()))
+            }
+        }
+    }
+}
+
+/// decrypt the column, the wrong key is the `Crypto` error, see `Error::from`.
+fn decrypt_column(row: &Row, column: &str) -> Result<Option<String>> {
+    row.get::<_, Option<String>>(column)?
+        .map(|data| {
+            decrypt(data).map_err(|e| {
+                let idx = row.as_ref().column_index(column).unwrap_or_default();
+                rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, Box::new(e))
+            })
+        })
+        .transpose()
+}
+
+fn from_row(row: &Row) -> Result<Remote> {
+    Ok(Remote {
+        index: row.get("idx")?,
+        user: row.get("user")?,
+        password: decrypt_column(row, "password")?.unwrap_or_default(),
+        ip: row.get("ip")?,
+        port: row.get("port")?,
+        authorized: row.get("authorized")?,
+        name: row.get("name")?,
+        note: row.get("note")?,
+        mode: row.get("mode")?,
+        principals: row.get("principals")?,
+        totp: decrypt_column(row, "totp")?,
+        sudo_password: decrypt_column(row, "sudo_password")?,
+        tags: row
+            .get::<_, Option<String>>("tags")?
+            .map(|t| t.split(',').map(|t| t.to_string()).collect())
+            .unwrap_or_default(),
+        options: row.get("options")?,
+    })
+}
+
+/// the columns of `records` and the tags joined by comma.
+const SELECT_RECORDS: &str = "SELECT idx, user, password, ip, port, authorized, name, note, mode, principals, totp, sudo_password, options,
+         (SELECT group_concat(name, ',') FROM (
+            SELECT tags.name FROM record_tags JOIN tags ON tags.id = record_tags.tag_id
+            WHERE record_tags.idx = records.idx ORDER BY tags.name)
+         ) AS tags
+         FROM records";
+
 pub(crate) fn insert(conn: &Connection, remote: &Remote) -> Result<usize> {
-    conn.execute(
-        "INSERT INTO records (user, password, ip, port, authorized, name, note)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    insert_by(conn, remote, current().get_enc_key().ok().as_deref())
+}
+
+/// insert the record encrypted by the given key, like the key of another profile.
+pub(crate) fn insert_by(conn: &Connection, remote: &Remote, key: Option<&str>) -> Result<usize> {
+    let encrypt = |data: &String| encrypt_by(data, key);
+    let n = conn.execute(
+        "INSERT INTO records (user, password, ip, port, authorized, name, note, mode, principals, totp, sudo_password, options)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
         params![
             remote.user,
             encrypt(&remote.password),
@@ -53,28 +212,72 @@ pub(crate) fn insert(conn: &Connection, remote: &Remote) -> Result<usize> {
             remote.authorized,
             remote.name,
             remote.note,
+            remote.mode,
+            remote.principals,
+            remote.totp.as_ref().map(encrypt),
+            remote.sudo_password.as_ref().map(encrypt),
+            remote.options,
         ],
-    )
+    )?;
+    let idx = conn.last_insert_rowid() as usize;
+    set_tags(conn, idx, &remote.tags)?;
+    Ok(n)
 }
 
-pub(crate) fn query_index(conn: &Connection, idx: usize) -> Result<Option<Remote>> {
-    let mut stmt = conn.prepare(
-        "SELECT idx, user, password, ip, port, authorized, name, note 
-         FROM records 
-         WHERE idx = ?1",
+/// update all fields of the record by index.
+pub(crate) fn update(conn: &Connection, remote: &Remote) -> Result<usize> {
+    let n = conn.execute(
+        "UPDATE records SET user = ?1, password = ?2, ip = ?3, port = ?4, authorized = ?5,
+         name = ?6, note = ?7, mode = ?8, principals = ?9, totp = ?10, sudo_password = ?11,
+         options = ?12 WHERE idx = ?13",
+        params![
+            remote.user,
+            encrypt(&remote.password),
+            remote.ip,
+            remote.port,
+            remote.authorized,
+            remote.name,
+            remote.note,
+            remote.mode,
+            remote.principals,
+            remote.totp.as_ref().map(encrypt),
+            remote.sudo_password.as_ref().map(encrypt),
+            remote.options,
+            remote.index,
+        ],
     )?;
-    let result = stmt.query_row(params![idx], |row| {
-        Ok(Remote {
-            index: row.get("idx")?,
-            user: row.get("user")?,
-            password: decrypt(&row.get::<_, String>("password")?),
-            ip: row.get("ip")?,
-            port: row.get("port")?,
-            authorized: row.get("authorized")?,
-            name: row.get("name")?,
-            note: row.get("note")?,
-        })
-    });
+    // never add the tags to a record not found
+    if n == 0 {
+        return Ok(0);
+    }
+    set_tags(conn, remote.index, &remote.tags)?;
+    Ok(n)
+}
+
+/// replace the tags of the record, the tags not used by any record are removed.
+pub(crate) fn set_tags(conn: &Connection, idx: usize, tags: &[String]) -> Result<()> {
+    conn.execute("DELETE FROM record_tags WHERE idx = ?1", params![idx])?;
+    for tag in tags.iter() {
+        conn.execute(
+            "INSERT OR IGNORE INTO tags (name) VALUES (?1)",
+            params![tag],
+        )?;
+        conn.execute(
+            "INSERT OR IGNORE INTO record_tags (idx, tag_id)
+             SELECT ?1, id FROM tags WHERE name = ?2",
+            params![idx, tag],
+        )?;
+    }
+    conn.execute(
+        "DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM record_tags)",
+        [],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn query_index(conn: &Connection, idx: usize) -> Result<Option<Remote>> {
+    let mut stmt = conn.prepare(&format!("{SELECT_RECORDS} WHERE idx = ?1"))?;
+    let result = stmt.query_row(params![idx], from_row);
     match result {
         Ok(remote) => Ok(Some(remote)),
         Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
@@ -82,26 +285,54 @@ pub(crate) fn query_index(conn: &Connection, idx: usize) -> Result<Option<Remote
     }
 }
 
+/// the index of the record with the same `user@ip:port`.
+pub(crate) fn query_target(
+    conn: &Connection,
+    user: &str,
+    ip: &str,
+    port: u16,
+) -> Result<Option<usize>> {
+    let mut stmt = conn.prepare(
+        "SELECT idx FROM records WHERE user = ?1 AND ip = ?2 AND port = ?3 ORDER BY idx LIMIT 1",
+    )?;
+    match stmt.query_row(params![user, ip, port], |row| row.get(0)) {
+        Ok(idx) => Ok(Some(idx)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// whether the records are encrypted by the key, `true` if no records.
+pub(crate) fn check_key(conn: &Connection, key: &str) -> Result<bool> {
+    // every secret, the records may be encrypted by different keys already
+    let mut stmt = conn.prepare("SELECT password, totp, sudo_password FROM records")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let password = row.get::<_, String>(0)?;
+        let totp = row.get::<_, Option<String>>(1)?;
+        let sudo = row.get::<_, Option<String>>(2)?;
+        let mut secrets = std::iter::once(password).chain(totp).chain(sudo);
+        if !secrets.all(|s| is_encrypted_by(s, key)) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// the number of records.
+pub(crate) fn count(conn: &Connection) -> Result<usize> {
+    conn.query_row("SELECT COUNT(*) FROM records", [], |row| row.get(0))
+}
+
 pub(crate) fn query_all(conn: &Connection) -> Result<Vec<Remote>> {
-    let mut stmt = conn.prepare("SELECT * FROM records")?;
-    let records = stmt
-        .query_map([], |row| {
-            Ok(Remote {
-                index: row.get("idx")?,
-                user: row.get("user")?,
-                password: decrypt(&row.get::<_, String>("password")?),
-                authorized: row.get("authorized")?,
-                ip: row.get("ip")?,
-                port: row.get("port")?,
-                name: row.get("name")?,
-                note: row.get("note")?,
-            })
-        })?
-        .collect::<Result<Vec<_>>>()?;
+    let mut stmt = conn.prepare(SELECT_RECORDS)?;
+    let records = stmt.query_map([], from_row)?.collect::<Result<Vec<_>>>()?;
     Ok(records)
 }
 
 pub(crate) fn delete_index(conn: &Connection, idx: usize) -> Result<usize> {
+    conn.execute("DELETE FROM grants WHERE idx = ?", params![idx])?;
+    set_tags(conn, idx, &[])?;
     conn.execute("DELETE FROM records WHERE idx = ?", params![idx])
 }
 
@@ -112,14 +343,85 @@ pub(crate) fn update_authorized(conn: &Connection, idx: usize, authorized: bool)
     )
 }
 
+pub(crate) fn insert_grant(conn: &Connection, idx: usize, grant: &Grant) -> Result<usize> {
+    conn.execute(
+        "INSERT OR REPLACE INTO grants (idx, fingerprint, comment, granted_by, granted_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            idx,
+            grant.fingerprint,
+            grant.comment,
+            grant.granted_by,
+            grant.granted_at,
+        ],
+    )
+}
+
+pub(crate) fn delete_grant(conn: &Connection, idx: usize, fingerprint: &str) -> Result<usize> {
+    conn.execute(
+        "DELETE FROM grants WHERE idx = ?1 AND fingerprint = ?2",
+        params![idx, fingerprint],
+    )
+}
+
+pub(crate) fn query_grants(conn: &Connection, idx: usize) -> Result<Vec<Grant>> {
+    let mut stmt = conn.prepare(
+        "SELECT fingerprint, comment, granted_by, granted_at FROM grants WHERE idx = ?1",
+    )?;
+    let grants = stmt
+        .query_map(params![idx], |row| {
+            Ok(Grant {
+                fingerprint: row.get("fingerprint")?,
+                comment: row.get("comment")?,
+                granted_by: row.get("granted_by")?,
+                granted_at: row.get("granted_at")?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(grants)
+}
+
 // test
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_check_key() {
+        let path =
+            std::env::temp_dir().join(format!("atsh-test-check-key-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let conn = db_init(&path).unwrap();
+        assert!(check_key(&conn, "one").unwrap());
+        let remote = Remote {
+            user: "root".to_string(),
+            password: "password".to_string(),
+            ip: "1.2.3.4".to_string(),
+            port: 22,
+            ..Default::default()
+        };
+        insert_by(&conn, &remote, Some("one")).unwrap();
+        assert!(check_key(&conn, "one").unwrap());
+        assert!(!check_key(&conn, "two").unwrap());
+        // the later record is encrypted by another key
+        let sudo = Remote {
+            sudo_password: Some("sudo".to_string()),
+            ..remote.clone()
+        };
+        insert_by(&conn, &sudo, Some("one")).unwrap();
+        conn.execute(
+            "UPDATE records SET sudo_password = ?1 WHERE idx = 2",
+            params![encrypt_by("sudo", Some("two"))],
+        )
+        .unwrap();
+        assert!(!check_key(&conn, "one").unwrap());
+        drop(conn);
+        let _ = std::fs::remove_file(&path);
+    }
+
     #[test]
     fn test_db() {
-        let db_path = CONFIG.work_dir_file("atsh.db");
+        let db_path = current().work_dir().join("atsh.db");
 
         let remote = Remote {
             index: 1,
@@ -130,6 +432,12 @@ mod tests {
             port: 2222,
             name: Some("name".to_string()),
             note: None,
+            mode: AuthMode::Ephemeral,
+            principals: Some("user,admin".to_string()),
+            totp: Some("GEZDGNBVGY3TQOJQ".to_string()),
+            sudo_password: Some("sudo".to_string()),
+            tags: vec!["db".to_string(), "prod".to_string()],
+            options: ConnectOptions::default(),
         };
         // init
         // crate::atsh::initialize(Option::<&str>::None).unwrap();
@@ -147,8 +455,8 @@ mod tests {
 
         // test insert 2
         {
-            let conn = get_connection().lock();
-            println!("conn: {:#?}", conn);
+            let conn = get_connection().unwrap().lock();
+            println!("conn: {:#?}", *conn);
             let n = insert(&conn, &remote);
             assert!(n.is_ok());
             assert_eq!(n.unwrap(), 1);
@@ -158,21 +466,93 @@ mod tests {
         // update auth
         {
             {
-                let conn = get_connection().lock();
+                let conn = get_connection().unwrap().lock();
                 let n = update_authorized(&conn, 1, true);
                 assert!(n.is_ok());
                 assert_eq!(n.unwrap(), 1);
             }
-            let conn = get_connection().lock();
+            let conn = get_connection().unwrap().lock();
             let one = query_index(&conn, 1);
             assert!(one.is_ok());
             let one = one.unwrap().unwrap();
-            assert_eq!(one.authorized, true);
+            assert!(one.authorized);
+        }
+
+        // query the same target
+        {
+            let conn = get_connection().unwrap().lock();
+            assert_eq!(
+                query_target(&conn, "user", "1.2.3.4", 2222).unwrap(),
+                Some(1)
+            );
+            assert_eq!(query_target(&conn, "user", "1.2.3.4", 22).unwrap(), None);
+        }
+
+        // update record and tags
+        {
+            let conn = get_connection().unwrap().lock();
+            let mut one = query_index(&conn, 1).unwrap().unwrap();
+            one.note = Some("note".to_string());
+            one.tags = vec!["web".to_string()];
+            one.options.connect_timeout = Some(5);
+            assert_eq!(update(&conn, &one).unwrap(), 1);
+            let one = query_index(&conn, 1).unwrap().unwrap();
+            assert_eq!(one.note.as_deref(), Some("note"));
+            assert_eq!(one.tags, vec!["web".to_string()]);
+            assert_eq!(one.options.connect_timeout, Some(5));
+            assert_eq!(one.password, remote.password);
+            // the record not found, the tags are untouched
+            let missing = Remote {
+                index: 999,
+                tags: vec!["orphan".to_string()],
+                ..one.clone()
+            };
+            assert_eq!(update(&conn, &missing).unwrap(), 0);
+            let orphan: usize = conn
+                .query_row(
+                    "SELECT COUNT(*) FROM tags WHERE name = 'orphan'",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(orphan, 0);
+            // restore it
+            assert_eq!(
+                update(
+                    &conn,
+                    &Remote {
+                        index: 1,
+                        authorized: true,
+                        ..remote.clone()
+                    }
+                )
+                .unwrap(),
+                1
+            );
+        }
+
+        // grant key
+        {
+            let conn = get_connection().unwrap().lock();
+            let grant = Grant {
+                fingerprint: "SHA256:test".to_string(),
+                comment: Some("alice@host".to_string()),
+                granted_by: "tester".to_string(),
+                granted_at: 1,
+            };
+            assert_eq!(insert_grant(&conn, 1, &grant).unwrap(), 1);
+            // replace the same one
+            assert_eq!(insert_grant(&conn, 1, &grant).unwrap(), 1);
+            let grants = query_grants(&conn, 1).unwrap();
+            assert_eq!(grants.len(), 1);
+            assert_eq!(grants[0].granted_by, "tester");
+            assert_eq!(delete_grant(&conn, 1, "SHA256:test").unwrap(), 1);
+            assert!(query_grants(&conn, 1).unwrap().is_empty());
         }
 
         // test query all
         let exist_idx = {
-            let conn = get_connection().lock();
+            let conn = get_connection().unwrap().lock();
             let all = query_all(&conn);
             println!("all: {:#?}", all);
             assert!(all.is_ok());
@@ -182,7 +562,7 @@ mod tests {
         };
         // test query by index
         {
-            let conn = get_connection().lock();
+            let conn = get_connection().unwrap().lock();
             let one = query_index(&conn, exist_idx[0]);
             println!("one: {:#?}", one);
             assert!(one.is_ok());
@@ -196,11 +576,16 @@ mod tests {
             assert!(one.port == remote.port);
             assert!(one.name == remote.name);
             assert!(one.note == remote.note);
+            assert!(one.mode == remote.mode);
+            assert!(one.principals == remote.principals);
+            assert!(one.totp == remote.totp);
+            assert!(one.sudo_password == remote.sudo_password);
+            assert!(one.tags == remote.tags);
         }
 
         // delete one
         {
-            let conn = get_connection().lock();
+            let conn = get_connection().unwrap().lock();
             let n = delete_index(&conn, exist_idx[0]);
             println!("delete: {:#?}", n);
             assert!(n.is_ok());
@@ -209,7 +594,7 @@ mod tests {
 
         // now we add one again
         {
-            let conn = get_connection().lock();
+            let conn = get_connection().unwrap().lock();
             let n = insert(&conn, &remote);
             assert_eq!(n.unwrap(), 1);
             // wo query all
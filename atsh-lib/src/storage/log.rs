@@ -1,4 +1,4 @@
-use std::io::Error;
+use crate::error::Error;
 use std::path::Path;
 
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
@@ -1,3 +1,4 @@
 pub(crate) mod db;
 pub(crate) mod log;
 pub(crate) mod secure;
+pub(crate) mod vault;
@@ -4,7 +4,8 @@ use chacha20poly1305::aead::generic_array::GenericArray;
 use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
 use chacha20poly1305::ChaCha20Poly1305;
 
-use crate::config::CONFIG;
+use crate::context::current;
+use crate::error::Error;
 
 fn generate_key(key: Option<impl AsRef<str>>) -> Vec<u8> {
     if key.is_none() {
@@ -22,7 +23,7 @@ fn generate_key(key: Option<impl AsRef<str>>) -> Vec<u8> {
         key.resize(32, 0);
         return key;
     }
-    return key;
+    key
 }
 
 fn chacha_encrypt(cleartext: &str, key: &[u8]) -> Vec<u8> {
@@ -35,22 +36,29 @@ fn chacha_encrypt(cleartext: &str, key: &[u8]) -> Vec<u8> {
     obsf
 }
 
-fn chacha_decrypt(obsf: &[u8], key: &[u8]) -> String {
+/// `None` if the data is not encrypted by the key.
+fn chacha_decrypt(obsf: &[u8], key: &[u8]) -> Option<String> {
     type NonceSize = <ChaCha20Poly1305 as AeadCore>::NonceSize;
+    if obsf.len() < NonceSize::to_usize() {
+        return None;
+    }
     let cipher = ChaCha20Poly1305::new(GenericArray::from_slice(key));
     let (nonce, ciphertext) = obsf.split_at(NonceSize::to_usize());
     let nonce = GenericArray::from_slice(nonce);
-    let plaintext = cipher
-        .decrypt(nonce, ciphertext)
-        .expect("decrypt failed by chacha20");
-    String::from_utf8(plaintext).unwrap()
+    let plaintext = cipher.decrypt(nonce, ciphertext).ok()?;
+    String::from_utf8(plaintext).ok()
 }
 
 pub fn encrypt(data: impl AsRef<str>) -> String {
+    encrypt_by(data, current().get_enc_key().ok().as_deref())
+}
+
+/// encrypt by the given key, like the key of another profile.
+pub(crate) fn encrypt_by(data: impl AsRef<str>, key: Option<&str>) -> String {
     let data = data.as_ref();
-    if let Ok(key) = CONFIG.get_enc_key() {
+    if let Some(key) = key {
         // log::debug!("we found `ASKEY` and will encrypt.");
-        let key = generate_key(Some(&key));
+        let key = generate_key(Some(key));
         let obsf = chacha_encrypt(data, &key);
         general_purpose::STANDARD_NO_PAD.encode(obsf)
     } else {
@@ -58,20 +66,32 @@ pub fn encrypt(data: impl AsRef<str>) -> String {
     }
 }
 
-pub fn decrypt(data: impl AsRef<str>) -> String {
+/// decrypt by the key in scope, the `Crypto` error if the key is wrong.
+pub fn decrypt(data: impl AsRef<str>) -> Result<String, Error> {
     let data = data.as_ref();
-    if let Ok(key) = CONFIG.get_enc_key() {
+    if let Ok(key) = current().get_enc_key() {
         // log::debug!("we found `ASKEY` and will decrypt.");
-        let obsf = general_purpose::STANDARD_NO_PAD
-            .decode(data.as_bytes())
-            .expect("decode failed by base64");
-        let key = generate_key(Some(&key));
-        chacha_decrypt(&obsf, &key)
+        decrypt_by(data, &key).ok_or_else(|| {
+            Error::Crypto("decrypt the password failed, the `ATSH_KEY` is wrong".to_string())
+        })
     } else {
-        data.to_string()
+        Ok(data.to_string())
     }
 }
 
+/// decrypt by the given key, `None` if the data is not encrypted by the key.
+fn decrypt_by(data: &str, key: &str) -> Option<String> {
+    let obsf = general_purpose::STANDARD_NO_PAD
+        .decode(data.as_bytes())
+        .ok()?;
+    chacha_decrypt(&obsf, &generate_key(Some(key)))
+}
+
+/// whether the data is encrypted by the key.
+pub(crate) fn is_encrypted_by(data: impl AsRef<str>, key: &str) -> bool {
+    decrypt_by(data.as_ref(), key).is_some()
+}
+
 // tests
 #[cfg(test)]
 mod tests {
@@ -83,7 +103,7 @@ mod tests {
         println!("chacha key: {:?}", key);
         let ciphertext = chacha_encrypt("plaintext message", &key);
         println!("encrypt: {:?}", ciphertext);
-        let plaintext = chacha_decrypt(&ciphertext, &key);
+        let plaintext = chacha_decrypt(&ciphertext, &key).unwrap();
         println!("decrypt: {:?}", plaintext);
         assert_eq!(plaintext, "plaintext message");
     }
@@ -100,8 +120,17 @@ mod tests {
         let data = "hello world";
         let enc = encrypt(data);
         println!("encrypt: {:?}", enc);
-        let dec = decrypt(&enc);
+        let dec = decrypt(&enc).unwrap();
         println!("decrypt: {:?}", dec);
         assert_eq!(data, dec);
     }
+
+    #[test]
+    fn test_encrypt_by() {
+        let enc = encrypt_by("hello world", Some("profile key"));
+        assert!(is_encrypted_by(&enc, "profile key"));
+        assert!(!is_encrypted_by(&enc, "other key"));
+        assert!(!is_encrypted_by("hello world", "profile key"));
+        assert_eq!(encrypt_by("hello world", None), "hello world");
+    }
 }
@@ -0,0 +1,115 @@
+use aes::cipher::{KeyIvInit, StreamCipher};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type Aes256Ctr = ctr::Ctr128BE<aes::Aes256>;
+
+const HEADER: &str = "$ANSIBLE_VAULT;1.1;AES256";
+const ITERATIONS: u32 = 10000;
+
+fn hexlify(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// the AES key, HMAC key and IV derived from the vault password.
+fn derive(password: &str, salt: &[u8]) -> [u8; 80] {
+    let mut derived = [0u8; 80];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, ITERATIONS, &mut derived);
+    derived
+}
+
+fn encrypt_with_salt(data: &str, password: &str, salt: &[u8]) -> String {
+    let derived = derive(password, salt);
+    let (key, rest) = derived.split_at(32);
+    let (hmac_key, iv) = rest.split_at(32);
+
+    // PKCS#7 padding like ansible, although CTR does not need it
+    let mut ciphertext = data.as_bytes().to_vec();
+    let pad = 16 - ciphertext.len() % 16;
+    ciphertext.extend(std::iter::repeat_n(pad as u8, pad));
+    Aes256Ctr::new(key.into(), iv.into()).apply_keystream(&mut ciphertext);
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_key).expect("HMAC accepts any key size");
+    mac.update(&ciphertext);
+    let payload = format!(
+        "{}\n{}\n{}",
+        hexlify(salt),
+        hexlify(&mac.finalize().into_bytes()),
+        hexlify(&ciphertext)
+    );
+    let body = hexlify(payload.as_bytes());
+    let lines = body
+        .as_bytes()
+        .chunks(80)
+        .map(|c| String::from_utf8_lossy(c).to_string())
+        .collect::<Vec<_>>();
+    format!("{HEADER}\n{}\n", lines.join("\n"))
+}
+
+/// encrypt the data like `ansible-vault encrypt_string`, the vault 1.1 format.
+pub fn encrypt(data: &str, password: &str) -> String {
+    let mut salt = [0u8; 32];
+    OsRng.fill_bytes(&mut salt);
+    encrypt_with_salt(data, password, &salt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vault() {
+        let salt = [7u8; 32];
+        let vault = encrypt_with_salt("secret", "vault-pass", &salt);
+        let mut lines = vault.lines();
+        assert_eq!(lines.next(), Some(HEADER));
+        let body = lines.collect::<String>();
+        assert!(vault.lines().skip(1).all(|l| l.len() <= 80));
+
+        // unhexlify the payload and check it by the same keys
+        let unhex = |s: &str| {
+            (0..s.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let payload = String::from_utf8(unhex(&body)).unwrap();
+        let parts = payload.split('\n').collect::<Vec<_>>();
+        assert_eq!(unhex(parts[0]), salt);
+        let mut ciphertext = unhex(parts[2]);
+        assert_eq!(ciphertext.len(), 16);
+
+        let derived = derive("vault-pass", &salt);
+        let mut mac = Hmac::<Sha256>::new_from_slice(&derived[32..64]).unwrap();
+        mac.update(&ciphertext);
+        mac.verify_slice(&unhex(parts[1])).unwrap();
+        Aes256Ctr::new(derived[..32].into(), derived[64..].into()).apply_keystream(&mut ciphertext);
+        assert_eq!(&ciphertext[..6], b"secret");
+        assert_eq!(ciphertext[6..], [10u8; 10]);
+
+        assert_ne!(
+            encrypt("secret", "vault-pass"),
+            encrypt("secret", "vault-pass")
+        );
+    }
+
+    #[test]
+    fn test_vault_known_answer() {
+        // `ansible-vault encrypt_string` of `fooooo` by the vault password `password`
+        let expected = "$ANSIBLE_VAULT;1.1;AES256
+62313365396662343061393464336163383764373764613633653634306231386433626436623361
+6134333665353966363534333632666535333761666131620a663537646436643839616531643561
+63396265333966386166373632626539326166353965363262633030333630313338646335303630
+3438626666666137650a353638643435666633633964366338633066623234616432373231333331
+6564
+";
+        let salt = [
+            0xb1, 0x3e, 0x9f, 0xb4, 0x0a, 0x94, 0xd3, 0xac, 0x87, 0xd7, 0x7d, 0xa6, 0x3e, 0x64,
+            0x0b, 0x18, 0xd3, 0xbd, 0x6b, 0x3a, 0xa4, 0x36, 0xe5, 0x9f, 0x65, 0x43, 0x62, 0xfe,
+            0x53, 0x7a, 0xfa, 0x1b,
+        ];
+        assert_eq!(encrypt_with_salt("fooooo", "password", &salt), expected);
+    }
+}
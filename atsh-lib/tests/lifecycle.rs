@@ -0,0 +1,464 @@
+//! the lifecycle of a remote server on the mock ssh server:
+//! add -> authenticate -> login by key -> exec and transfer -> delete with revoke.
+
+mod mock;
+
+use atsh_lib::atsh::{Atsh, AuthMode, Event, Remote, Target};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+
+use mock::proxy::{Kind, MockProxy};
+use mock::MockServer;
+
+/// whether the tool is found in `PATH`, or the test is skipped with the message.
+fn require(tool: &str) -> bool {
+    let found = std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|p| p.join(tool).is_file()));
+    if !found {
+        eprintln!("skip: `{tool}` is not found in PATH");
+    }
+    found
+}
+
+/// the work directory with the `ssh-ed25519` key pair of atsh, `None` without `ssh-keygen`.
+fn open(name: &str) -> Option<(Atsh, PathBuf)> {
+    if !require("ssh-keygen") {
+        return None;
+    }
+    let dir = std::env::temp_dir().join(format!("atsh-{name}-{}", std::process::id()));
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    let atsh = Atsh::open(&dir).unwrap();
+    atsh.set_enc_key(Some("lifecycle")).unwrap();
+    let status = Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-C", "atsh", "-f"])
+        .arg(atsh.config().get_private())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    Some((atsh, dir))
+}
+
+fn remote(server: &MockServer, password: &str, mode: AuthMode) -> Remote {
+    Remote {
+        user: server.user.clone(),
+        password: password.to_string(),
+        ip: "127.0.0.1".to_string(),
+        port: server.port,
+        mode,
+        ..Default::default()
+    }
+}
+
+/// add the record and return its index, the last one.
+fn add(atsh: &Atsh, remote: &Remote) -> usize {
+    atsh.add_remote(remote).unwrap();
+    atsh.get_all().unwrap().last().unwrap().index
+}
+
+/// the exit status of the command on one server.
+fn exec(atsh: &Atsh, index: usize, command: &str) -> i32 {
+    let outcomes = atsh.exec(&Target::Index(index), command, false).unwrap();
+    outcomes[0].value.as_ref().unwrap().code
+}
+
+/// login by the atsh key like `ssh -i`, not the password.
+fn login_by_key(server: &MockServer, atsh: &Atsh) -> bool {
+    let tcp = std::net::TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+    let mut session = ssh2::Session::new().unwrap();
+    session.set_tcp_stream(tcp);
+    session.handshake().unwrap();
+    let _ = session.userauth_pubkey_file(&server.user, None, atsh.config().get_private(), None);
+    session.authenticated()
+}
+
+#[test]
+fn test_lifecycle() {
+    let server = MockServer::start("alice", "secret");
+    let Some((atsh, dir)) = open("lifecycle") else {
+        return;
+    };
+    let (tx, rx) = std::sync::mpsc::channel();
+    atsh.set_observer(Some(Arc::new(tx)));
+
+    // the wrong password is an auth error, not a transport one
+    let e = atsh
+        .verify_remote(&remote(&server, "wrong", AuthMode::Key))
+        .unwrap_err();
+    assert_eq!(e.code(), 1004, "{e}");
+
+    // add and authenticate by the password
+    let key = add(&atsh, &remote(&server, "secret", AuthMode::Key));
+    assert!(!login_by_key(&server, &atsh));
+    atsh.authenticate(key).unwrap();
+    let public = std::fs::read_to_string(atsh.config().get_public()).unwrap();
+    assert!(server.wait_for(|s| s.authorized_keys().contains(public.trim())));
+    assert!(atsh.try_get(key).unwrap().authorized);
+    assert!(login_by_key(&server, &atsh));
+    // authenticate again never duplicates the key
+    atsh.authenticate(key).unwrap();
+    assert_eq!(server.authorized_keys().matches("ssh-ed25519").count(), 1);
+
+    // exec by the installed key over ssh2
+    let target = Target::Index(key);
+    assert_eq!(exec(&atsh, key, "true"), 0);
+    assert_eq!(exec(&atsh, key, "exit 3"), 3);
+    let outcomes = atsh.exec(&target, "echo hello", false).unwrap();
+    assert_eq!(outcomes[0].value.as_ref().unwrap().output, "hello\n");
+
+    // one unreachable server never stops the others
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut dead = remote(&server, "secret", AuthMode::Key);
+    dead.port = closed.local_addr().unwrap().port();
+    dead.options.set("retries", "0").unwrap();
+    drop(closed);
+    let dead = add(&atsh, &dead);
+    let outcomes = atsh.exec(&Target::All, "echo hello", false).unwrap();
+    assert_eq!(outcomes.len(), 2);
+    assert_eq!(outcomes[0].value.as_ref().unwrap().code, 0);
+    assert!(outcomes[1].value.is_none() && outcomes[1].error.is_some());
+    atsh.remove(&[dead]).unwrap();
+
+    // grant a teammate's key, the existing one is never recorded as granted
+    let teammate = dir.join("teammate");
+    let status = Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-C", "teammate", "-f"])
+        .arg(&teammate)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let teammate = teammate.with_extension("pub");
+    let teammate = teammate.to_str().unwrap();
+    let granted = atsh.copy_id(&target, teammate).unwrap();
+    assert_eq!(
+        (granted[0].value, granted[0].error.as_ref()),
+        (Some(true), None)
+    );
+    assert_eq!(
+        atsh.copy_id(&target, teammate).unwrap()[0].value,
+        Some(false)
+    );
+    assert_eq!(
+        atsh.revoke_id(&target, teammate).unwrap()[0].value,
+        Some(true)
+    );
+    assert!(!server.authorized_keys().contains("teammate"));
+    // the grant of the key removed by audit is deleted too
+    atsh.copy_id(&target, teammate).unwrap();
+    let audit = atsh.audit_keys(&target, &[] as &[&str]).unwrap();
+    let granted = audit[0].keys.iter().find(|k| k.granted).unwrap();
+    let fingerprint = granted.key.fingerprint.clone();
+    let audit = atsh.audit_keys(&target, &[fingerprint]).unwrap();
+    assert!(audit[0].keys.iter().any(|k| k.granted && k.removed));
+    let revoked = atsh.revoke_id(&target, teammate).unwrap();
+    assert!(revoked[0].error.as_ref().unwrap().contains("not granted"));
+    let public = std::fs::read_to_string(atsh.config().get_public()).unwrap();
+    assert_eq!(
+        atsh.copy_id(&target, &public).unwrap()[0].value,
+        Some(false)
+    );
+    let revoked = atsh.revoke_id(&target, &public).unwrap();
+    assert!(revoked[0].error.as_ref().unwrap().contains("not granted"));
+    assert!(login_by_key(&server, &atsh));
+
+    // remove the atsh key by audit, the next login installs it again
+    let audit = atsh.audit_keys(&target, &[] as &[&str]).unwrap();
+    let atsh_key = audit[0].keys.iter().find(|k| k.atsh).unwrap();
+    let fingerprint = atsh_key.key.fingerprint.clone();
+    let audit = atsh.audit_keys(&target, &[fingerprint]).unwrap();
+    assert!(audit[0].keys.iter().any(|k| k.atsh && k.removed));
+    assert!(!login_by_key(&server, &atsh));
+    assert!(!atsh.try_get(key).unwrap().authorized);
+    atsh.authenticate(key).unwrap();
+    assert!(atsh.try_get(key).unwrap().authorized);
+
+    // delete with revoke, the key is removed from the server
+    assert_eq!(atsh.remove(&[key]).unwrap(), 1);
+    assert!(server.wait_for(|s| !s.authorized_keys().contains(public.trim())));
+    assert!(!login_by_key(&server, &atsh));
+    assert!(atsh.get_all().unwrap().is_empty());
+
+    // transfer by SFTP in the password mode, `scp` of the key mode in `test_scp`
+    let index = add(&atsh, &remote(&server, "secret", AuthMode::Password));
+    let local = dir.join("hello.txt");
+    let data = "hello atsh\n".repeat(10000);
+    std::fs::write(&local, &data).unwrap();
+    let uploaded = format!("{}/hello.txt", server.home());
+    atsh.upload(index, &[local.to_str().unwrap(), &server.home()])
+        .unwrap();
+    assert_eq!(server.fs.read(&uploaded).unwrap(), data.as_bytes());
+    let entries = atsh.list_dir(index, &server.home()).unwrap();
+    assert!(entries.contains(&("hello.txt".to_string(), false)));
+    assert!(entries.contains(&(".ssh".to_string(), true)));
+
+    // the symlink to the directory itself is never followed
+    #[cfg(unix)]
+    {
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(&tree).unwrap();
+        std::fs::write(tree.join("a.txt"), "a").unwrap();
+        std::os::unix::fs::symlink(&tree, tree.join("loop")).unwrap();
+        atsh.upload(index, &[tree.to_str().unwrap(), &server.home()])
+            .unwrap();
+        let uploaded = format!("{}/tree", server.home());
+        assert_eq!(server.fs.read(&format!("{uploaded}/a.txt")).unwrap(), b"a");
+        assert!(!server.fs.exists(&format!("{uploaded}/loop")));
+    }
+
+    let downloaded = dir.join("download.txt");
+    atsh.download(index, &[uploaded.as_str(), downloaded.to_str().unwrap()])
+        .unwrap();
+    assert_eq!(std::fs::read_to_string(&downloaded).unwrap(), data);
+
+    let events = rx.try_iter().collect::<Vec<_>>();
+    for expect in [
+        "key_installed",
+        "key_revoked",
+        "transferred",
+        "output",
+        "failed",
+    ] {
+        assert!(
+            events
+                .iter()
+                .any(|e| serde_json::to_value(e).unwrap()["event"] == expect),
+            "{expect} not in {events:?}"
+        );
+    }
+    assert!(matches!(events.last(), Some(Event::Finished { .. })));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_scp() {
+    if !require("scp") {
+        return;
+    }
+    let server = MockServer::start("alice", "secret");
+    let Some((atsh, dir)) = open("scp") else {
+        return;
+    };
+    // the spawned `scp` never reads the known hosts of the user, and talks the legacy
+    // `scp -t/-f` protocol instead of SFTP which is tested in the password mode
+    let bin = dir.join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    let real = Command::new("sh")
+        .args(["-c", "command -v scp"])
+        .output()
+        .unwrap();
+    let real = String::from_utf8(real.stdout).unwrap();
+    let wrapper = bin.join("scp");
+    std::fs::write(
+        &wrapper,
+        format!(
+            "#!/bin/sh\nexec {} -O -o BatchMode=yes -o StrictHostKeyChecking=no \
+             -o UserKnownHostsFile=/dev/null -o LogLevel=ERROR \"$@\"\n",
+            real.trim()
+        ),
+    )
+    .unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&wrapper, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let paths = std::iter::once(bin).chain(std::env::split_paths(&path));
+    std::env::set_var("PATH", std::env::join_paths(paths).unwrap());
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    atsh.set_observer(Some(Arc::new(tx)));
+    // the key is installed by the password before the first `scp`
+    let index = add(&atsh, &remote(&server, "secret", AuthMode::Key));
+    let local = dir.join("hello.txt");
+    let data = "hello scp\n".repeat(1000);
+    std::fs::write(&local, &data).unwrap();
+    atsh.upload(index, &[local.to_str().unwrap(), &server.home()])
+        .unwrap();
+    let uploaded = format!("{}/hello.txt", server.home());
+    assert_eq!(server.fs.read(&uploaded).unwrap(), data.as_bytes());
+    assert!(atsh.try_get(index).unwrap().authorized);
+    assert!(server.commands.lock().iter().any(|c| c.starts_with("scp ")));
+
+    let downloaded = dir.join("download.txt");
+    atsh.download(index, &[uploaded.as_str(), downloaded.to_str().unwrap()])
+        .unwrap();
+    assert_eq!(std::fs::read_to_string(&downloaded).unwrap(), data);
+
+    // the start and end of the upload, the end of the download
+    let total = data.len() as u64;
+    let transferred = rx
+        .try_iter()
+        .filter_map(|e| match e {
+            Event::Transferred { bytes, total, .. } => Some((bytes, total)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(transferred, [(0, total), (total, total), (total, total)]);
+
+    // the failure of `scp` is returned with its message
+    let missing = format!("{}/missing.txt", server.home());
+    let e = atsh
+        .download(index, &[missing.as_str(), downloaded.to_str().unwrap()])
+        .unwrap_err();
+    assert_eq!(e.code(), 1003, "{e}");
+    assert!(e.to_string().contains("No such file"), "{e}");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_unreadable() {
+    let server = MockServer::start("alice", "secret");
+    let Some((atsh, dir)) = open("unreadable") else {
+        return;
+    };
+    // only the missing file is created, the unreadable one is never replaced
+    let file = format!("{}/.ssh/authorized_keys", server.home());
+    server.fs.mkdir_all(&file);
+    let index = add(&atsh, &remote(&server, "secret", AuthMode::Key));
+    let e = atsh.authenticate(index).unwrap_err();
+    assert_eq!(e.code(), 1003, "{e}");
+    assert!(e.to_string().contains("unable to read"), "{e}");
+    assert!(server.fs.is_dir(&file));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_dead_host() {
+    // the host accepts the tcp but never says the ssh version
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let Some((atsh, dir)) = open("dead") else {
+        return;
+    };
+    let mut dead = Remote {
+        user: "alice".to_string(),
+        ip: "127.0.0.1".to_string(),
+        port: listener.local_addr().unwrap().port(),
+        ..Default::default()
+    };
+    dead.options.set("handshake_timeout", "1").unwrap();
+    dead.options.set("retries", "1").unwrap();
+    dead.options.set("backoff", "100").unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    atsh.set_observer(Some(Arc::new(tx)));
+    let start = std::time::Instant::now();
+    let e = atsh.verify_remote(&dead).unwrap_err();
+    assert_eq!(e.code(), 1003, "{e}");
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    // connect once and retry once
+    let connecting = rx
+        .try_iter()
+        .filter(|e| matches!(e, Event::Connecting { .. }))
+        .count();
+    assert_eq!(connecting, 2);
+    drop(listener);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_ipv6_and_hostname() {
+    let Some((atsh, dir)) = open("ipv6") else {
+        return;
+    };
+    let local = dir.join("hello.txt");
+    std::fs::write(&local, "hello ipv6\n").unwrap();
+    let mut binds = vec![("127.0.0.1:0", "localhost")];
+    if std::net::TcpListener::bind("[::1]:0").is_ok() {
+        binds.insert(0, ("[::1]:0", "::1"));
+    } else {
+        eprintln!("skip: the IPv6 loopback `::1` is not available");
+    }
+    for (bind, host) in binds {
+        let server = MockServer::bind(bind, "alice", "secret");
+        let remote = Remote {
+            ip: host.to_string(),
+            ..remote(&server, "secret", AuthMode::Password)
+        };
+        atsh.verify_remote(&remote).unwrap();
+        let index = add(&atsh, &remote);
+        assert_eq!(atsh.try_get(index).unwrap().ip, host);
+        assert_eq!(exec(&atsh, index, "true"), 0);
+        atsh.upload(index, &[local.to_str().unwrap(), &server.home()])
+            .unwrap();
+        let uploaded = format!("{}/hello.txt", server.home());
+        assert_eq!(server.fs.read(&uploaded).unwrap(), b"hello ipv6\n");
+    }
+    // the brackets are only used in the uri and `scp`
+    for bad in ["[::1]", "10.0.0.256", "bad host"] {
+        let remote = Remote {
+            user: "alice".to_string(),
+            ip: bad.to_string(),
+            port: 22,
+            ..Default::default()
+        };
+        assert_eq!(atsh.add_remote(&remote).unwrap_err().code(), 1006, "{bad}");
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_proxy() {
+    let Some((atsh, dir)) = open("proxy") else {
+        return;
+    };
+    let server = MockServer::start("alice", "secret");
+    for (kind, scheme) in [(Kind::Socks5, "socks5"), (Kind::Http, "http")] {
+        let proxy = MockProxy::start(kind, Some(("bob", "p@ss")));
+        let mut remote = remote(&server, "secret", AuthMode::Password);
+        remote.options.set("retries", "0").unwrap();
+        // the credentials are never stored in the record
+        let url = format!("{scheme}://bob:p%40ss@127.0.0.1:{}", proxy.port);
+        assert_eq!(remote.options.set("proxy", &url).unwrap_err().code(), 1006);
+        let url = format!("{scheme}://127.0.0.1:{}", proxy.port);
+        remote.options.set("proxy", &url).unwrap();
+        std::env::set_var("ATSH_PROXY_USER", "bob");
+        std::env::set_var("ATSH_PROXY_PASSWORD", "p@ss");
+        atsh.verify_remote(&remote).unwrap();
+        let index = add(&atsh, &remote);
+        assert_eq!(exec(&atsh, index, "exit 7"), 7);
+        let target = format!("127.0.0.1:{}", server.port);
+        assert!(proxy.targets.lock().iter().all(|t| *t == target));
+        assert_eq!(proxy.targets.lock().len(), 2);
+        atsh.remove(&[index]).unwrap();
+
+        // the wrong credentials never leak into the error
+        std::env::set_var("ATSH_PROXY_PASSWORD", "wrong");
+        let e = atsh.verify_remote(&remote).unwrap_err();
+        assert_eq!(e.code(), 1003, "{e}");
+        assert!(!e.to_string().contains("wrong"), "{e}");
+    }
+    std::env::remove_var("ATSH_PROXY_USER");
+    std::env::remove_var("ATSH_PROXY_PASSWORD");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_algorithms() {
+    let Some((atsh, dir)) = open("algorithms") else {
+        return;
+    };
+    // the server offers `curve25519-sha256`, `ssh-ed25519`, `aes128-ctr` and `hmac-sha2-256` only
+    let server = MockServer::start("alice", "secret");
+    let verify = |options: &[(&str, &str)]| {
+        let mut remote = remote(&server, "secret", AuthMode::Password);
+        remote.options.set("retries", "0").unwrap();
+        for (key, value) in options {
+            remote.options.set(key, value).unwrap();
+        }
+        atsh.verify_remote(&remote).map_err(|e| e.code())
+    };
+    assert_eq!(verify(&[]), Ok(()));
+    // the legacy ones are added to the default ones
+    assert_eq!(verify(&[("kex", "+diffie-hellman-group14-sha1")]), Ok(()));
+    assert_eq!(verify(&[("ciphers", "^aes256-ctr")]), Ok(()));
+    assert_eq!(verify(&[("macs", "hmac-sha2-256,hmac-sha1")]), Ok(()));
+    // no common algorithm
+    assert_eq!(verify(&[("ciphers", "aes256-ctr")]), Err(1003));
+    assert_eq!(verify(&[("hostkey", "-ssh-ed25519")]), Err(1003));
+    // none is supported by libssh2
+    assert_eq!(verify(&[("kex", "unknown-kex")]), Err(1006));
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,144 @@
+//! the in-memory filesystem of the mock server.
+
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+#[derive(Clone)]
+enum Node {
+    Dir,
+    File(Vec<u8>),
+}
+
+/// The files by the absolute path, shared by all connections.
+#[derive(Clone, Default)]
+pub struct Fs(Arc<Mutex<BTreeMap<String, Node>>>);
+
+/// the absolute path without `.` and `..`, the relative path is from `/`.
+pub fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = vec![];
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+fn parent(path: &str) -> String {
+    match path.rsplit_once('/') {
+        Some(("", _)) | None => "/".to_string(),
+        Some((parent, _)) => parent.to_string(),
+    }
+}
+
+impl Fs {
+    pub fn new() -> Self {
+        let fs = Fs::default();
+        fs.0.lock().insert("/".to_string(), Node::Dir);
+        fs
+    }
+
+    /// create the directory and its parents.
+    pub fn mkdir_all(&self, path: &str) {
+        let path = normalize(path);
+        let mut nodes = self.0.lock();
+        let mut current = String::new();
+        for part in path.split('/').filter(|p| !p.is_empty()) {
+            current = format!("{current}/{part}");
+            nodes.entry(current.clone()).or_insert(Node::Dir);
+        }
+    }
+
+    /// create the directory, the parent must exist.
+    pub fn mkdir(&self, path: &str) -> bool {
+        let path = normalize(path);
+        if !self.is_dir(&parent(&path)) || self.exists(&path) {
+            return false;
+        }
+        self.0.lock().insert(path, Node::Dir);
+        true
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        self.0.lock().contains_key(&normalize(path))
+    }
+
+    pub fn is_dir(&self, path: &str) -> bool {
+        matches!(self.0.lock().get(&normalize(path)), Some(Node::Dir))
+    }
+
+    pub fn read(&self, path: &str) -> Option<Vec<u8>> {
+        match self.0.lock().get(&normalize(path)) {
+            Some(Node::File(data)) => Some(data.clone()),
+            _ => None,
+        }
+    }
+
+    /// the size of the file, zero for the directory.
+    pub fn size(&self, path: &str) -> Option<u64> {
+        match self.0.lock().get(&normalize(path))? {
+            Node::File(data) => Some(data.len() as u64),
+            Node::Dir => Some(0),
+        }
+    }
+
+    /// replace the file, the parent must be a directory.
+    pub fn write(&self, path: &str, data: &[u8]) -> bool {
+        self.write_at(path, 0, data, true)
+    }
+
+    /// write the data at the offset, the file is created if not exists.
+    pub fn write_at(&self, path: &str, offset: u64, data: &[u8], truncate: bool) -> bool {
+        let path = normalize(path);
+        if !self.is_dir(&parent(&path)) {
+            return false;
+        }
+        let mut nodes = self.0.lock();
+        let node = nodes.entry(path).or_insert(Node::File(vec![]));
+        let Node::File(file) = node else {
+            return false;
+        };
+        if truncate {
+            file.clear();
+        }
+        let offset = offset as usize;
+        if file.len() < offset + data.len() {
+            file.resize(offset + data.len(), 0);
+        }
+        file[offset..offset + data.len()].copy_from_slice(data);
+        true
+    }
+
+    pub fn remove(&self, path: &str) -> bool {
+        self.0.lock().remove(&normalize(path)).is_some()
+    }
+
+    /// the entries of the directory, the name and whether it is a directory.
+    pub fn list(&self, dir: &str) -> Option<Vec<(String, bool)>> {
+        let dir = normalize(dir);
+        let nodes = self.0.lock();
+        if !matches!(nodes.get(&dir), Some(Node::Dir)) {
+            return None;
+        }
+        let prefix = if dir == "/" {
+            dir.clone()
+        } else {
+            format!("{dir}/")
+        };
+        Some(
+            nodes
+                .iter()
+                .filter_map(|(path, node)| {
+                    let name = path.strip_prefix(&prefix)?;
+                    (!name.is_empty() && !name.contains('/'))
+                        .then(|| (name.to_string(), matches!(node, Node::Dir)))
+                })
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,605 @@
+//! An embedded ssh server for the integration tests, it listens on `127.0.0.1` with a random port.
+//!
+//! It supports the password and `ssh-ed25519` public key authentication, the `exec` of a few
+//! commands, `scp -t/-f` and SFTP on an in-memory filesystem. The public keys are read from
+//! `~/.ssh/authorized_keys` in that filesystem, like `sshd`.
+
+#![allow(dead_code)]
+
+pub mod fs;
+pub mod proxy;
+mod sftp;
+mod transport;
+mod wire;
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use ed25519_dalek::{Signature, SigningKey, Verifier, VerifyingKey};
+use parking_lot::Mutex;
+use rand_core::{OsRng, RngCore};
+use std::collections::HashMap;
+use std::io::Result;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use fs::Fs;
+use sftp::Sftp;
+use transport::{invalid, Transport, MSG_DEBUG, MSG_DISCONNECT, MSG_IGNORE, MSG_UNIMPLEMENTED};
+use wire::{Reader, Writer};
+
+const MSG_SERVICE_REQUEST: u8 = 5;
+const MSG_SERVICE_ACCEPT: u8 = 6;
+const MSG_USERAUTH_REQUEST: u8 = 50;
+const MSG_USERAUTH_FAILURE: u8 = 51;
+const MSG_USERAUTH_SUCCESS: u8 = 52;
+const MSG_USERAUTH_PK_OK: u8 = 60;
+const MSG_GLOBAL_REQUEST: u8 = 80;
+const MSG_REQUEST_FAILURE: u8 = 82;
+const MSG_CHANNEL_OPEN: u8 = 90;
+const MSG_CHANNEL_OPEN_CONFIRMATION: u8 = 91;
+const MSG_CHANNEL_OPEN_FAILURE: u8 = 92;
+const MSG_CHANNEL_WINDOW_ADJUST: u8 = 93;
+const MSG_CHANNEL_DATA: u8 = 94;
+const MSG_CHANNEL_EXTENDED_DATA: u8 = 95;
+const MSG_CHANNEL_EOF: u8 = 96;
+const MSG_CHANNEL_CLOSE: u8 = 97;
+const MSG_CHANNEL_REQUEST: u8 = 98;
+const MSG_CHANNEL_SUCCESS: u8 = 99;
+const MSG_CHANNEL_FAILURE: u8 = 100;
+
+const WINDOW: u32 = 2 * 1024 * 1024;
+const CHUNK: usize = 16 * 1024;
+
+/// The mock server, it runs until the test process exits.
+pub struct MockServer {
+    pub port: u16,
+    pub user: String,
+    pub fs: Fs,
+    /// the commands executed, include `scp`.
+    pub commands: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockServer {
+    pub fn start(user: &str, password: &str) -> MockServer {
+        Self::bind("127.0.0.1:0", user, password)
+    }
+
+    /// listen on the address like `[::1]:0`.
+    pub fn bind(addr: &str, user: &str, password: &str) -> MockServer {
+        let listener = TcpListener::bind(addr).expect("bind mock server");
+        let port = listener.local_addr().unwrap().port();
+        let mut seed = [0u8; 32];
+        OsRng.fill_bytes(&mut seed);
+        let fs = Fs::new();
+        fs.mkdir_all(&format!("/home/{user}/.ssh"));
+        let server = MockServer {
+            port,
+            user: user.to_string(),
+            fs: fs.clone(),
+            commands: Arc::default(),
+        };
+        let auth = Arc::new((user.to_string(), password.to_string()));
+        let commands = server.commands.clone();
+        std::thread::spawn(move || {
+            let host_key = SigningKey::from_bytes(&seed);
+            for stream in listener.incoming().map_while(Result::ok) {
+                let (fs, auth, commands) = (fs.clone(), auth.clone(), commands.clone());
+                let host_key = host_key.clone();
+                std::thread::spawn(move || {
+                    if let Err(e) = Connection::serve(stream, &host_key, fs, &auth, commands) {
+                        // the client closes the connection without disconnect usually
+                        use std::io::ErrorKind::*;
+                        if !matches!(e.kind(), UnexpectedEof | ConnectionReset | BrokenPipe) {
+                            eprintln!("mock server: {e}");
+                        }
+                    }
+                });
+            }
+        });
+        server
+    }
+
+    pub fn home(&self) -> String {
+        format!("/home/{}", self.user)
+    }
+
+    pub fn authorized_keys(&self) -> String {
+        let file = format!("{}/.ssh/authorized_keys", self.home());
+        String::from_utf8(self.fs.read(&file).unwrap_or_default()).unwrap()
+    }
+
+    /// wait the server to handle the last packets of the closed session.
+    pub fn wait_for(&self, f: impl Fn(&MockServer) -> bool) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if f(self) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+}
+
+/// remove the shell quotes of the argument, like `'a b'` or `a\ b`.
+fn unquote(arg: &str) -> String {
+    let (mut out, mut quoted, mut chars) = (String::new(), false, arg.chars());
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => quoted = !quoted,
+            '\\' if !quoted => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+enum Handler {
+    Idle,
+    /// `scp -f`, the file is sent after every ack of the client.
+    ScpSource {
+        path: String,
+        preserve: bool,
+        step: u8,
+    },
+    /// `scp -t`, the file is received.
+    ScpSink {
+        path: String,
+        buf: Vec<u8>,
+        size: Option<usize>,
+        name: String,
+    },
+    Sftp(Sftp),
+}
+
+struct Channel {
+    peer: u32,
+    handler: Handler,
+    consumed: u32,
+    closed: bool,
+}
+
+struct Connection {
+    transport: Transport,
+    fs: Fs,
+    user: String,
+    password: String,
+    channels: HashMap<u32, Channel>,
+    next: u32,
+    commands: Arc<Mutex<Vec<String>>>,
+}
+
+impl Connection {
+    fn serve(
+        stream: TcpStream,
+        host_key: &SigningKey,
+        fs: Fs,
+        auth: &(String, String),
+        commands: Arc<Mutex<Vec<String>>>,
+    ) -> Result<()> {
+        let mut conn = Connection {
+            transport: Transport::accept(stream, host_key)?,
+            fs,
+            user: auth.0.clone(),
+            password: auth.1.clone(),
+            channels: HashMap::new(),
+            next: 0,
+            commands,
+        };
+        loop {
+            let payload = conn.transport.read_packet()?;
+            let mut r = Reader(&payload[1..]);
+            let handled = match payload[0] {
+                MSG_DISCONNECT => return Ok(()),
+                MSG_IGNORE | MSG_DEBUG | MSG_UNIMPLEMENTED => Some(()),
+                MSG_SERVICE_REQUEST => {
+                    let name = r.string().ok_or_else(|| invalid("bad service"))?.to_vec();
+                    conn.send(Writer::new(MSG_SERVICE_ACCEPT).string(name))?;
+                    Some(())
+                }
+                MSG_USERAUTH_REQUEST => conn.userauth(&mut r)?,
+                MSG_GLOBAL_REQUEST => {
+                    r.string();
+                    if r.bool() == Some(true) {
+                        conn.send(Writer::new(MSG_REQUEST_FAILURE))?;
+                    }
+                    Some(())
+                }
+                MSG_CHANNEL_OPEN => conn.open(&mut r)?,
+                MSG_CHANNEL_WINDOW_ADJUST | MSG_CHANNEL_EXTENDED_DATA => Some(()),
+                MSG_CHANNEL_REQUEST => conn.request(&mut r)?,
+                MSG_CHANNEL_DATA => conn.data(&mut r)?,
+                MSG_CHANNEL_EOF => {
+                    let id = r.u32().ok_or_else(|| invalid("bad eof"))?;
+                    conn.finish(id, 0)?;
+                    Some(())
+                }
+                MSG_CHANNEL_CLOSE => {
+                    let id = r.u32().ok_or_else(|| invalid("bad close"))?;
+                    if let Some(channel) = conn.channels.remove(&id) {
+                        if !channel.closed {
+                            conn.send(Writer::new(MSG_CHANNEL_CLOSE).u32(channel.peer))?;
+                        }
+                    }
+                    Some(())
+                }
+                kind => return Err(invalid(format!("unexpected message {kind}"))),
+            };
+            handled.ok_or_else(|| invalid(format!("bad message {}", payload[0])))?;
+        }
+    }
+
+    fn send(&mut self, w: Writer) -> Result<()> {
+        self.transport.write_packet(&w.0)
+    }
+
+    /// whether the public key is in `~/.ssh/authorized_keys`.
+    fn authorized(&self, blob: &[u8]) -> bool {
+        let file = format!("/home/{}/.ssh/authorized_keys", self.user);
+        let data = String::from_utf8(self.fs.read(&file).unwrap_or_default()).unwrap_or_default();
+        data.lines().any(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            fields
+                .iter()
+                .position(|f| *f == "ssh-ed25519")
+                .and_then(|i| fields.get(i + 1))
+                .and_then(|key| BASE64_STANDARD.decode(key).ok())
+                .is_some_and(|key| key == blob)
+        })
+    }
+
+    fn userauth(&mut self, r: &mut Reader) -> Result<Option<()>> {
+        let (Some(user), Some(service), Some(method)) = (r.text(), r.text(), r.text()) else {
+            return Ok(None);
+        };
+        let success = match method.as_str() {
+            "password" => {
+                r.bool();
+                user == self.user && r.text().as_ref() == Some(&self.password)
+            }
+            "publickey" => {
+                let (Some(signed), Some(alg), Some(blob)) = (r.bool(), r.text(), r.string()) else {
+                    return Ok(None);
+                };
+                if user != self.user || alg != "ssh-ed25519" || !self.authorized(blob) {
+                    false
+                } else if !signed {
+                    self.send(Writer::new(MSG_USERAUTH_PK_OK).string(&alg).string(blob))?;
+                    return Ok(Some(()));
+                } else {
+                    let data = Writer::default()
+                        .string(&self.transport.session_id)
+                        .byte(MSG_USERAUTH_REQUEST)
+                        .string(&user)
+                        .string(&service)
+                        .string("publickey")
+                        .bool(true)
+                        .string(&alg)
+                        .string(blob)
+                        .0;
+                    verify(blob, r.string().unwrap_or_default(), &data)
+                }
+            }
+            _ => false,
+        };
+        if success {
+            self.send(Writer::new(MSG_USERAUTH_SUCCESS))?;
+        } else {
+            self.send(
+                Writer::new(MSG_USERAUTH_FAILURE)
+                    .string("publickey,password")
+                    .bool(false),
+            )?;
+        }
+        Ok(Some(()))
+    }
+
+    fn open(&mut self, r: &mut Reader) -> Result<Option<()>> {
+        let (Some(kind), Some(peer)) = (r.text(), r.u32()) else {
+            return Ok(None);
+        };
+        if kind != "session" {
+            self.send(
+                Writer::new(MSG_CHANNEL_OPEN_FAILURE)
+                    .u32(peer)
+                    .u32(3)
+                    .string("only session")
+                    .string(""),
+            )?;
+            return Ok(Some(()));
+        }
+        self.next += 1;
+        self.channels.insert(
+            self.next,
+            Channel {
+                peer,
+                handler: Handler::Idle,
+                consumed: 0,
+                closed: false,
+            },
+        );
+        self.send(
+            Writer::new(MSG_CHANNEL_OPEN_CONFIRMATION)
+                .u32(peer)
+                .u32(self.next)
+                .u32(WINDOW)
+                .u32(32 * 1024),
+        )?;
+        Ok(Some(()))
+    }
+
+    fn request(&mut self, r: &mut Reader) -> Result<Option<()>> {
+        let (Some(id), Some(kind), Some(reply)) = (r.u32(), r.text(), r.bool()) else {
+            return Ok(None);
+        };
+        let Some(peer) = self.channels.get(&id).map(|c| c.peer) else {
+            return Ok(None);
+        };
+        let ok = matches!(
+            kind.as_str(),
+            "pty-req" | "env" | "exec" | "subsystem" | "shell"
+        );
+        if reply {
+            let kind = if ok {
+                MSG_CHANNEL_SUCCESS
+            } else {
+                MSG_CHANNEL_FAILURE
+            };
+            self.send(Writer::new(kind).u32(peer))?;
+        }
+        match kind.as_str() {
+            "exec" => self.exec(id, &r.text().unwrap_or_default())?,
+            "subsystem" if r.text().as_deref() == Some("sftp") => {
+                self.set_handler(id, Handler::Sftp(Sftp::new(self.fs.clone())));
+            }
+            "subsystem" => self.finish(id, 1)?,
+            "shell" => {
+                self.write(id, b"mock shell\n")?;
+                self.finish(id, 0)?;
+            }
+            _ => {}
+        }
+        Ok(Some(()))
+    }
+
+    fn set_handler(&mut self, id: u32, handler: Handler) {
+        if let Some(channel) = self.channels.get_mut(&id) {
+            channel.handler = handler;
+        }
+    }
+
+    /// the commands of `atsh`, any other command is not found.
+    fn exec(&mut self, id: u32, command: &str) -> Result<()> {
+        self.commands.lock().push(command.to_string());
+        let args = command.split_whitespace().collect::<Vec<_>>();
+        match args.as_slice() {
+            ["scp", .., flags, path] if flags.starts_with('-') && flags.contains(['f', 't']) => {
+                let path = unquote(path);
+                if flags.contains('t') {
+                    self.set_handler(
+                        id,
+                        Handler::ScpSink {
+                            path,
+                            buf: vec![],
+                            size: None,
+                            name: String::new(),
+                        },
+                    );
+                    // ready to receive
+                    return self.write(id, b"\0");
+                }
+                let preserve = flags.contains('p');
+                self.set_handler(
+                    id,
+                    Handler::ScpSource {
+                        path,
+                        preserve,
+                        step: 0,
+                    },
+                );
+                Ok(())
+            }
+            ["echo", "$HOME"] => {
+                let home = format!("/home/{}\n", self.user);
+                self.write(id, home.as_bytes())?;
+                self.finish(id, 0)
+            }
+            ["echo", rest @ ..] => {
+                self.write(id, format!("{}\n", rest.join(" ")).as_bytes())?;
+                self.finish(id, 0)
+            }
+            ["true"] => self.finish(id, 0),
+            ["false"] => self.finish(id, 1),
+            ["exit", code] => self.finish(id, code.parse().unwrap_or(1)),
+            _ => {
+                self.write(id, format!("mock: {command}: not found\n").as_bytes())?;
+                self.finish(id, 127)
+            }
+        }
+    }
+
+    fn data(&mut self, r: &mut Reader) -> Result<Option<()>> {
+        let (Some(id), Some(data)) = (r.u32(), r.string()) else {
+            return Ok(None);
+        };
+        let Some(channel) = self.channels.get_mut(&id) else {
+            return Ok(None);
+        };
+        channel.consumed += data.len() as u32;
+        if channel.consumed > WINDOW / 2 {
+            let (peer, n) = (channel.peer, channel.consumed);
+            channel.consumed = 0;
+            self.send(Writer::new(MSG_CHANNEL_WINDOW_ADJUST).u32(peer).u32(n))?;
+        }
+        let Some(channel) = self.channels.get_mut(&id) else {
+            return Ok(None);
+        };
+        match &mut channel.handler {
+            Handler::Sftp(sftp) => {
+                let responses = sftp.feed(data);
+                for response in responses {
+                    self.write(id, &response)?;
+                }
+            }
+            Handler::ScpSource { .. } => {
+                for &ack in data {
+                    self.scp_source(id, ack)?;
+                }
+            }
+            Handler::ScpSink { .. } => self.scp_sink(id, data)?,
+            Handler::Idle => {}
+        }
+        Ok(Some(()))
+    }
+
+    fn scp_source(&mut self, id: u32, ack: u8) -> Result<()> {
+        let Some(Handler::ScpSource {
+            path,
+            preserve,
+            step,
+        }) = self.channels.get_mut(&id).map(|c| &mut c.handler)
+        else {
+            return Ok(());
+        };
+        let (path, preserve, current) = (path.clone(), *preserve, *step);
+        *step += 1;
+        if ack != 0 {
+            return self.finish(id, 1);
+        }
+        if self.fs.is_dir(&path) {
+            let error = format!("\x01scp: {path}: not a regular file\n");
+            self.write(id, error.as_bytes())?;
+            return self.finish(id, 1);
+        }
+        let Some(data) = self.fs.read(&path) else {
+            let error = format!("\x01scp: {path}: No such file or directory\n");
+            self.write(id, error.as_bytes())?;
+            return self.finish(id, 1);
+        };
+        let header = format!("C0644 {} {}\n", data.len(), basename(&path));
+        match (current, preserve) {
+            (0, true) => self.write(id, b"T0 0 0 0\n"),
+            (0, false) | (1, true) => self.write(id, header.as_bytes()),
+            (1, false) | (2, true) => {
+                self.write(id, &data)?;
+                self.write(id, b"\0")
+            }
+            _ => self.finish(id, 0),
+        }
+    }
+
+    fn scp_sink(&mut self, id: u32, data: &[u8]) -> Result<()> {
+        let Some(Handler::ScpSink {
+            path,
+            buf,
+            size,
+            name,
+        }) = self.channels.get_mut(&id).map(|c| &mut c.handler)
+        else {
+            return Ok(());
+        };
+        buf.extend_from_slice(data);
+        let mut replies: Vec<&[u8]> = vec![];
+        let mut written = None;
+        loop {
+            match *size {
+                None => {
+                    let Some(end) = buf.iter().position(|&b| b == b'\n') else {
+                        break;
+                    };
+                    let line = String::from_utf8_lossy(&buf[..end]).to_string();
+                    buf.drain(..=end);
+                    // the `C0600 size name` of the file, the `T` of times is ignored
+                    if let Some(header) = line.strip_prefix('C') {
+                        let fields = header.splitn(3, ' ').collect::<Vec<_>>();
+                        *size = fields.get(1).and_then(|s| s.parse().ok());
+                        *name = fields.get(2).unwrap_or(&"").to_string();
+                    }
+                    replies.push(b"\0");
+                }
+                Some(n) if buf.len() >= n => {
+                    let file = if self.fs.is_dir(path) {
+                        format!("{path}/{name}")
+                    } else {
+                        path.clone()
+                    };
+                    written = Some(self.fs.write(&file, &buf[..n]));
+                    buf.drain(..n);
+                    // the `\0` after the data, if any
+                    if buf.first() == Some(&0) {
+                        buf.remove(0);
+                    }
+                    *size = None;
+                    replies.push(b"\0");
+                }
+                Some(_) => break,
+            }
+        }
+        if written == Some(false) {
+            let error = format!("\x01scp: {path}: No such file or directory\n");
+            self.write(id, error.as_bytes())?;
+            return self.finish(id, 1);
+        }
+        for reply in replies {
+            self.write(id, reply)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, id: u32, data: &[u8]) -> Result<()> {
+        let Some(peer) = self.channels.get(&id).map(|c| c.peer) else {
+            return Ok(());
+        };
+        for chunk in data.chunks(CHUNK) {
+            self.send(Writer::new(MSG_CHANNEL_DATA).u32(peer).string(chunk))?;
+        }
+        Ok(())
+    }
+
+    /// send the exit status and close the channel.
+    fn finish(&mut self, id: u32, code: u32) -> Result<()> {
+        let Some(channel) = self.channels.get_mut(&id) else {
+            return Ok(());
+        };
+        if channel.closed {
+            return Ok(());
+        }
+        channel.closed = true;
+        let peer = channel.peer;
+        self.send(
+            Writer::new(MSG_CHANNEL_REQUEST)
+                .u32(peer)
+                .string("exit-status")
+                .bool(false)
+                .u32(code),
+        )?;
+        self.send(Writer::new(MSG_CHANNEL_EOF).u32(peer))?;
+        self.send(Writer::new(MSG_CHANNEL_CLOSE).u32(peer))
+    }
+}
+
+/// verify the `ssh-ed25519` signature of the data.
+fn verify(blob: &[u8], signature: &[u8], data: &[u8]) -> bool {
+    let mut r = Reader(blob);
+    let key = r
+        .string()
+        .filter(|alg| *alg == b"ssh-ed25519")
+        .and_then(|_| r.string())
+        .and_then(|k| <[u8; 32]>::try_from(k).ok())
+        .and_then(|k| VerifyingKey::from_bytes(&k).ok());
+    let mut r = Reader(signature);
+    let sig = r
+        .string()
+        .filter(|alg| *alg == b"ssh-ed25519")
+        .and_then(|_| r.string())
+        .and_then(|s| <[u8; 64]>::try_from(s).ok())
+        .map(|s| Signature::from_bytes(&s));
+    match (key, sig) {
+        (Some(key), Some(sig)) => key.verify(data, &sig).is_ok(),
+        _ => false,
+    }
+}
@@ -0,0 +1,157 @@
+//! the SOCKS5 and HTTP CONNECT proxy, the tunnel is piped to the target.
+
+use parking_lot::Mutex;
+use std::io::{BufRead, BufReader, Read, Result, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, TcpListener, TcpStream};
+use std::sync::Arc;
+
+use super::transport::invalid;
+
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Socks5,
+    Http,
+}
+
+pub struct MockProxy {
+    pub port: u16,
+    /// the targets like `127.0.0.1:22` of the tunnels.
+    pub targets: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockProxy {
+    /// the proxy which requires the credentials if `auth` is given.
+    pub fn start(kind: Kind, auth: Option<(&str, &str)>) -> MockProxy {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock proxy");
+        let proxy = MockProxy {
+            port: listener.local_addr().unwrap().port(),
+            targets: Arc::default(),
+        };
+        let targets = proxy.targets.clone();
+        let auth = auth.map(|(u, p)| (u.to_string(), p.to_string()));
+        std::thread::spawn(move || {
+            for stream in listener.incoming().map_while(std::result::Result::ok) {
+                let (targets, auth) = (targets.clone(), auth.clone());
+                std::thread::spawn(move || {
+                    let tunnel = match kind {
+                        Kind::Socks5 => socks5(stream, auth.as_ref()),
+                        Kind::Http => http(stream, auth.as_ref()),
+                    };
+                    if let Ok((client, server, target)) = tunnel {
+                        targets.lock().push(target);
+                        let _ = pipe(client, server);
+                    }
+                });
+            }
+        });
+        proxy
+    }
+}
+
+fn socks5(
+    mut client: TcpStream,
+    auth: Option<&(String, String)>,
+) -> Result<(TcpStream, TcpStream, String)> {
+    let mut head = [0u8; 2];
+    client.read_exact(&mut head)?;
+    let mut methods = vec![0u8; head[1] as usize];
+    client.read_exact(&mut methods)?;
+    if let Some((user, password)) = auth {
+        client.write_all(&[5, 2])?;
+        let mut version = [0u8; 2];
+        client.read_exact(&mut version)?;
+        let mut u = vec![0u8; version[1] as usize];
+        client.read_exact(&mut u)?;
+        let mut n = [0u8; 1];
+        client.read_exact(&mut n)?;
+        let mut p = vec![0u8; n[0] as usize];
+        client.read_exact(&mut p)?;
+        if u != user.as_bytes() || p != password.as_bytes() {
+            client.write_all(&[1, 1])?;
+            return Err(invalid("bad socks5 credentials"));
+        }
+        client.write_all(&[1, 0])?;
+    } else {
+        client.write_all(&[5, 0])?;
+    }
+    let mut request = [0u8; 4];
+    client.read_exact(&mut request)?;
+    let host = match request[3] {
+        1 => {
+            let mut ip = [0u8; 4];
+            client.read_exact(&mut ip)?;
+            Ipv4Addr::from(ip).to_string()
+        }
+        4 => {
+            let mut ip = [0u8; 16];
+            client.read_exact(&mut ip)?;
+            format!("[{}]", Ipv6Addr::from(ip))
+        }
+        _ => {
+            let mut n = [0u8; 1];
+            client.read_exact(&mut n)?;
+            let mut name = vec![0u8; n[0] as usize];
+            client.read_exact(&mut name)?;
+            String::from_utf8_lossy(&name).to_string()
+        }
+    };
+    let mut port = [0u8; 2];
+    client.read_exact(&mut port)?;
+    let target = format!("{host}:{}", u16::from_be_bytes(port));
+    let server = match TcpStream::connect(&target) {
+        Ok(server) => server,
+        Err(e) => {
+            client.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0])?;
+            return Err(e);
+        }
+    };
+    client.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])?;
+    Ok((client, server, target))
+}
+
+fn http(
+    client: TcpStream,
+    auth: Option<&(String, String)>,
+) -> Result<(TcpStream, TcpStream, String)> {
+    let mut reader = BufReader::new(client.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let target = line
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| invalid("bad connect"))?
+        .to_string();
+    let mut authorized = auth.is_none();
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((user, password)) = auth {
+            use base64::prelude::{Engine, BASE64_STANDARD};
+            let token = BASE64_STANDARD.encode(format!("{user}:{password}"));
+            authorized |= header.trim() == format!("Proxy-Authorization: Basic {token}");
+        }
+    }
+    let mut client = client;
+    if !authorized {
+        client.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")?;
+        return Err(invalid("bad http credentials"));
+    }
+    let server = TcpStream::connect(&target)?;
+    client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+    Ok((client, server, target))
+}
+
+/// copy the data of both sides until one is closed.
+fn pipe(client: TcpStream, server: TcpStream) -> Result<()> {
+    let (mut from, mut to) = (server.try_clone()?, client.try_clone()?);
+    std::thread::spawn(move || {
+        let _ = std::io::copy(&mut from, &mut to);
+        let _ = to.shutdown(Shutdown::Both);
+    });
+    let (mut from, mut to) = (client, server);
+    std::io::copy(&mut from, &mut to)?;
+    to.shutdown(Shutdown::Both)
+}
@@ -0,0 +1,262 @@
+//! the SFTP v3 subsystem on the in-memory filesystem, see draft-ietf-secsh-filexfer-02.
+
+use std::collections::HashMap;
+
+use super::fs::{normalize, Fs};
+use super::wire::{Reader, Writer};
+
+const FXP_INIT: u8 = 1;
+const FXP_VERSION: u8 = 2;
+const FXP_OPEN: u8 = 3;
+const FXP_CLOSE: u8 = 4;
+const FXP_READ: u8 = 5;
+const FXP_WRITE: u8 = 6;
+const FXP_LSTAT: u8 = 7;
+const FXP_FSTAT: u8 = 8;
+const FXP_SETSTAT: u8 = 9;
+const FXP_FSETSTAT: u8 = 10;
+const FXP_OPENDIR: u8 = 11;
+const FXP_READDIR: u8 = 12;
+const FXP_REMOVE: u8 = 13;
+const FXP_MKDIR: u8 = 14;
+const FXP_REALPATH: u8 = 16;
+const FXP_STAT: u8 = 17;
+const FXP_STATUS: u8 = 101;
+const FXP_HANDLE: u8 = 102;
+const FXP_DATA: u8 = 103;
+const FXP_NAME: u8 = 104;
+const FXP_ATTRS: u8 = 105;
+
+const FX_OK: u32 = 0;
+const FX_EOF: u32 = 1;
+const FX_NO_SUCH_FILE: u32 = 2;
+const FX_FAILURE: u32 = 4;
+const FX_OP_UNSUPPORTED: u32 = 8;
+
+const FXF_WRITE: u32 = 0x02;
+const FXF_CREAT: u32 = 0x08;
+const FXF_TRUNC: u32 = 0x10;
+
+const ATTR_SIZE: u32 = 0x01;
+const ATTR_UIDGID: u32 = 0x02;
+const ATTR_PERMISSIONS: u32 = 0x04;
+const ATTR_ACMODTIME: u32 = 0x08;
+const ATTR_EXTENDED: u32 = 0x8000_0000;
+
+enum Handle {
+    File(String),
+    /// the entries not sent yet.
+    Dir(Vec<(String, bool)>),
+}
+
+pub struct Sftp {
+    fs: Fs,
+    buf: Vec<u8>,
+    handles: HashMap<u32, Handle>,
+    next: u32,
+}
+
+/// skip the attributes of the request.
+fn skip_attrs(r: &mut Reader) -> Option<()> {
+    let flags = r.u32()?;
+    if flags & ATTR_SIZE != 0 {
+        r.u64()?;
+    }
+    if flags & ATTR_UIDGID != 0 {
+        r.take(8)?;
+    }
+    if flags & ATTR_PERMISSIONS != 0 {
+        r.u32()?;
+    }
+    if flags & ATTR_ACMODTIME != 0 {
+        r.take(8)?;
+    }
+    if flags & ATTR_EXTENDED != 0 {
+        for _ in 0..r.u32()? {
+            r.string()?;
+            r.string()?;
+        }
+    }
+    Some(())
+}
+
+impl Sftp {
+    pub fn new(fs: Fs) -> Self {
+        Sftp {
+            fs,
+            buf: vec![],
+            handles: HashMap::new(),
+            next: 0,
+        }
+    }
+
+    fn attrs(&self, w: Writer, path: &str) -> Writer {
+        let size = self.fs.size(path).unwrap_or_default();
+        let mode = if self.fs.is_dir(path) {
+            0o40755
+        } else {
+            0o100644
+        };
+        w.u32(ATTR_SIZE | ATTR_PERMISSIONS).u64(size).u32(mode)
+    }
+
+    fn status(id: u32, code: u32) -> Vec<u8> {
+        Writer::new(FXP_STATUS)
+            .u32(id)
+            .u32(code)
+            .string("")
+            .string("")
+            .0
+    }
+
+    fn handle(&mut self, id: u32, handle: Handle) -> Vec<u8> {
+        self.next += 1;
+        self.handles.insert(self.next, handle);
+        Writer::new(FXP_HANDLE)
+            .u32(id)
+            .string(self.next.to_be_bytes())
+            .0
+    }
+
+    fn handle_of(r: &mut Reader) -> Option<u32> {
+        Some(u32::from_be_bytes(r.string()?.try_into().ok()?))
+    }
+
+    /// the responses of the data from client, every one is framed by its length.
+    pub fn feed(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.buf.extend_from_slice(data);
+        let mut responses = vec![];
+        while let Some(length) = Reader(&self.buf).u32() {
+            let length = length as usize;
+            if self.buf.len() < 4 + length {
+                break;
+            }
+            let request = self.buf[4..4 + length].to_vec();
+            self.buf.drain(..4 + length);
+            if let Some(response) = self.request(&request) {
+                responses.push(Writer::default().string(response).0);
+            }
+        }
+        responses
+    }
+
+    fn request(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        let mut r = Reader(request);
+        let kind = r.byte()?;
+        if kind == FXP_INIT {
+            return Some(Writer::new(FXP_VERSION).u32(3).0);
+        }
+        let id = r.u32()?;
+        let response = match kind {
+            FXP_OPEN => {
+                let path = normalize(&r.text()?);
+                let flags = r.u32()?;
+                skip_attrs(&mut r)?;
+                if flags & FXF_WRITE != 0 {
+                    let exists = self.fs.exists(&path);
+                    if !exists && flags & FXF_CREAT == 0 {
+                        return Some(Self::status(id, FX_NO_SUCH_FILE));
+                    }
+                    if (!exists || flags & FXF_TRUNC != 0) && !self.fs.write(&path, &[]) {
+                        return Some(Self::status(id, FX_NO_SUCH_FILE));
+                    }
+                } else if self.fs.read(&path).is_none() {
+                    return Some(Self::status(id, FX_NO_SUCH_FILE));
+                }
+                self.handle(id, Handle::File(path))
+            }
+            FXP_CLOSE => {
+                let handle = Self::handle_of(&mut r)?;
+                self.handles.remove(&handle);
+                Self::status(id, FX_OK)
+            }
+            FXP_READ => {
+                let handle = Self::handle_of(&mut r)?;
+                let (offset, len) = (r.u64()? as usize, r.u32()? as usize);
+                let Some(Handle::File(path)) = self.handles.get(&handle) else {
+                    return Some(Self::status(id, FX_FAILURE));
+                };
+                let data = self.fs.read(path).unwrap_or_default();
+                if offset >= data.len() {
+                    return Some(Self::status(id, FX_EOF));
+                }
+                let end = data.len().min(offset + len);
+                Writer::new(FXP_DATA).u32(id).string(&data[offset..end]).0
+            }
+            FXP_WRITE => {
+                let handle = Self::handle_of(&mut r)?;
+                let (offset, data) = (r.u64()?, r.string()?);
+                let Some(Handle::File(path)) = self.handles.get(&handle) else {
+                    return Some(Self::status(id, FX_FAILURE));
+                };
+                let ok = self.fs.write_at(path, offset, data, false);
+                Self::status(id, if ok { FX_OK } else { FX_FAILURE })
+            }
+            FXP_STAT | FXP_LSTAT => {
+                let path = normalize(&r.text()?);
+                if !self.fs.exists(&path) {
+                    return Some(Self::status(id, FX_NO_SUCH_FILE));
+                }
+                self.attrs(Writer::new(FXP_ATTRS).u32(id), &path).0
+            }
+            FXP_FSTAT => {
+                let handle = Self::handle_of(&mut r)?;
+                let Some(Handle::File(path)) = self.handles.get(&handle) else {
+                    return Some(Self::status(id, FX_FAILURE));
+                };
+                self.attrs(Writer::new(FXP_ATTRS).u32(id), path).0
+            }
+            FXP_SETSTAT | FXP_FSETSTAT => Self::status(id, FX_OK),
+            FXP_OPENDIR => {
+                let path = normalize(&r.text()?);
+                let Some(entries) = self.fs.list(&path) else {
+                    return Some(Self::status(id, FX_NO_SUCH_FILE));
+                };
+                let mut all = vec![(".".to_string(), true), ("..".to_string(), true)];
+                all.extend(
+                    entries
+                        .into_iter()
+                        .map(|(name, dir)| (format!("{path}/{name}"), dir)),
+                );
+                self.handle(id, Handle::Dir(all))
+            }
+            FXP_READDIR => {
+                let handle = Self::handle_of(&mut r)?;
+                let Some(Handle::Dir(entries)) = self.handles.get_mut(&handle) else {
+                    return Some(Self::status(id, FX_FAILURE));
+                };
+                if entries.is_empty() {
+                    return Some(Self::status(id, FX_EOF));
+                }
+                let entries = std::mem::take(entries);
+                let mut w = Writer::new(FXP_NAME).u32(id).u32(entries.len() as u32);
+                for (path, _) in entries.iter() {
+                    let name = path.rsplit('/').next().unwrap_or(path);
+                    w = w.string(name).string(name);
+                    w = self.attrs(w, path);
+                }
+                w.0
+            }
+            FXP_REMOVE => {
+                let path = r.text()?;
+                let ok = self.fs.read(&path).is_some() && self.fs.remove(&path);
+                Self::status(id, if ok { FX_OK } else { FX_NO_SUCH_FILE })
+            }
+            FXP_MKDIR => {
+                let ok = self.fs.mkdir(&r.text()?);
+                Self::status(id, if ok { FX_OK } else { FX_FAILURE })
+            }
+            FXP_REALPATH => {
+                let path = normalize(&r.text()?);
+                let w = Writer::new(FXP_NAME)
+                    .u32(id)
+                    .u32(1)
+                    .string(&path)
+                    .string(&path);
+                self.attrs(w, &path).0
+            }
+            _ => Self::status(id, FX_OP_UNSUPPORTED),
+        };
+        Some(response)
+    }
+}
@@ -0,0 +1,240 @@
+//! the transport layer of the server side, see RFC 4253.
+//! only `curve25519-sha256`, `ssh-ed25519`, `aes128-ctr` and `hmac-sha2-256` are offered.
+
+use aes::Aes128;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use ed25519_dalek::{Signer, SigningKey};
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::TcpStream;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use super::wire::{Reader, Writer};
+
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+
+pub const MSG_DISCONNECT: u8 = 1;
+pub const MSG_IGNORE: u8 = 2;
+pub const MSG_UNIMPLEMENTED: u8 = 3;
+pub const MSG_DEBUG: u8 = 4;
+const MSG_KEXINIT: u8 = 20;
+const MSG_NEWKEYS: u8 = 21;
+const MSG_KEX_ECDH_INIT: u8 = 30;
+const MSG_KEX_ECDH_REPLY: u8 = 31;
+
+const VERSION: &str = "SSH-2.0-AtshMock_1.0";
+const KEX: &str = "curve25519-sha256,curve25519-sha256@libssh.org";
+const HOST_KEY: &str = "ssh-ed25519";
+const CIPHER: &str = "aes128-ctr";
+const MAC: &str = "hmac-sha2-256";
+
+pub fn invalid(msg: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.into())
+}
+
+/// the cipher and mac of one direction.
+struct Keys {
+    cipher: Aes128Ctr,
+    mac: Vec<u8>,
+}
+
+pub struct Transport {
+    stream: TcpStream,
+    seq_in: u32,
+    seq_out: u32,
+    keys_in: Option<Keys>,
+    keys_out: Option<Keys>,
+    pub session_id: Vec<u8>,
+}
+
+impl Transport {
+    /// exchange the version and keys with the client.
+    pub fn accept(stream: TcpStream, host_key: &SigningKey) -> Result<Transport> {
+        let mut t = Transport {
+            stream,
+            seq_in: 0,
+            seq_out: 0,
+            keys_in: None,
+            keys_out: None,
+            session_id: vec![],
+        };
+        t.stream.write_all(format!("{VERSION}\r\n").as_bytes())?;
+        let client_version = t.read_version()?;
+
+        let mut cookie = [0u8; 16];
+        OsRng.fill_bytes(&mut cookie);
+        let server_kexinit = Writer::new(MSG_KEXINIT)
+            .raw(&cookie)
+            .string(KEX)
+            .string(HOST_KEY)
+            .string(CIPHER)
+            .string(CIPHER)
+            .string(MAC)
+            .string(MAC)
+            .string("none")
+            .string("none")
+            .string("")
+            .string("")
+            .bool(false)
+            .u32(0)
+            .0;
+        t.write_packet(&server_kexinit)?;
+        let client_kexinit = t.read_kind(MSG_KEXINIT)?;
+        let mut r = Reader(&client_kexinit[17..]);
+        let kex = r.text().ok_or_else(|| invalid("bad kexinit"))?;
+        if !kex.split(',').any(|k| KEX.split(',').any(|s| s == k)) {
+            return Err(invalid(format!("no common kex in `{kex}`")));
+        }
+
+        let init = t.read_kind(MSG_KEX_ECDH_INIT)?;
+        let client_public = Reader(&init[1..])
+            .string()
+            .and_then(|q| <[u8; 32]>::try_from(q).ok())
+            .ok_or_else(|| invalid("bad ecdh init"))?;
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let server_public = PublicKey::from(&secret);
+        let shared = secret.diffie_hellman(&PublicKey::from(client_public));
+        let k = Writer::default().mpint(shared.as_bytes()).0;
+
+        let host_blob = Writer::default()
+            .string(HOST_KEY)
+            .string(host_key.verifying_key().as_bytes())
+            .0;
+        let h: Vec<u8> = Sha256::new()
+            .chain_update(Writer::default().string(client_version.trim_end()).0)
+            .chain_update(Writer::default().string(VERSION).0)
+            .chain_update(Writer::default().string(&client_kexinit).0)
+            .chain_update(Writer::default().string(&server_kexinit).0)
+            .chain_update(Writer::default().string(&host_blob).0)
+            .chain_update(Writer::default().string(client_public).0)
+            .chain_update(Writer::default().string(server_public.as_bytes()).0)
+            .chain_update(&k)
+            .finalize()
+            .to_vec();
+        let signature = Writer::default()
+            .string(HOST_KEY)
+            .string(host_key.sign(&h).to_bytes())
+            .0;
+        t.write_packet(
+            &Writer::new(MSG_KEX_ECDH_REPLY)
+                .string(&host_blob)
+                .string(server_public.as_bytes())
+                .string(&signature)
+                .0,
+        )?;
+        t.session_id = h.clone();
+
+        let derive = |letter: u8| -> Vec<u8> {
+            Sha256::new()
+                .chain_update(&k)
+                .chain_update(&h)
+                .chain_update([letter])
+                .chain_update(&h)
+                .finalize()
+                .to_vec()
+        };
+        let keys = |iv: u8, key: u8, mac: u8| Keys {
+            cipher: Aes128Ctr::new(derive(key)[..16].into(), derive(iv)[..16].into()),
+            mac: derive(mac),
+        };
+        t.write_packet(&[MSG_NEWKEYS])?;
+        t.keys_out = Some(keys(b'B', b'D', b'F'));
+        t.read_kind(MSG_NEWKEYS)?;
+        t.keys_in = Some(keys(b'A', b'C', b'E'));
+        Ok(t)
+    }
+
+    /// the version line of the client, the lines before it are ignored.
+    fn read_version(&mut self) -> Result<String> {
+        loop {
+            let mut line = vec![];
+            let mut byte = [0u8; 1];
+            while byte[0] != b'\n' {
+                self.stream.read_exact(&mut byte)?;
+                line.push(byte[0]);
+            }
+            let line = String::from_utf8_lossy(&line).to_string();
+            if line.starts_with("SSH-") {
+                return Ok(line);
+            }
+        }
+    }
+
+    fn read_kind(&mut self, kind: u8) -> Result<Vec<u8>> {
+        loop {
+            let payload = self.read_packet()?;
+            match payload.first() {
+                Some(&k) if k == kind => return Ok(payload),
+                Some(&MSG_IGNORE | &MSG_DEBUG) => continue,
+                _ => return Err(invalid(format!("expect message {kind}, got {payload:?}"))),
+            }
+        }
+    }
+
+    /// the payload of the next packet.
+    pub fn read_packet(&mut self) -> Result<Vec<u8>> {
+        let mut packet = vec![0u8; 16];
+        let block = if self.keys_in.is_some() { 16 } else { 4 };
+        packet.truncate(block);
+        self.stream.read_exact(&mut packet)?;
+        if let Some(keys) = self.keys_in.as_mut() {
+            keys.cipher.apply_keystream(&mut packet);
+        }
+        let length = u32::from_be_bytes(packet[..4].try_into().unwrap()) as usize;
+        if !(5..=256 * 1024).contains(&length) {
+            return Err(invalid(format!("bad packet length {length}")));
+        }
+        let mut rest = vec![0u8; length + 4 - block];
+        self.stream.read_exact(&mut rest)?;
+        if let Some(keys) = self.keys_in.as_mut() {
+            keys.cipher.apply_keystream(&mut rest);
+            packet.extend_from_slice(&rest);
+            let mut tag = [0u8; 32];
+            self.stream.read_exact(&mut tag)?;
+            Hmac::<Sha256>::new_from_slice(&keys.mac)
+                .expect("any key size")
+                .chain_update(self.seq_in.to_be_bytes())
+                .chain_update(&packet)
+                .verify_slice(&tag)
+                .map_err(|_| invalid("bad mac"))?;
+        } else {
+            packet.extend_from_slice(&rest);
+        }
+        self.seq_in = self.seq_in.wrapping_add(1);
+        let padding = packet[4] as usize;
+        if padding + 1 > length {
+            return Err(invalid("bad padding"));
+        }
+        Ok(packet[5..4 + length - padding].to_vec())
+    }
+
+    pub fn write_packet(&mut self, payload: &[u8]) -> Result<()> {
+        let block = if self.keys_out.is_some() { 16 } else { 8 };
+        let mut padding = block - (payload.len() + 5) % block;
+        if padding < 4 {
+            padding += block;
+        }
+        let mut packet = Writer::default()
+            .u32((payload.len() + padding + 1) as u32)
+            .byte(padding as u8)
+            .raw(payload)
+            .0;
+        let mut random = vec![0u8; padding];
+        OsRng.fill_bytes(&mut random);
+        packet.extend_from_slice(&random);
+        if let Some(keys) = self.keys_out.as_mut() {
+            let tag = Hmac::<Sha256>::new_from_slice(&keys.mac)
+                .expect("any key size")
+                .chain_update(self.seq_out.to_be_bytes())
+                .chain_update(&packet)
+                .finalize()
+                .into_bytes();
+            keys.cipher.apply_keystream(&mut packet);
+            packet.extend_from_slice(&tag);
+        }
+        self.seq_out = self.seq_out.wrapping_add(1);
+        self.stream.write_all(&packet)
+    }
+}
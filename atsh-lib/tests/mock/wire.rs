@@ -0,0 +1,94 @@
+//! the data types of the ssh protocol, see RFC 4251 section 5.
+
+/// the encoder of the ssh message.
+#[derive(Default)]
+pub struct Writer(pub Vec<u8>);
+
+impl Writer {
+    pub fn new(kind: u8) -> Self {
+        Writer(vec![kind])
+    }
+
+    pub fn byte(mut self, v: u8) -> Self {
+        self.0.push(v);
+        self
+    }
+
+    pub fn bool(self, v: bool) -> Self {
+        self.byte(v as u8)
+    }
+
+    pub fn u32(mut self, v: u32) -> Self {
+        self.0.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+
+    pub fn u64(mut self, v: u64) -> Self {
+        self.0.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+
+    pub fn string(self, v: impl AsRef<[u8]>) -> Self {
+        let v = v.as_ref();
+        let mut w = self.u32(v.len() as u32);
+        w.0.extend_from_slice(v);
+        w
+    }
+
+    /// the unsigned big-endian integer.
+    pub fn mpint(self, v: &[u8]) -> Self {
+        let v = &v[v.iter().take_while(|&&b| b == 0).count()..];
+        if v.first().is_some_and(|&b| b & 0x80 != 0) {
+            let mut padded = vec![0];
+            padded.extend_from_slice(v);
+            return self.string(padded);
+        }
+        self.string(v)
+    }
+
+    pub fn raw(mut self, v: &[u8]) -> Self {
+        self.0.extend_from_slice(v);
+        self
+    }
+}
+
+/// the decoder of the ssh message, `None` if the data is too short.
+pub struct Reader<'a>(pub &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub fn byte(&mut self) -> Option<u8> {
+        let (v, rest) = self.0.split_first()?;
+        self.0 = rest;
+        Some(*v)
+    }
+
+    pub fn bool(&mut self) -> Option<bool> {
+        self.byte().map(|v| v != 0)
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    pub fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (v, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(v)
+    }
+
+    pub fn string(&mut self) -> Option<&'a [u8]> {
+        let n = self.u32()? as usize;
+        self.take(n)
+    }
+
+    pub fn text(&mut self) -> Option<String> {
+        Some(String::from_utf8_lossy(self.string()?).to_string())
+    }
+}